@@ -0,0 +1,197 @@
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+
+use rand::seq::{IteratorRandom, SliceRandom};
+
+/// Most addresses kept per network group, so peers gossiping addresses they control can't
+/// crowd everybody else out of the book.
+pub const MAX_ADDRESSES_PER_GROUP: usize = 16;
+/// Most network groups kept, so addresses in ever more groups can't tilt the pick of outbound
+/// peers towards whoever gossips them.
+pub const MAX_GROUPS: usize = 256;
+/// Most addresses kept in all.
+pub const MAX_ADDRESSES: usize = 1024;
+
+/// Addresses sharing a network group are likely run by the same operator, who can get hold of
+/// many of them cheaply.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum NetworkGroup {
+    /// The /16 of a public IPv4 address.
+    Ipv4([u8; 2]),
+    /// The /32 of a public IPv6 address.
+    Ipv6([u16; 2]),
+    /// Loopback and private addresses can't be obtained in bulk from outside, so each one is a
+    /// group of its own.
+    Local(SocketAddr),
+}
+
+impl NetworkGroup {
+    pub fn of(addr: SocketAddr) -> Self {
+        let ip = match addr.ip() {
+            IpAddr::V6(ip) => ip.to_ipv4_mapped().map_or(IpAddr::V6(ip), IpAddr::V4),
+            ip => ip,
+        };
+
+        match ip {
+            IpAddr::V4(ip)
+                if ip.is_loopback()
+                    || ip.is_private()
+                    || ip.is_link_local()
+                    || ip.is_unspecified() =>
+            {
+                Self::Local(addr)
+            }
+            IpAddr::V4(ip) => {
+                let [a, b, ..] = ip.octets();
+                Self::Ipv4([a, b])
+            }
+            IpAddr::V6(ip)
+                if ip.is_loopback()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local()
+                    || ip.is_unspecified() =>
+            {
+                Self::Local(addr)
+            }
+            IpAddr::V6(ip) => {
+                let [a, b, ..] = ip.segments();
+                Self::Ipv6([a, b])
+            }
+        }
+    }
+}
+
+/// Addresses of peers we heard about, bucketed by [`NetworkGroup`] so outbound connections can
+/// be spread over as many groups as possible. An attacker has to control addresses in many
+/// groups to eclipse a node, i.e. to become all of its peers.
+#[derive(Default)]
+pub struct AddressBook {
+    buckets: HashMap<NetworkGroup, Vec<SocketAddr>>,
+}
+
+impl AddressBook {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Remembers `addr` unless its group, or the book, is already full. Returns whether it was
+    /// added.
+    pub fn add(&mut self, addr: SocketAddr) -> bool {
+        let group = NetworkGroup::of(addr);
+        if self.len() >= MAX_ADDRESSES
+            || (!self.buckets.contains_key(&group) && self.buckets.len() >= MAX_GROUPS)
+        {
+            return false;
+        }
+
+        let bucket = self.buckets.entry(group).or_default();
+        if bucket.contains(&addr) || bucket.len() >= MAX_ADDRESSES_PER_GROUP {
+            return false;
+        }
+
+        bucket.push(addr);
+        true
+    }
+
+    /// Same, for an address gossiped by the peer at `source`. Local addresses are only taken
+    /// from peers that are local themselves: anybody else can't reach them, and could make up
+    /// any number of them.
+    pub fn add_gossiped(&mut self, addr: SocketAddr, source: SocketAddr) -> bool {
+        let local = |addr| matches!(NetworkGroup::of(addr), NetworkGroup::Local(_));
+        if local(addr) && !local(source) {
+            return false;
+        }
+        self.add(addr)
+    }
+
+    pub fn remove(&mut self, addr: SocketAddr) {
+        let group = NetworkGroup::of(addr);
+        if let Some(bucket) = self.buckets.get_mut(&group) {
+            bucket.retain(|known| *known != addr);
+            if bucket.is_empty() {
+                self.buckets.remove(&group);
+            }
+        }
+    }
+
+    /// Picks a random address from a random group outside of `used`, the groups we already
+    /// have outbound connections to.
+    pub fn select(&self, used: &HashSet<NetworkGroup>) -> Option<SocketAddr> {
+        let mut rng = rand::thread_rng();
+        let (_, bucket) = self
+            .buckets
+            .iter()
+            .filter(|(group, _)| !used.contains(group))
+            .choose(&mut rng)?;
+
+        bucket.choose(&mut rng).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.buckets.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn address_book_spreads_over_network_groups() {
+        let mut addresses = AddressBook::new();
+        for host in 0..=MAX_ADDRESSES_PER_GROUP {
+            let addr = SocketAddr::from(([203, 0, 113, host as u8], 9000));
+            assert_eq!(addresses.add(addr), host < MAX_ADDRESSES_PER_GROUP);
+        }
+        let other = SocketAddr::from(([198, 51, 100, 1], 9000));
+        assert!(addresses.add(other));
+        assert_eq!(addresses.len(), MAX_ADDRESSES_PER_GROUP + 1);
+
+        let used = HashSet::from([NetworkGroup::of(SocketAddr::from(([203, 0, 1, 1], 9000)))]);
+        assert_eq!(addresses.select(&used), Some(other));
+
+        let local = SocketAddr::from(([127, 0, 0, 1], 9000));
+        assert_eq!(NetworkGroup::of(local), NetworkGroup::Local(local));
+    }
+
+    #[test]
+    fn address_book_caps_groups_and_addresses() {
+        let mut addresses = AddressBook::new();
+        // Every private address is a group of its own, so they fill the groups first
+        for host in 0..MAX_GROUPS as u32 {
+            let ip = IpAddr::from(((10 << 24) + host).to_be_bytes());
+            assert!(addresses.add(SocketAddr::new(ip, 9000)));
+        }
+        assert!(!addresses.add(SocketAddr::from(([203, 0, 113, 1], 9000))));
+        assert_eq!(addresses.len(), MAX_GROUPS);
+
+        let mut addresses = AddressBook::new();
+        let group = |index: usize| [100 + (index / 256) as u8, (index % 256) as u8];
+        for index in 0..MAX_ADDRESSES / MAX_ADDRESSES_PER_GROUP {
+            let [a, b] = group(index);
+            for host in 0..MAX_ADDRESSES_PER_GROUP as u8 {
+                assert!(addresses.add(SocketAddr::from(([a, b, 0, host], 9000))));
+            }
+        }
+        assert_eq!(addresses.len(), MAX_ADDRESSES);
+        let [a, b] = group(MAX_ADDRESSES / MAX_ADDRESSES_PER_GROUP);
+        assert!(!addresses.add(SocketAddr::from(([a, b, 0, 0], 9000))));
+    }
+
+    #[test]
+    fn local_addresses_are_only_learned_from_local_peers() {
+        let mut addresses = AddressBook::new();
+        let (public, private) = (
+            SocketAddr::from(([203, 0, 113, 1], 9000)),
+            SocketAddr::from(([192, 168, 1, 1], 9000)),
+        );
+        assert!(!addresses.add_gossiped(private, public));
+        assert!(addresses.add_gossiped(private, SocketAddr::from(([10, 0, 0, 1], 9000))));
+        assert!(addresses.add_gossiped(public, public));
+        assert_eq!(addresses.len(), 2);
+    }
+}
@@ -0,0 +1,225 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use colored::Colorize;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::network::Network;
+use crate::ChainHandle;
+
+/// How often the [`AlertMonitor`] looks for trouble.
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+/// Free space left on the disk of the blocks below which it's nearly full.
+pub const MIN_FREE_DISK_BYTES: u64 = 1 << 30;
+/// How far the clock of the network may be off ours, see [`crate::Blockchain::time_offset`],
+/// before ours is considered skewed.
+pub const MAX_CLOCK_SKEW_SECS: i64 = 60;
+/// Blocks a reorg has to disconnect to count as a long fork.
+pub const LONG_FORK_BLOCKS: usize = 6;
+/// How long the alert about a long fork stays up.
+pub const LONG_FORK_ALERT: Duration = Duration::from_secs(60 * 60);
+/// How long the node may go without peers before it's alerted.
+pub const NO_PEERS_GRACE: Duration = Duration::from_secs(60);
+
+/// A condition an operator has to know about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    DiskNearlyFull,
+    ClockSkew,
+    LongFork,
+    NoPeers,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Alert {
+    pub kind: AlertKind,
+    pub message: String,
+    /// When the condition was first noticed, in Unix seconds.
+    pub raised_at: i64,
+    /// When the alert goes away by itself, for alerts about something that happened rather
+    /// than a condition that lasts.
+    #[serde(skip)]
+    expires: Option<Instant>,
+}
+
+/// The alerts currently up on a chain, at most one of each kind. Raising or clearing one is
+/// logged in a way that stands out.
+#[derive(Default)]
+pub struct Alerts {
+    active: Mutex<Vec<Alert>>,
+}
+
+/// Checks the node for the conditions of [`AlertKind`] it can notice by looking, raising and
+/// clearing the alerts of the network. Long forks are raised by the network as they happen.
+pub struct AlertMonitor {
+    alerts: Arc<Alerts>,
+    blockchain: ChainHandle,
+    network: Arc<Network>,
+    /// Where the blocks are stored, to check the disk they are on.
+    storage_path: PathBuf,
+    no_peers_since: Option<Instant>,
+}
+
+impl Alerts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Raises an alert of `kind`, or updates the message of the one already up.
+    pub fn raise(&self, kind: AlertKind, message: String) {
+        self.raise_until(kind, message, None);
+    }
+
+    /// Same, taking the alert down again after `duration`.
+    pub fn raise_for(&self, kind: AlertKind, message: String, duration: Duration) {
+        self.raise_until(kind, message, Some(Instant::now() + duration));
+    }
+
+    fn raise_until(&self, kind: AlertKind, message: String, expires: Option<Instant>) {
+        let mut active = self.active.lock().unwrap();
+        if let Some(alert) = active.iter_mut().find(|alert| alert.kind == kind) {
+            alert.message = message;
+            alert.expires = expires;
+            return;
+        }
+
+        warn!("{}", format!("ALERT: {}", message).red().bold());
+        active.push(Alert {
+            kind,
+            message,
+            raised_at: Utc::now().timestamp(),
+            expires,
+        });
+    }
+
+    pub fn clear(&self, kind: AlertKind) {
+        let mut active = self.active.lock().unwrap();
+        if let Some(index) = active.iter().position(|alert| alert.kind == kind) {
+            let alert = active.remove(index);
+            info!("Resolved: {}", alert.message);
+        }
+    }
+
+    /// The alerts up, oldest first.
+    pub fn active(&self) -> Vec<Alert> {
+        let now = Instant::now();
+        let mut active = self.active.lock().unwrap();
+        active.retain(|alert| alert.expires.is_none_or(|expires| expires > now));
+        active.clone()
+    }
+}
+
+impl AlertMonitor {
+    pub fn new(blockchain: ChainHandle, network: Arc<Network>, storage_path: PathBuf) -> Self {
+        Self {
+            alerts: network.alerts(),
+            blockchain,
+            network,
+            storage_path,
+            no_peers_since: None,
+        }
+    }
+
+    /// Looks for every condition once.
+    pub fn check(&mut self) {
+        match fs4::available_space(&self.storage_path) {
+            Ok(free) if free < MIN_FREE_DISK_BYTES => self.alerts.raise(
+                AlertKind::DiskNearlyFull,
+                format!(
+                    "only {} MiB are left on the disk of {}",
+                    free >> 20,
+                    self.storage_path.display()
+                ),
+            ),
+            Ok(_) => self.alerts.clear(AlertKind::DiskNearlyFull),
+            Err(err) => warn!(
+                "Failed to check the free space on the disk of {}: {}",
+                self.storage_path.display(),
+                err
+            ),
+        }
+
+        let offset = self.blockchain.read().time_offset();
+        if offset.abs() > MAX_CLOCK_SKEW_SECS {
+            self.alerts.raise(
+                AlertKind::ClockSkew,
+                format!("our clock is {}s off the network's", offset),
+            );
+        } else {
+            self.alerts.clear(AlertKind::ClockSkew);
+        }
+
+        if self.network.peer_count() > 0 {
+            self.no_peers_since = None;
+            self.alerts.clear(AlertKind::NoPeers);
+        } else {
+            let since = *self.no_peers_since.get_or_insert_with(Instant::now);
+            if since.elapsed() >= NO_PEERS_GRACE {
+                self.alerts.raise(
+                    AlertKind::NoPeers,
+                    format!("no peers for {}s", since.elapsed().as_secs()),
+                );
+            }
+        }
+    }
+
+    /// Checks every [`CHECK_INTERVAL`], forever.
+    pub async fn run(mut self) {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            self.check();
+        }
+    }
+}
+
+/// The alerts framed so they can't be missed, e.g. on startup.
+pub fn banner(alerts: &[Alert]) -> String {
+    let lines: Vec<String> = alerts
+        .iter()
+        .map(|alert| format!("! {}", alert.message))
+        .collect();
+    let width = lines
+        .iter()
+        .map(|line| line.chars().count())
+        .max()
+        .unwrap_or(0);
+    let rule = "!".repeat(width + 4);
+
+    let mut banner = format!("{}\n", rule);
+    for line in lines {
+        banner += &format!("{:<width$}   !\n", line, width = width);
+    }
+    banner += &rule;
+    banner.red().bold().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alerts_stay_up_until_cleared_or_expired() {
+        let alerts = Alerts::new();
+        alerts.raise(AlertKind::NoPeers, String::from("no peers for 60s"));
+        alerts.raise(AlertKind::NoPeers, String::from("no peers for 70s"));
+        alerts.raise_for(
+            AlertKind::LongFork,
+            String::from("a reorg rolled back 6 block(s)"),
+            Duration::ZERO,
+        );
+
+        let active = alerts.active();
+        assert_eq!(active.len(), 1);
+        assert_eq!(active[0].kind, AlertKind::NoPeers);
+        assert_eq!(active[0].message, "no peers for 70s");
+        assert!(banner(&active).contains("no peers for 70s"));
+
+        alerts.clear(AlertKind::NoPeers);
+        assert!(alerts.active().is_empty());
+    }
+}
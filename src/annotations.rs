@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+/// A note an operator attached to a block or a transaction, e.g. a label or the write-up of an
+/// incident. Annotations stay on the node; they are neither relayed nor part of any hash, and
+/// never affect validation.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Annotation {
+    pub label: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    /// When the annotation was added, in Unix seconds.
+    pub created_at: i64,
+}
+
+/// Annotations of blocks and transactions by block hash or transaction id, kept in a JSON
+/// sidecar file next to the blocks, if anywhere. The file is rewritten on every change.
+#[derive(Default)]
+pub struct Annotations {
+    path: Option<PathBuf>,
+    annotations: HashMap<String, Vec<Annotation>>,
+}
+
+impl Annotation {
+    pub fn new(label: String, note: Option<String>) -> Self {
+        Self {
+            label,
+            note,
+            created_at: Utc::now().timestamp(),
+        }
+    }
+}
+
+impl Annotations {
+    /// Annotations kept in memory only.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads the annotations stored in `path`, none if it doesn't exist yet, and saves every
+    /// change back to it.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let annotations = match fs::read(&path) {
+            Ok(annotations) => serde_json::from_slice(&annotations)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(err),
+        };
+
+        Ok(Self {
+            path: Some(path),
+            annotations,
+        })
+    }
+
+    /// Annotations of the block or transaction with that hash, oldest first.
+    pub fn get(&self, hash: &str) -> &[Annotation] {
+        self.annotations.get(hash).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn add(&mut self, hash: String, annotation: Annotation) -> io::Result<()> {
+        self.annotations.entry(hash).or_default().push(annotation);
+        self.save()
+    }
+
+    /// Drops every annotation of `hash`, returning how many there were.
+    pub fn remove(&mut self, hash: &str) -> io::Result<usize> {
+        let removed = self
+            .annotations
+            .remove(hash)
+            .map_or(0, |removed| removed.len());
+        if removed > 0 {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    /// Writes the annotations to a temporary file first, so a crash halfway through leaves the
+    /// previous ones in place.
+    fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let temporary = path.with_extension("json.tmp");
+        fs::write(&temporary, serde_json::to_vec(&self.annotations)?)?;
+        fs::rename(temporary, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    #[test]
+    fn annotations_persist_in_their_sidecar_file() {
+        let (annotated, other) = ("a".repeat(64), "b".repeat(64));
+        let dir = env::temp_dir().join(format!("blockchain-annotations-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("annotations.json");
+
+        let mut annotations = Annotations::open(&path).unwrap();
+        annotations
+            .add(
+                annotated.clone(),
+                Annotation::new("incident".into(), Some("stale for an hour".into())),
+            )
+            .unwrap();
+        annotations
+            .add(annotated.clone(), Annotation::new("resolved".into(), None))
+            .unwrap();
+
+        let reopened = Annotations::open(&path).unwrap();
+        let labels: Vec<&str> = reopened
+            .get(&annotated)
+            .iter()
+            .map(|annotation| annotation.label.as_str())
+            .collect();
+        assert_eq!(labels, ["incident", "resolved"]);
+        assert!(reopened.get(&other).is_empty());
+
+        assert_eq!(annotations.remove(&other).unwrap(), 0);
+        assert_eq!(annotations.remove(&annotated).unwrap(), 2);
+        assert!(Annotations::open(&path).unwrap().get(&annotated).is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
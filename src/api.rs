@@ -0,0 +1,1054 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+use axum::extract::ws::{self, WebSocket, WebSocketUpgrade};
+use axum::extract::{ConnectInfo, Path, Query, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::net::TcpListener;
+use tokio::time::{Duration, Instant};
+
+use crate::alerts::Alert;
+use crate::annotations::{Annotation, Annotations};
+use crate::changes::{ChangeKind, Cursor, CursorError};
+use crate::difficulty::Epoch;
+use crate::merkle::MerkleProof;
+use crate::network::{Message, Network};
+use crate::stats::{StatsHistory, StatsSample};
+use crate::subscriptions::AddressWatch;
+use crate::transaction::Transaction;
+use crate::view::{ChainView, ChainViews};
+use crate::work::{Work, WorkQueue};
+use crate::{Block, BlockHeader, BlockTemplate, BlockchainError, ChainHandle};
+
+/// How long a long-polling template request waits for a change before answering anyway.
+pub const LONGPOLL_TIMEOUT: Duration = Duration::from_secs(60);
+/// How often a long-polling template request looks for a change.
+const LONGPOLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How often address subscriptions look for new events.
+const SUBSCRIPTION_INTERVAL: Duration = Duration::from_millis(500);
+/// Requests per second a client of a [`public_router`] may make on average, by IP.
+pub const PUBLIC_REQUESTS_PER_SEC: f64 = 5.0;
+/// Requests a client of a [`public_router`] may make at once before being held to the rate.
+pub const PUBLIC_BURST: f64 = 20.0;
+/// Largest response a [`public_router`] sends.
+pub const PUBLIC_MAX_RESPONSE_BYTES: usize = 1 << 20;
+/// Clients whose request budget is tracked before the least recently seen one is forgotten.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+/// Most changes of the chain a single `GET /changes` returns.
+pub const MAX_CHANGES_PER_PAGE: usize = 100;
+/// Most headers a single `GET /headers/{from}` returns, well within
+/// [`PUBLIC_MAX_RESPONSE_BYTES`].
+pub const MAX_HEADERS_PER_PAGE: usize = 2_000;
+
+/// What the handlers share: the chain to submit to and views of it to query, the network to
+/// relay accepted transactions to, the recent stats of the node and the annotations of its
+/// operator.
+#[derive(Clone)]
+struct ApiState {
+    blockchain: ChainHandle,
+    views: ChainViews,
+    network: Arc<Network>,
+    stats: Arc<StatsHistory>,
+    work: Arc<Mutex<WorkQueue>>,
+    annotations: Arc<Mutex<Annotations>>,
+    /// Largest response to send, if any, see [`ApiState::json`].
+    response_cap: Option<usize>,
+}
+
+/// What each client of a [`public_router`] has left to spend, a token bucket by IP refilled at
+/// [`PUBLIC_REQUESTS_PER_SEC`] up to [`PUBLIC_BURST`]. Only the [`MAX_TRACKED_CLIENTS`] seen
+/// most recently are tracked.
+#[derive(Default)]
+struct RateLimiter {
+    budgets: Mutex<Budgets>,
+}
+
+#[derive(Default)]
+struct Budgets {
+    by_client: HashMap<IpAddr, Budget>,
+    /// The tracked clients by [`Budget::seen`], least recently seen first.
+    by_recency: BTreeMap<u64, IpAddr>,
+    next_seen: u64,
+}
+
+struct Budget {
+    tokens: f64,
+    /// When `tokens` were counted.
+    at: Instant,
+    /// Sequence number of the last request of the client.
+    seen: u64,
+}
+
+/// Body of a response being serialized, failing as soon as it grows past `cap` bytes.
+struct CappedBody {
+    bytes: Vec<u8>,
+    cap: usize,
+}
+
+/// An error response, sent as `{"error": "..."}`.
+struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiState {
+    fn new(
+        blockchain: ChainHandle,
+        network: Arc<Network>,
+        stats: Arc<StatsHistory>,
+        annotations: Annotations,
+    ) -> Self {
+        Self {
+            views: blockchain.views(),
+            blockchain,
+            network,
+            stats,
+            work: Arc::new(Mutex::new(WorkQueue::new())),
+            annotations: Arc::new(Mutex::new(annotations)),
+            response_cap: None,
+        }
+    }
+
+    /// `value` as a JSON response. It's serialized only up to the response cap, failing once
+    /// past it.
+    fn json(&self, value: &impl Serialize) -> Result<Response, ApiError> {
+        let mut body = CappedBody {
+            bytes: Vec::new(),
+            cap: self.response_cap.unwrap_or(usize::MAX),
+        };
+        match serde_json::to_writer(&mut body, value) {
+            Ok(()) => {
+                Ok(([(header::CONTENT_TYPE, "application/json")], body.bytes).into_response())
+            }
+            Err(err) if err.is_io() => Err(ApiError::bad_request(format!(
+                "the response would be larger than {} bytes, ask for less",
+                body.cap
+            ))),
+            Err(err) => Err(BlockchainError::Encoding(err.to_string()).into()),
+        }
+    }
+}
+
+impl Write for CappedBody {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.bytes.len() + buf.len() > self.cap {
+            return Err(io::Error::other("response cap exceeded"));
+        }
+        self.bytes.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl ApiError {
+    fn not_found(message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::NOT_FOUND,
+            message: message.into(),
+        }
+    }
+
+    fn bad_request(message: impl Into<String>) -> Self {
+        Self {
+            status: StatusCode::BAD_REQUEST,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<BlockchainError> for ApiError {
+    fn from(err: BlockchainError) -> Self {
+        let status = match err {
+            BlockchainError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            BlockchainError::PolicyRejected { .. } => StatusCode::FORBIDDEN,
+            _ => StatusCode::BAD_REQUEST,
+        };
+
+        Self {
+            status,
+            message: err.to_string(),
+        }
+    }
+}
+
+impl RateLimiter {
+    /// Spends a request of `ip`, unless it has none left.
+    fn allow(&self, ip: IpAddr) -> bool {
+        let now = Instant::now();
+        let mut budgets = self.budgets.lock().unwrap();
+        let Budgets {
+            by_client,
+            by_recency,
+            next_seen,
+        } = &mut *budgets;
+        let seen = *next_seen;
+        *next_seen += 1;
+
+        let budget = match by_client.get_mut(&ip) {
+            Some(budget) => {
+                by_recency.remove(&budget.seen);
+                budget
+            }
+            None => {
+                if by_client.len() >= MAX_TRACKED_CLIENTS {
+                    // The client idle the longest most likely refilled its budget already
+                    if let Some((_, idle)) = by_recency.pop_first() {
+                        by_client.remove(&idle);
+                    }
+                }
+                by_client.entry(ip).or_insert(Budget {
+                    tokens: PUBLIC_BURST,
+                    at: now,
+                    seen,
+                })
+            }
+        };
+        by_recency.insert(seen, ip);
+        budget.seen = seen;
+
+        let refill = now.duration_since(budget.at).as_secs_f64() * PUBLIC_REQUESTS_PER_SEC;
+        let tokens = (budget.tokens + refill).min(PUBLIC_BURST);
+        if tokens < 1.0 {
+            return false;
+        }
+        (budget.tokens, budget.at) = (tokens - 1.0, now);
+        true
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(json!({ "error": self.message }))).into_response()
+    }
+}
+
+/// Routes of the HTTP API:
+///
+/// - `GET /tip?verbosity=...`: the last block of the active chain. At verbosity `0` it's the
+///   hex of its bincode encoding, at `1`, the default, its header with its size, weight, miner
+///   tag, annotations and transaction ids, and at `2` the same with the transactions in full
+///   and their annotations
+/// - `GET /blocks/{id_or_hash}?verbosity=...`: same for a block of the active chain by id or
+///   hash
+/// - `GET /blocks/{id_or_hash}/proofs/{transaction}`: Merkle proof that the transaction with
+///   that id is included in the block
+/// - `POST /blocks`: connects a block mined elsewhere, e.g. from a template, and relays it
+/// - `GET /template?address=...&longpoll=...`: the block to mine next, paying `address`. With
+///   the `longpoll_id` of a previous answer as `longpoll`, it only answers once the tip or the
+///   transactions to mine changed, or after [`LONGPOLL_TIMEOUT`]
+/// - `GET /work?address=...`: a range of nonces of the block to mine next, paying `address`,
+///   for a remote worker, see [`WorkQueue`]
+/// - `POST /work`: `{"work_id": ..., "nonce": ...}`, a nonce solving a range handed out before;
+///   the block is connected and relayed
+/// - `GET /headers/{from}?limit=...`: up to `limit` headers of the active chain from id `from`
+///   on, at most [`MAX_HEADERS_PER_PAGE`], for relaying to a [`crate::bridge::HeaderRelay`]. The
+///   next page starts after the last header returned
+/// - `GET /changes?cursor=...&limit=...`: the blocks connected and disconnected since the
+///   cursor, in order, connected ones along with the block if it's still on the active chain,
+///   and the cursor to ask from next, see [`crate::changes::ChangeLog`]. Without a cursor,
+///   only the current cursor is returned: take it, mirror the chain, then follow the changes
+///   from it. A cursor that expired, e.g. because the node restarted, answers 410
+/// - `POST /transactions`: queues a signed transaction for mining and relays it to our peers,
+///   answering with its id and virtual size
+/// - `GET /balances/{address}`: confirmed balance of the address, how much of it isn't
+///   already spent by pending transactions and the nonce to sign its next transaction with
+/// - `GET /transactions/{hash}/confirmations`: how many blocks confirm the transaction, see
+///   [`crate::Blockchain::confirmations`], and whether it's pending with what notes the mempool
+///   policies attached to it
+/// - `POST /packages`: same for a list of dependent transactions, accepted all together or not
+///   at all
+/// - `GET /epochs`: the retarget intervals of the active chain, see [`Epoch`]
+/// - `GET /difficulty/{height}`: difficulty of the block at that height, see
+///   [`crate::Blockchain::difficulty_at`]
+/// - `GET /annotations/{hash}`: annotations of the block or transaction with that hash, see
+///   [`Annotation`]
+/// - `POST /annotations/{hash}`: `{"label": ..., "note": ...}`, annotates the block or
+///   transaction, the note being optional
+/// - `DELETE /annotations/{hash}`: drops every annotation of the block or transaction
+/// - `GET /validate`: re-validates the whole chain
+/// - `GET /stats`: the recent [`StatsHistory`] of the node, oldest sample first
+/// - `GET /alerts`: the alerts up on the chain, oldest first, see [`crate::alerts::AlertKind`]
+/// - `GET /ws`: WebSocket taking `subscribe_address <address>` and `unsubscribe_address
+///   <address>` text commands and pushing an [`crate::subscriptions::AddressEvent`] for every
+///   mempool sighting or confirmation update of a transaction involving a subscribed address
+pub fn router(
+    blockchain: ChainHandle,
+    network: Arc<Network>,
+    stats: Arc<StatsHistory>,
+    annotations: Annotations,
+) -> Router {
+    Router::new()
+        .route("/tip", get(tip))
+        .route("/blocks/{id_or_hash}", get(block))
+        .route("/blocks/{id_or_hash}/proofs/{transaction}", get(proof))
+        .route("/blocks", post(submit_block))
+        .route("/template", get(template))
+        .route("/work", get(get_work).post(submit_work))
+        .route("/headers/{from}", get(headers))
+        .route("/changes", get(changes))
+        .route("/transactions", post(submit_transaction))
+        .route("/balances/{address}", get(balance))
+        .route("/transactions/{hash}/confirmations", get(confirmations))
+        .route("/packages", post(submit_package))
+        .route("/epochs", get(epochs))
+        .route("/difficulty/{height}", get(difficulty_at))
+        .route(
+            "/annotations/{hash}",
+            get(list_annotations)
+                .post(annotate)
+                .delete(remove_annotations),
+        )
+        .route("/validate", get(validate))
+        .route("/stats", get(stats_history))
+        .route("/alerts", get(alerts))
+        .route("/ws", get(subscribe))
+        .with_state(ApiState::new(blockchain, network, stats, annotations))
+}
+
+/// The routes of the [`router`] that are safe to expose to anybody, e.g. for an explorer: those
+/// reading the chain, but none changing anything, mining, or revealing how the node is doing.
+/// Every client is rate limited by IP, see [`PUBLIC_REQUESTS_PER_SEC`], and responses are
+/// capped at [`PUBLIC_MAX_RESPONSE_BYTES`], larger ones answering 400 instead.
+pub fn public_router(
+    blockchain: ChainHandle,
+    network: Arc<Network>,
+    stats: Arc<StatsHistory>,
+    annotations: Annotations,
+) -> Router {
+    Router::new()
+        .route("/tip", get(tip))
+        .route("/blocks/{id_or_hash}", get(block))
+        .route("/blocks/{id_or_hash}/proofs/{transaction}", get(proof))
+        .route("/headers/{from}", get(headers))
+        .route("/changes", get(changes))
+        .route("/balances/{address}", get(balance))
+        .route("/transactions/{hash}/confirmations", get(confirmations))
+        .route("/epochs", get(epochs))
+        .route("/difficulty/{height}", get(difficulty_at))
+        .route("/annotations/{hash}", get(list_annotations))
+        .with_state(ApiState {
+            response_cap: Some(PUBLIC_MAX_RESPONSE_BYTES),
+            ..ApiState::new(blockchain, network, stats, annotations)
+        })
+        .layer(middleware::from_fn_with_state(
+            Arc::new(RateLimiter::default()),
+            limit_public,
+        ))
+}
+
+/// Turns away clients of a [`public_router`] out of requests.
+async fn limit_public(
+    State(limiter): State<Arc<RateLimiter>>,
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !limiter.allow(client.ip()) {
+        return ApiError {
+            status: StatusCode::TOO_MANY_REQUESTS,
+            message: format!("more than {} requests per second", PUBLIC_REQUESTS_PER_SEC),
+        }
+        .into_response();
+    }
+
+    next.run(request).await
+}
+
+/// Serves the [`router`] on `addr`, or only the [`public_router`] if `public`, until the
+/// listener fails.
+pub async fn serve(
+    addr: SocketAddr,
+    blockchain: ChainHandle,
+    network: Arc<Network>,
+    stats: Arc<StatsHistory>,
+    annotations: Annotations,
+    public: bool,
+) -> io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    let app = if public {
+        info!("Serving the public HTTP API on {}", addr);
+        public_router(blockchain, network, stats, annotations)
+    } else {
+        info!("Serving the HTTP API on {}", addr);
+        router(blockchain, network, stats, annotations)
+    };
+
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+}
+
+/// Query of the block routes.
+#[derive(Deserialize)]
+struct BlockQuery {
+    #[serde(default = "BlockQuery::default_verbosity")]
+    verbosity: u8,
+}
+
+impl BlockQuery {
+    fn default_verbosity() -> u8 {
+        1
+    }
+}
+
+/// A block as served by the API at verbosity 1: its header, measurements and annotations, and
+/// only the ids of its transactions.
+#[derive(Serialize)]
+struct BlockSummary {
+    #[serde(flatten)]
+    header: BlockHeader,
+    size: usize,
+    weight: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    miner_tag: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    annotations: Vec<Annotation>,
+    transactions: Vec<String>,
+}
+
+impl BlockSummary {
+    fn new(block: &Block, annotations: &Annotations) -> Self {
+        Self {
+            header: block.header(),
+            size: block.size(),
+            weight: block.weight(),
+            miner_tag: block.miner_tag().map(String::from),
+            annotations: annotations.get(&block.hash).to_vec(),
+            transactions: block.transactions.iter().map(Transaction::hash).collect(),
+        }
+    }
+}
+
+/// A block as served by the API at verbosity 2, along with its measurements and annotations.
+#[derive(Serialize)]
+struct BlockResponse {
+    #[serde(flatten)]
+    block: Block,
+    size: usize,
+    weight: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    miner_tag: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    annotations: Vec<Annotation>,
+    /// Annotations of the transactions of the block that have any, by transaction id.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    transaction_annotations: HashMap<String, Vec<Annotation>>,
+}
+
+impl BlockResponse {
+    fn new(block: &Block, annotations: &Annotations) -> Self {
+        let transaction_annotations = block
+            .transactions
+            .iter()
+            .map(|transaction| transaction.hash())
+            .filter_map(|hash| {
+                let annotations = annotations.get(&hash);
+                (!annotations.is_empty()).then(|| (hash, annotations.to_vec()))
+            })
+            .collect();
+
+        Self {
+            size: block.size(),
+            weight: block.weight(),
+            miner_tag: block.miner_tag().map(String::from),
+            annotations: annotations.get(&block.hash).to_vec(),
+            transaction_annotations,
+            block: block.clone(),
+        }
+    }
+}
+
+/// `block` at the `verbosity` of [`BlockQuery`].
+fn block_response(state: &ApiState, block: &Block, verbosity: u8) -> Result<Response, ApiError> {
+    let annotations = state.annotations.lock().unwrap();
+    match verbosity {
+        0 => {
+            let bytes = bincode::serialize(block)
+                .map_err(|err| BlockchainError::Encoding(err.to_string()))?;
+            state.json(&hex::encode(bytes))
+        }
+        1 => state.json(&BlockSummary::new(block, &annotations)),
+        2 => state.json(&BlockResponse::new(block, &annotations)),
+        _ => Err(ApiError::bad_request(format!(
+            "verbosity {} isn't 0, 1 or 2",
+            verbosity
+        ))),
+    }
+}
+
+async fn tip(
+    State(state): State<ApiState>,
+    Query(query): Query<BlockQuery>,
+) -> Result<Response, ApiError> {
+    let view = state.views.latest();
+    let tip = view
+        .tip()
+        .ok_or_else(|| ApiError::not_found("the blockchain is empty"))?;
+
+    block_response(&state, tip, query.verbosity)
+}
+
+fn find_block<'a>(view: &'a ChainView, id_or_hash: &str) -> Result<&'a Block, ApiError> {
+    view.block(id_or_hash)
+        .ok_or_else(|| ApiError::not_found(format!("no block {} in the chain", id_or_hash)))
+}
+
+async fn block(
+    State(state): State<ApiState>,
+    Path(id_or_hash): Path<String>,
+    Query(query): Query<BlockQuery>,
+) -> Result<Response, ApiError> {
+    let view = state.views.latest();
+    let block = find_block(&view, &id_or_hash)?;
+
+    block_response(&state, block, query.verbosity)
+}
+
+async fn proof(
+    State(state): State<ApiState>,
+    Path((id_or_hash, transaction)): Path<(String, String)>,
+) -> Result<Response, ApiError> {
+    let view = state.views.latest();
+    let proof: MerkleProof = find_block(&view, &id_or_hash)?
+        .transaction_proof(&transaction)
+        .ok_or_else(|| {
+            ApiError::not_found(format!(
+                "no transaction {} in block {}",
+                transaction, id_or_hash
+            ))
+        })?;
+
+    state.json(&proof)
+}
+
+async fn submit_block(
+    State(state): State<ApiState>,
+    Json(block): Json<Block>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    let hash = block.hash.clone();
+    let announcement = Message::new_block(block.clone());
+
+    state.blockchain.try_add_block(block)?;
+    state.network.broadcast(&announcement, None);
+
+    Ok((StatusCode::ACCEPTED, Json(json!({ "hash": hash }))))
+}
+
+#[derive(Deserialize)]
+struct TemplateQuery {
+    address: String,
+    longpoll: Option<String>,
+}
+
+#[derive(Serialize)]
+struct TemplateResponse {
+    /// See [`BlockTemplate::fingerprint`].
+    longpoll_id: String,
+    template: BlockTemplate,
+}
+
+async fn template(
+    State(state): State<ApiState>,
+    Query(query): Query<TemplateQuery>,
+) -> Result<Json<TemplateResponse>, ApiError> {
+    let deadline = Instant::now() + LONGPOLL_TIMEOUT;
+
+    loop {
+        let template = state.blockchain.read().block_template(&query.address)?;
+        let longpoll_id = template.fingerprint();
+
+        if query.longpoll.as_ref() != Some(&longpoll_id) || Instant::now() >= deadline {
+            return Ok(Json(TemplateResponse {
+                longpoll_id,
+                template,
+            }));
+        }
+        tokio::time::sleep(LONGPOLL_INTERVAL).await;
+    }
+}
+
+#[derive(Deserialize)]
+struct WorkQuery {
+    address: String,
+}
+
+async fn get_work(
+    State(state): State<ApiState>,
+    Query(query): Query<WorkQuery>,
+) -> Result<Json<Work>, ApiError> {
+    let template = state.blockchain.read().block_template(&query.address)?;
+
+    Ok(Json(state.work.lock().unwrap().get_work(template)))
+}
+
+#[derive(Deserialize)]
+struct WorkSolution {
+    work_id: u64,
+    nonce: u64,
+}
+
+async fn submit_work(
+    State(state): State<ApiState>,
+    Json(solution): Json<WorkSolution>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    let block = state
+        .work
+        .lock()
+        .unwrap()
+        .solution(solution.work_id, solution.nonce)?;
+    let hash = block.hash.clone();
+    let announcement = Message::new_block(block.clone());
+
+    state.blockchain.try_add_block(block)?;
+    state.network.broadcast(&announcement, None);
+
+    Ok((StatusCode::ACCEPTED, Json(json!({ "hash": hash }))))
+}
+
+/// Query of `GET /headers/{from}`.
+#[derive(Deserialize)]
+struct HeadersQuery {
+    limit: Option<usize>,
+}
+
+async fn headers(
+    State(state): State<ApiState>,
+    Path(from): Path<u64>,
+    Query(query): Query<HeadersQuery>,
+) -> Result<Response, ApiError> {
+    let limit = query
+        .limit
+        .unwrap_or(MAX_HEADERS_PER_PAGE)
+        .min(MAX_HEADERS_PER_PAGE);
+    let view = state.views.latest();
+    let headers: Vec<BlockHeader> = view
+        .blocks_from(from)
+        .take(limit)
+        .map(Block::header)
+        .collect();
+
+    state.json(&headers)
+}
+
+/// Query of `GET /changes`.
+#[derive(Deserialize)]
+struct ChangesQuery {
+    cursor: Option<String>,
+    limit: Option<usize>,
+}
+
+async fn changes(
+    State(state): State<ApiState>,
+    Query(query): Query<ChangesQuery>,
+) -> Result<Response, ApiError> {
+    let Some(cursor) = query.cursor else {
+        return state.json(&json!({
+            "changes": [],
+            "cursor": state.views.cursor().to_string(),
+            "more": false,
+        }));
+    };
+    let cursor: Cursor = cursor.parse().map_err(ApiError::bad_request)?;
+    let limit = query
+        .limit
+        .unwrap_or(MAX_CHANGES_PER_PAGE)
+        .min(MAX_CHANGES_PER_PAGE);
+
+    let (changes, next) = state
+        .views
+        .changes_since(cursor, limit)
+        .map_err(|err| ApiError {
+            status: match err {
+                CursorError::Expired => StatusCode::GONE,
+                CursorError::Unknown => StatusCode::BAD_REQUEST,
+            },
+            message: err.to_string(),
+        })?;
+    let more = state.views.cursor() != next;
+
+    let view = state.views.latest();
+    let changes: Vec<Value> = changes
+        .into_iter()
+        .map(|change| {
+            let block = (change.kind == ChangeKind::Connected)
+                .then(|| view.block(&change.hash))
+                .flatten()
+                .filter(|block| block.hash == change.hash);
+            let mut change = json!(change);
+            if let Some(block) = block {
+                change["block"] = json!(block);
+            }
+            change
+        })
+        .collect();
+
+    state.json(&json!({
+        "changes": changes,
+        "cursor": next.to_string(),
+        "more": more,
+    }))
+}
+
+async fn submit_transaction(
+    State(state): State<ApiState>,
+    Json(transaction): Json<Transaction>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    let hash = transaction.hash();
+    let vsize = transaction.vsize();
+
+    state.blockchain.submit_transaction(transaction.clone())?;
+    state.network.announce_local(vec![transaction]);
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(json!({ "hash": hash, "vsize": vsize })),
+    ))
+}
+
+async fn balance(
+    State(state): State<ApiState>,
+    Path(address): Path<String>,
+) -> Result<Response, ApiError> {
+    let balance = {
+        let blockchain = state.blockchain.read();
+        json!({
+            "address": address,
+            "balance": blockchain.balance(&address),
+            "available": blockchain.available(&address),
+            "next_nonce": blockchain.next_nonce(&address),
+        })
+    };
+
+    state.json(&balance)
+}
+
+async fn confirmations(
+    State(state): State<ApiState>,
+    Path(hash): Path<String>,
+) -> Result<Response, ApiError> {
+    let (pending, notes) = {
+        let blockchain = state.blockchain.read();
+        let mempool = blockchain.mempool();
+        (mempool.contains(&hash), mempool.notes(&hash).to_vec())
+    };
+
+    state.json(&json!({
+        "hash": hash,
+        "confirmations": state.views.latest().confirmations(&hash),
+        "pending": pending,
+        "notes": notes,
+    }))
+}
+
+async fn submit_package(
+    State(state): State<ApiState>,
+    Json(transactions): Json<Vec<Transaction>>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    let hashes: Vec<String> = transactions.iter().map(Transaction::hash).collect();
+
+    state.blockchain.submit_package(transactions.clone())?;
+    state.network.announce_local(transactions);
+
+    Ok((StatusCode::ACCEPTED, Json(json!({ "hashes": hashes }))))
+}
+
+async fn epochs(State(state): State<ApiState>) -> Result<Response, ApiError> {
+    let epochs: Vec<Epoch> = state.blockchain.read().epochs().to_vec();
+
+    state.json(&epochs)
+}
+
+async fn difficulty_at(
+    State(state): State<ApiState>,
+    Path(height): Path<u64>,
+) -> Result<Response, ApiError> {
+    let difficulty = state
+        .blockchain
+        .read()
+        .difficulty_at(height)
+        .ok_or_else(|| {
+            ApiError::not_found(format!("no difficulty is known at height {}", height))
+        })?;
+
+    state.json(&json!({ "height": height, "difficulty": difficulty }))
+}
+
+async fn validate(State(state): State<ApiState>) -> Json<Value> {
+    match state.views.latest().validate() {
+        Ok(()) => Json(json!({ "valid": true })),
+        Err(err) => Json(json!({ "valid": false, "error": err.to_string() })),
+    }
+}
+
+async fn subscribe(State(state): State<ApiState>, upgrade: WebSocketUpgrade) -> Response {
+    upgrade.on_upgrade(move |socket| watch_addresses(socket, state))
+}
+
+/// Serves the address subscriptions of one WebSocket client until it goes away.
+async fn watch_addresses(mut socket: WebSocket, state: ApiState) {
+    let mut watch = AddressWatch::new(&state.views.latest());
+    let mut interval = tokio::time::interval(SUBSCRIPTION_INTERVAL);
+
+    loop {
+        let replies = tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(ws::Message::Text(command))) => {
+                    vec![subscription_command(&mut watch, &command)]
+                }
+                Some(Ok(ws::Message::Close(_)) | Err(_)) | None => return,
+                Some(Ok(_)) => continue,
+            },
+            _ = interval.tick() => {
+                let view = state.views.latest();
+                let events = watch.poll(&view, state.blockchain.read().mempool());
+                events.iter().map(|event| json!(event)).collect()
+            }
+        };
+
+        for reply in replies {
+            let text = ws::Utf8Bytes::from(reply.to_string());
+            if socket.send(ws::Message::Text(text)).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn subscription_command(watch: &mut AddressWatch, command: &str) -> Value {
+    match command.split_whitespace().collect::<Vec<_>>()[..] {
+        ["subscribe_address", address] => {
+            watch.subscribe(address.to_string());
+            json!({ "type": "subscribed", "address": address })
+        }
+        ["unsubscribe_address", address] => {
+            watch.unsubscribe(address);
+            json!({ "type": "unsubscribed", "address": address })
+        }
+        _ => json!({ "error": format!("unknown command {:?}", command) }),
+    }
+}
+
+/// Body of `POST /annotations/{hash}`.
+#[derive(Deserialize)]
+struct AnnotationRequest {
+    label: String,
+    note: Option<String>,
+}
+
+async fn list_annotations(
+    State(state): State<ApiState>,
+    Path(hash): Path<String>,
+) -> Result<Response, ApiError> {
+    let annotations = state.annotations.lock().unwrap().get(&hash).to_vec();
+
+    state.json(&annotations)
+}
+
+async fn annotate(
+    State(state): State<ApiState>,
+    Path(hash): Path<String>,
+    Json(request): Json<AnnotationRequest>,
+) -> Result<(StatusCode, Json<Annotation>), ApiError> {
+    let annotation = Annotation::new(request.label, request.note);
+    state
+        .annotations
+        .lock()
+        .unwrap()
+        .add(hash, annotation.clone())
+        .map_err(BlockchainError::Io)?;
+
+    Ok((StatusCode::CREATED, Json(annotation)))
+}
+
+async fn remove_annotations(
+    State(state): State<ApiState>,
+    Path(hash): Path<String>,
+) -> Result<Json<Value>, ApiError> {
+    let removed = state
+        .annotations
+        .lock()
+        .unwrap()
+        .remove(&hash)
+        .map_err(BlockchainError::Io)?;
+
+    Ok(Json(json!({ "hash": hash, "removed": removed })))
+}
+
+async fn stats_history(State(state): State<ApiState>) -> Json<Vec<StatsSample>> {
+    Json(state.stats.samples())
+}
+
+async fn alerts(State(state): State<ApiState>) -> Json<Vec<Alert>> {
+    Json(state.network.alerts().active())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors;
+    use crate::Blockchain;
+
+    #[test]
+    fn clients_are_limited_to_their_own_budget() {
+        let limiter = RateLimiter::default();
+        let (client, other) = (
+            IpAddr::from([203, 0, 113, 1]),
+            IpAddr::from([203, 0, 113, 2]),
+        );
+
+        let allowed = (0..2 * PUBLIC_BURST as usize)
+            .filter(|_| limiter.allow(client))
+            .count();
+        assert_eq!(allowed, PUBLIC_BURST as usize);
+        assert!(!limiter.allow(client));
+        assert!(limiter.allow(other));
+
+        // Past the tracked clients, the least recently seen one is forgotten
+        for index in 0..MAX_TRACKED_CLIENTS as u32 - 2 {
+            assert!(limiter.allow(IpAddr::from(((1 << 24) + index).to_be_bytes())));
+        }
+        assert!(!limiter.allow(client));
+        assert!(limiter.allow(IpAddr::from([198, 51, 100, 1])));
+        let budgets = limiter.budgets.lock().unwrap();
+        assert_eq!(budgets.by_client.len(), MAX_TRACKED_CLIENTS);
+        assert!(!budgets.by_client.contains_key(&other));
+        assert!(budgets.by_client.contains_key(&client));
+    }
+
+    /// The state of an API over an empty chain, whose responses are capped at `response_cap`.
+    fn state(response_cap: usize) -> ApiState {
+        let blockchain = ChainHandle::new(Blockchain::new());
+        let network = Network::new(
+            String::from("test"),
+            SocketAddr::from(([127, 0, 0, 1], 0)),
+            0,
+            blockchain.clone(),
+            None,
+            None,
+            false,
+        );
+        ApiState {
+            response_cap: Some(response_cap),
+            ..ApiState::new(
+                blockchain,
+                network,
+                Arc::new(StatsHistory::new()),
+                Annotations::new(),
+            )
+        }
+    }
+
+    #[test]
+    fn responses_are_cut_off_at_the_cap() {
+        let state = state(16);
+
+        assert!(state.json(&"fits").is_ok());
+        let err = state.json(&vec!["too"; 1 << 20]).unwrap_err();
+        assert_eq!(err.status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn headers_are_paged_to_fit_the_cap() {
+        let state = state(PUBLIC_MAX_RESPONSE_BYTES);
+        // Headers as large as they get, more of them than a response holds
+        let genesis = vectors::blocks().remove(0);
+        let blocks: Vec<Block> = (0..4 * MAX_HEADERS_PER_PAGE as u64)
+            .map(|id| Block {
+                id,
+                hash: format!("{:064x}", id),
+                previous_hash: "f".repeat(64),
+                timestamp: i64::MIN,
+                difficulty: u64::MAX,
+                merkle_root: "f".repeat(64),
+                nonce: u64::MAX,
+                ..genesis.clone()
+            })
+            .collect();
+        state.views.sync(&blocks, 0);
+        let all: Vec<_> = blocks.iter().map(Block::header).collect();
+        assert!(state.json(&all).is_err());
+
+        let page = |from: u64, limit: Option<usize>| {
+            let state = state.clone();
+            async move {
+                let response = headers(State(state), Path(from), Query(HeadersQuery { limit }))
+                    .await
+                    .unwrap_or_else(|err| panic!("{}", err.message));
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                serde_json::from_slice::<Vec<BlockHeader>>(&body).unwrap()
+            }
+        };
+        let first = page(0, None).await;
+        assert_eq!(first.len(), MAX_HEADERS_PER_PAGE);
+        assert_eq!(first[0].hash, blocks[0].hash);
+        let next = page(MAX_HEADERS_PER_PAGE as u64, Some(usize::MAX)).await;
+        assert_eq!(next.len(), MAX_HEADERS_PER_PAGE);
+        assert_eq!(next[0].hash, blocks[MAX_HEADERS_PER_PAGE].hash);
+        assert_eq!(page(5, Some(3)).await.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn the_public_api_only_serves_reads_within_the_rate_limit() {
+        let blockchain = ChainHandle::new(Blockchain::new());
+        let network = Network::new(
+            String::from("test"),
+            SocketAddr::from(([127, 0, 0, 1], 0)),
+            0,
+            blockchain.clone(),
+            None,
+            None,
+            false,
+        );
+        let app = public_router(
+            blockchain,
+            network,
+            Arc::new(StatsHistory::new()),
+            Annotations::new(),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await
+        });
+
+        let client = reqwest::Client::new();
+        let url = |path: &str| format!("http://{}{}", addr, path);
+        // The chain is empty, so there's no tip, but the request is served and counted
+        let tip = client.get(url("/tip")).send().await.unwrap();
+        assert_eq!(tip.status(), StatusCode::NOT_FOUND);
+
+        let submitted = client.post(url("/blocks")).send().await.unwrap();
+        assert_eq!(submitted.status(), StatusCode::NOT_FOUND);
+
+        let mut statuses = Vec::new();
+        for _ in 0..PUBLIC_BURST as usize {
+            statuses.push(client.get(url("/epochs")).send().await.unwrap().status());
+        }
+        // Two requests of the budget were spent above already
+        assert_eq!(
+            statuses.iter().filter(|status| status.is_success()).count(),
+            PUBLIC_BURST as usize - 2
+        );
+        assert_eq!(statuses.last(), Some(&StatusCode::TOO_MANY_REQUESTS));
+    }
+}
@@ -0,0 +1,218 @@
+use serde::{Deserialize, Serialize};
+
+use crate::hashing;
+use crate::merkle::{self, MerkleProof};
+use crate::transaction::{self, Transaction};
+
+/// A block of the chain, in the encoding it is stored and sent to peers in.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Block {
+    /// Height of the block, the genesis block being `0`.
+    pub id: u64,
+    /// Hex-encoded hash of the block, see [`Block::hash`].
+    pub hash: String,
+    /// Hash of the predecessor, or `"genesis"` for the genesis block.
+    pub previous_hash: String,
+    /// When the block was mined, in Unix seconds.
+    pub timestamp: i64,
+    /// Proof-of-work difficulty the block was mined at, see [`crate::difficulty::meets`].
+    pub difficulty: u64,
+    /// Merkle root of the transactions, see [`transaction::merkle_root`].
+    pub merkle_root: String,
+    /// Transactions of the block; all but the genesis block start with the coinbase.
+    pub transactions: Vec<Transaction>,
+    /// The nonce that solved the proof of work.
+    pub nonce: u64,
+}
+
+/// A block without its transactions: what a light client such as
+/// [`crate::bridge::HeaderRelay`] needs to follow a chain and check inclusion proofs.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BlockHeader {
+    pub id: u64,
+    pub hash: String,
+    pub previous_hash: String,
+    pub timestamp: i64,
+    pub difficulty: u64,
+    pub merkle_root: String,
+    pub nonce: u64,
+}
+
+/// Everything needed to mine the next block except the nonce, as handed out by
+/// [`crate::Blockchain::block_template`] and solved by [`crate::miner::Miner::mine`].
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BlockTemplate {
+    pub id: u64,
+    pub previous_hash: String,
+    pub difficulty: u64,
+    /// Network-adjusted time the template was made at, see [`crate::Blockchain::adjusted_time`].
+    /// Miners move it forward by the time they spend on the block.
+    pub timestamp: i64,
+    pub transactions: Vec<Transaction>,
+}
+
+impl Block {
+    /// The block hash over the header fields. Numbers are encoded as fixed-width big-endian
+    /// integers and strings are prefixed with their length, so no two headers encode alike.
+    pub fn hash(
+        id: u64,
+        previous_hash: &str,
+        timestamp: i64,
+        difficulty: u64,
+        merkle_root: &str,
+        nonce: u64,
+    ) -> String {
+        let mut header = Vec::with_capacity(48 + previous_hash.len() + merkle_root.len());
+        header.extend_from_slice(&id.to_be_bytes());
+        hashing::encode_str(&mut header, previous_hash);
+        header.extend_from_slice(&timestamp.to_be_bytes());
+        header.extend_from_slice(&difficulty.to_be_bytes());
+        hashing::encode_str(&mut header, merkle_root);
+        header.extend_from_slice(&nonce.to_be_bytes());
+
+        hashing::tagged_hash(hashing::BLOCK_TAG, header)
+    }
+
+    /// Recomputes the hash of the block from its header.
+    pub fn calculate_hash(&self) -> String {
+        Self::hash(
+            self.id,
+            &self.previous_hash,
+            self.timestamp,
+            self.difficulty,
+            &self.merkle_root,
+            self.nonce,
+        )
+    }
+
+    /// Tag of the miner of the block, carried by its first coinbase transaction, see
+    /// [`Transaction::miner_tag`].
+    pub fn miner_tag(&self) -> Option<&str> {
+        self.transactions.first().and_then(Transaction::miner_tag)
+    }
+
+    /// The header of the block.
+    pub fn header(&self) -> BlockHeader {
+        BlockHeader {
+            id: self.id,
+            hash: self.hash.clone(),
+            previous_hash: self.previous_hash.clone(),
+            timestamp: self.timestamp,
+            difficulty: self.difficulty,
+            merkle_root: self.merkle_root.clone(),
+            nonce: self.nonce,
+        }
+    }
+
+    /// Size of the block in bytes, its header and transactions measured like
+    /// [`Transaction::size`].
+    pub fn size(&self) -> usize {
+        self.header_size()
+            + self
+                .transactions
+                .iter()
+                .map(Transaction::size)
+                .sum::<usize>()
+    }
+
+    /// Weight of the block, its header counted like the non-signature bytes of a transaction,
+    /// see [`Transaction::weight`].
+    pub fn weight(&self) -> usize {
+        transaction::WITNESS_SCALE_FACTOR * self.header_size()
+            + self
+                .transactions
+                .iter()
+                .map(Transaction::weight)
+                .sum::<usize>()
+    }
+
+    fn header_size(&self) -> usize {
+        self.hash.len() + self.previous_hash.len() + self.merkle_root.len() + 4 * 8
+    }
+
+    /// Recomputes the Merkle root of the transactions.
+    pub fn calculate_merkle_root(&self) -> String {
+        transaction::merkle_root(&self.transactions)
+    }
+
+    /// Proof that the transaction with id `hash` is included in the block. It verifies with
+    /// [`merkle::verify_proof`] against [`Block::merkle_root`] and the witness digest of the
+    /// transaction.
+    pub fn transaction_proof(&self, hash: &str) -> Option<MerkleProof> {
+        let index = self
+            .transactions
+            .iter()
+            .position(|transaction| transaction.hash() == hash)?;
+        let leaves: Vec<[u8; 32]> = self
+            .transactions
+            .iter()
+            .map(Transaction::witness_digest)
+            .collect();
+
+        merkle::generate_proof(&leaves, index)
+    }
+}
+
+impl BlockTemplate {
+    /// Changes whenever the template changes materially, i.e. the tip or the non-coinbase
+    /// transactions differ; a fresh coinbase alone doesn't count.
+    pub fn fingerprint(&self) -> String {
+        let transactions = self.transactions.get(1..).unwrap_or_default();
+        format!(
+            "{}:{}",
+            self.previous_hash,
+            transaction::merkle_root(transactions)
+        )
+    }
+}
+
+impl BlockHeader {
+    /// Recomputes the hash of the block from its header, see [`Block::hash`].
+    pub fn calculate_hash(&self) -> String {
+        Block::hash(
+            self.id,
+            &self.previous_hash,
+            self.timestamp,
+            self.difficulty,
+            &self.merkle_root,
+            self.nonce,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transaction::MAX_MINER_TAG_LEN;
+    use crate::vectors;
+    use crate::{Blockchain, BlockchainError};
+
+    #[test]
+    fn block_weight_counts_the_header_in_full() {
+        for block in vectors::blocks() {
+            assert!(block.weight() > 3 * block.size());
+        }
+    }
+
+    #[test]
+    fn miner_tags_are_committed_to_by_the_block() {
+        let blocks = vectors::blocks();
+        let mut block = blocks[1].clone();
+        assert_eq!(block.miner_tag(), None);
+
+        block.transactions[0].signature = String::from("my-pool");
+        assert_eq!(block.miner_tag(), Some("my-pool"));
+        assert_ne!(block.calculate_merkle_root(), block.merkle_root);
+
+        let mut blockchain = Blockchain::new();
+        assert!(matches!(
+            blockchain.set_miner_tag(Some("x".repeat(MAX_MINER_TAG_LEN + 1))),
+            Err(BlockchainError::MinerTagTooLong { .. })
+        ));
+        blockchain
+            .set_miner_tag(Some(String::from("my-pool")))
+            .unwrap();
+        blockchain.try_add_block(blocks[0].clone()).unwrap();
+        let template = blockchain.block_template("miner").unwrap();
+        assert_eq!(template.transactions[0].miner_tag(), Some("my-pool"));
+    }
+}
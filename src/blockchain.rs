@@ -0,0 +1,1315 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::Utc;
+use colored::Colorize;
+use log::{debug, info, warn};
+
+use crate::block::{Block, BlockTemplate};
+use crate::checkpoint::{Checkpoint, CHECKPOINT_INTERVAL};
+use crate::decisions::{self, Decision, DecisionLog, Input};
+use crate::difficulty::{self, Epoch};
+use crate::error::BlockchainError;
+use crate::ledger::Ledger;
+use crate::mempool::Mempool;
+use crate::policy::{MempoolPolicy, Verdict};
+use crate::reward::RewardSplit;
+use crate::storage::Storage;
+use crate::transaction::{Transaction, MAX_MINER_TAG_LEN};
+use crate::verifier::SignatureVerifier;
+use crate::view::ChainViews;
+
+/// Most transactions a block may hold, coinbase included.
+pub const MAX_BLOCK_TRANSACTIONS: usize = 100;
+/// Amount the coinbase transactions of every block pay out together, usually all of it to the
+/// miner, see [`RewardSplit`].
+pub const BLOCK_REWARD: u64 = 50;
+/// How far ahead of our clock, see [`Blockchain::adjusted_time`], a block may be timestamped.
+pub const MAX_FUTURE_BLOCK_TIME_SECS: i64 = 2 * 60;
+/// Most transactions a package submitted with [`Blockchain::submit_package`] may hold.
+pub const MAX_PACKAGE_TRANSACTIONS: usize = 25;
+
+/// The active chain together with the ledger it leads to and the transactions waiting to be
+/// mined on top of it. Every block is validated before it becomes part of the chain.
+#[derive(Default)]
+pub struct Blockchain {
+    blocks: Vec<Block>,
+    /// Balances and nonces the active chain leads to.
+    ledger: Ledger,
+    /// Id of the block of the active chain each confirmed transaction is in, by transaction id.
+    transaction_index: HashMap<String, u64>,
+    mempool: Mempool,
+    storage: Option<Storage>,
+    /// Where checkpoints of the stored chain are saved, see [`Blockchain::load`].
+    checkpoint_path: Option<PathBuf>,
+    /// Height of the last checkpoint the active chain still starts with, or lower.
+    checkpoint_height: u64,
+    /// Seconds to add to our clock to agree with the network, see
+    /// [`Blockchain::set_time_offset`].
+    time_offset: i64,
+    views: ChainViews,
+    /// Retarget intervals of the active chain, oldest first.
+    epochs: Vec<Epoch>,
+    /// How the reward of the blocks we mine is paid out.
+    reward_split: RewardSplit,
+    /// What the blocks we mine identify their miner as, see [`Transaction::miner_tag`].
+    miner_tag: Option<String>,
+    /// Where the inputs the chain decides on are recorded, if anywhere.
+    decisions: Option<DecisionLog>,
+    /// Checked in order on every transaction about to be queued.
+    policies: Vec<Box<dyn MempoolPolicy>>,
+}
+
+/// What [`Blockchain::replace_chain`] decided to do with a valid candidate chain.
+pub enum ReplaceChainOutcome {
+    /// The candidate had more work and is now the active chain. Blocks from `fork_id` on were
+    /// swapped: `disconnected` of ours for `connected` of the candidate's.
+    Replaced {
+        fork_id: u64,
+        disconnected: usize,
+        connected: usize,
+    },
+    /// The candidate doesn't have more work than the active chain.
+    NotHeavier,
+}
+
+impl Blockchain {
+    /// An empty chain kept in memory only.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An in-memory chain made of `blocks`, which are fully validated from genesis first.
+    pub fn from_blocks(blocks: Vec<Block>) -> Result<Self, BlockchainError> {
+        let mut blockchain = Self {
+            blocks,
+            ..Self::default()
+        };
+        blockchain.ledger = blockchain.replay(&blockchain.blocks)?;
+        for block in &blockchain.blocks {
+            Self::index_block(&mut blockchain.transaction_index, block);
+        }
+        blockchain.sync_history(0);
+        Ok(blockchain)
+    }
+
+    /// Opens the chain persisted at `path` and re-validates it before use. New blocks are
+    /// appended to the same file as they are added.
+    ///
+    /// Every [`CHECKPOINT_INTERVAL`] blocks, the state derived from them is saved to
+    /// `checkpoint.json` next to the file. If the stored chain still starts with the blocks of
+    /// that checkpoint, only the blocks after them are re-validated.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, BlockchainError> {
+        let checkpoint_path = path.as_ref().with_file_name("checkpoint.json");
+        let mut storage = Storage::open(path)?;
+        let blocks = storage.read_blocks()?;
+
+        let mut blockchain = Self {
+            blocks,
+            storage: Some(storage),
+            ..Self::default()
+        };
+        let checkpoint = match Checkpoint::read(&checkpoint_path) {
+            Ok(checkpoint) => checkpoint.filter(|checkpoint| blockchain.starts_with(checkpoint)),
+            Err(err) => {
+                warn!(
+                    "Ignoring the checkpoint at {}: {}",
+                    checkpoint_path.display(),
+                    err
+                );
+                None
+            }
+        };
+        let from = match checkpoint {
+            Some(checkpoint) => {
+                let from = checkpoint.height as usize;
+                info!(
+                    "Replaying {} block(s) on top of the checkpoint at height {}",
+                    blockchain.blocks.len() - from,
+                    from
+                );
+                blockchain.ledger =
+                    blockchain.replay_from(&blockchain.blocks, from, checkpoint.ledger)?;
+                blockchain.transaction_index = checkpoint.transaction_index;
+                blockchain.checkpoint_height = checkpoint.height;
+                from
+            }
+            None => {
+                blockchain.ledger = blockchain.replay(&blockchain.blocks)?;
+                0
+            }
+        };
+        for block in &blockchain.blocks[from..] {
+            Self::index_block(&mut blockchain.transaction_index, block);
+        }
+        blockchain.checkpoint_path = Some(checkpoint_path);
+        blockchain.sync_history(0);
+
+        info!("Loaded {} block(s) from storage", blockchain.blocks.len());
+        Ok(blockchain)
+    }
+
+    /// Where queries get a consistent view of the chain from without locking it. The views
+    /// follow every block connected and every reorg.
+    pub fn views(&self) -> ChainViews {
+        self.views.clone()
+    }
+
+    /// Brings the views and the epochs up to date after the blocks from `from` on changed.
+    fn sync_history(&mut self, from: usize) {
+        self.views.sync(&self.blocks, from);
+        difficulty::sync_epochs(&mut self.epochs, &self.blocks, from);
+    }
+
+    /// Retarget intervals of the active chain, oldest first, the last one possibly still
+    /// incomplete.
+    pub fn epochs(&self) -> &[Epoch] {
+        &self.epochs
+    }
+
+    /// Difficulty a block at `height` of the active chain was mined at, or, for the height
+    /// right above the tip, has to be mined at. `None` further up, where the difficulty
+    /// depends on blocks that don't exist yet.
+    pub fn difficulty_at(&self, height: u64) -> Option<u64> {
+        if height == self.height() {
+            return Some(self.next_difficulty());
+        }
+        self.epochs
+            .get((height / difficulty::RETARGET_INTERVAL) as usize)
+            .filter(|_| height < self.height())
+            .map(|epoch| epoch.difficulty)
+    }
+
+    /// Forces every stored block onto the disk.
+    pub fn flush(&mut self) -> Result<(), BlockchainError> {
+        if let Some(storage) = self.storage.as_mut() {
+            storage.flush()?;
+        }
+        Ok(())
+    }
+
+    fn persist(&mut self, block: &Block) -> Result<(), BlockchainError> {
+        if let Some(storage) = self.storage.as_mut() {
+            storage.append(block)?;
+        }
+        Ok(())
+    }
+
+    fn starts_with(&self, checkpoint: &Checkpoint) -> bool {
+        checkpoint.height > 0
+            && self
+                .blocks
+                .get(checkpoint.height as usize - 1)
+                .is_some_and(|block| block.hash == checkpoint.tip)
+    }
+
+    /// Saves a [`Checkpoint`] of a stored chain once it grew [`CHECKPOINT_INTERVAL`] blocks past
+    /// the last one. Failing to doesn't fail connecting blocks; the next start just replays
+    /// more of them.
+    fn update_checkpoint(&mut self) {
+        let (Some(path), Some(tip)) = (&self.checkpoint_path, self.blocks.last()) else {
+            return;
+        };
+        if self.height() < self.checkpoint_height + CHECKPOINT_INTERVAL {
+            return;
+        }
+
+        let checkpoint = Checkpoint {
+            height: self.height(),
+            tip: tip.hash.clone(),
+            ledger: self.ledger.clone(),
+            transaction_index: self.transaction_index.clone(),
+        };
+        match checkpoint.write(path) {
+            Ok(()) => {
+                debug!("Saved a checkpoint at height {}", checkpoint.height);
+                self.checkpoint_height = checkpoint.height;
+            }
+            Err(err) => warn!("Failed to save a checkpoint to {}: {}", path.display(), err),
+        }
+    }
+
+    fn index_block(index: &mut HashMap<String, u64>, block: &Block) {
+        for transaction in &block.transactions {
+            index.insert(transaction.hash(), block.id);
+        }
+    }
+
+    /// How many blocks of the active chain confirm the transaction `hash`: one once it's in
+    /// the tip, one more for every block on top. Zero if it's pending, unknown or was in a
+    /// block a reorg disconnected, so the count can go down. This is what anything reporting
+    /// confirmations should go by.
+    pub fn confirmations(&self, hash: &str) -> u64 {
+        self.transaction_index
+            .get(hash)
+            .map_or(0, |id| self.height() - id)
+    }
+
+    /// The genesis block to mine when starting an empty chain.
+    pub fn genesis_template() -> BlockTemplate {
+        BlockTemplate {
+            id: 0,
+            previous_hash: String::from("genesis"),
+            difficulty: difficulty::INITIAL_DIFFICULTY,
+            timestamp: Utc::now().timestamp(),
+            transactions: Vec::new(),
+        }
+    }
+
+    /// Blocks of the active chain, from genesis to the tip.
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+
+    /// The last block of the active chain.
+    pub fn tip(&self) -> Option<&Block> {
+        self.blocks.last()
+    }
+
+    /// Number of blocks in the active chain.
+    pub fn height(&self) -> u64 {
+        self.blocks.len() as u64
+    }
+
+    /// Cumulative work of the active chain, see [`difficulty::work`].
+    pub fn work(&self) -> u64 {
+        difficulty::work(&self.blocks)
+    }
+
+    /// Transactions waiting to be mined.
+    pub fn mempool(&self) -> &Mempool {
+        &self.mempool
+    }
+
+    /// Confirmed balance of `address`.
+    pub fn balance(&self, address: &str) -> u64 {
+        self.ledger.balance(address)
+    }
+
+    /// Nonce the next transaction of `address` has to carry once its confirmed ones are
+    /// applied, see [`Transaction::nonce`].
+    pub fn nonce(&self, address: &str) -> u64 {
+        self.ledger.nonce(address)
+    }
+
+    /// Nonce to give the next transaction `address` submits: the one after its last pending
+    /// transaction, if any.
+    pub fn next_nonce(&self, address: &str) -> u64 {
+        let confirmed = self.nonce(address);
+        self.mempool
+            .last_nonce(address)
+            .map_or(confirmed, |nonce| confirmed.max(nonce + 1))
+    }
+
+    fn validate_genesis(&self, block: &Block) -> Result<(), BlockchainError> {
+        if block.id == 0
+            && block.difficulty == difficulty::INITIAL_DIFFICULTY
+            && difficulty::meets(&block.hash, block.difficulty)
+            && block.transactions.is_empty()
+            && block.calculate_merkle_root() == block.merkle_root
+            && block.calculate_hash() == block.hash
+        {
+            Ok(())
+        } else {
+            Err(BlockchainError::InvalidGenesis)
+        }
+    }
+
+    /// Validates every block of `blocks` from genesis while rebuilding the ledger they lead
+    /// to. An empty chain is trivially valid.
+    fn replay(&self, blocks: &[Block]) -> Result<Ledger, BlockchainError> {
+        self.replay_from(blocks, 0, Ledger::new())
+    }
+
+    /// Same, trusting the first `from` blocks to lead to `ledger`.
+    fn replay_from(
+        &self,
+        blocks: &[Block],
+        from: usize,
+        mut ledger: Ledger,
+    ) -> Result<Ledger, BlockchainError> {
+        if let (0, Some(genesis)) = (from, blocks.first()) {
+            self.validate_genesis(genesis)?;
+        }
+
+        for block_index in from.max(1)..blocks.len() {
+            self.validate_block(&blocks[block_index], &blocks[..block_index])?;
+            ledger.apply_block(&blocks[block_index])?;
+        }
+
+        info!("Blockchain is {}", "valid".green());
+        Ok(ledger)
+    }
+
+    fn validate_transactions(&self, block: &Block) -> Result<(), BlockchainError> {
+        if block.transactions.len() > MAX_BLOCK_TRANSACTIONS {
+            return Err(BlockchainError::TooManyTransactions {
+                id: block.id,
+                count: block.transactions.len(),
+            });
+        }
+
+        // The reward may be split over several coinbase transactions, all leading the block
+        let coinbases = block
+            .transactions
+            .iter()
+            .take_while(|transaction| transaction.is_coinbase())
+            .count();
+        let reward = block.transactions[..coinbases]
+            .iter()
+            .fold(0, |reward: u64, transaction| {
+                reward.saturating_add(transaction.amount)
+            });
+        if coinbases > 0 && reward != BLOCK_REWARD {
+            return Err(BlockchainError::MalformedCoinbase {
+                id: block.id,
+                hash: block.transactions[0].hash(),
+            });
+        }
+
+        // Verify all signatures at once, so checking each transaction below finds them cached
+        SignatureVerifier::shared().verify_all(&block.transactions[coinbases..]);
+
+        let mut hashes = HashSet::new();
+        for (index, transaction) in block.transactions.iter().enumerate() {
+            let hash = transaction.hash();
+
+            let tag_too_long = transaction
+                .miner_tag()
+                .is_some_and(|tag| tag.len() > MAX_MINER_TAG_LEN);
+            let misplaced = index >= coinbases || transaction.nonce != block.id;
+            if transaction.is_coinbase() && (misplaced || tag_too_long) {
+                return Err(BlockchainError::MalformedCoinbase { id: block.id, hash });
+            }
+
+            if !transaction.is_valid() {
+                return Err(BlockchainError::InvalidTransaction { hash });
+            }
+
+            if !hashes.insert(hash.clone()) {
+                return Err(BlockchainError::DuplicateTransaction { id: block.id, hash });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// How the reward of the blocks we mine is split, see [`Blockchain::set_reward_split`].
+    pub fn reward_split(&self) -> &RewardSplit {
+        &self.reward_split
+    }
+
+    /// Pays the shares of `reward_split` out of the reward of the blocks we mine from now on.
+    pub fn set_reward_split(&mut self, reward_split: RewardSplit) {
+        self.reward_split = reward_split;
+    }
+
+    /// Tags the blocks we mine from now on with `tag`, see [`Transaction::miner_tag`].
+    pub fn set_miner_tag(&mut self, tag: Option<String>) -> Result<(), BlockchainError> {
+        if let Some(tag) = tag.as_ref().filter(|tag| tag.len() > MAX_MINER_TAG_LEN) {
+            return Err(BlockchainError::MinerTagTooLong { tag: tag.clone() });
+        }
+        self.miner_tag = tag;
+        Ok(())
+    }
+
+    /// Our clock corrected by the offset to the network, in Unix seconds.
+    pub fn adjusted_time(&self) -> i64 {
+        Utc::now().timestamp() + self.time_offset
+    }
+
+    /// Seconds added to our clock to agree with the network, see
+    /// [`Blockchain::set_time_offset`].
+    pub fn time_offset(&self) -> i64 {
+        self.time_offset
+    }
+
+    /// Corrects our clock by `offset` seconds when validating timestamps, so a skewed local
+    /// clock doesn't make us reject the blocks everybody else accepts.
+    pub fn set_time_offset(&mut self, offset: i64) {
+        self.time_offset = offset;
+    }
+
+    fn validate_timestamp(
+        &self,
+        block: &Block,
+        previous_block: &Block,
+    ) -> Result<(), BlockchainError> {
+        if block.timestamp < previous_block.timestamp {
+            return Err(BlockchainError::TimestampBeforePrevious {
+                id: block.id,
+                timestamp: block.timestamp,
+                previous: previous_block.timestamp,
+            });
+        }
+
+        if block.timestamp > self.adjusted_time() + MAX_FUTURE_BLOCK_TIME_SECS {
+            return Err(BlockchainError::TimestampInFuture {
+                id: block.id,
+                timestamp: block.timestamp,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Validates `block` as the successor of `previous_blocks`, the chain leading up to it.
+    /// Balances aren't checked here, only what the block and its ancestors alone determine.
+    pub fn validate_block(
+        &self,
+        block: &Block,
+        previous_blocks: &[Block],
+    ) -> Result<(), BlockchainError> {
+        let Some(previous_block) = previous_blocks.last() else {
+            return Err(BlockchainError::EmptyChain);
+        };
+
+        if block.id != previous_block.id + 1 {
+            return Err(BlockchainError::UnexpectedId {
+                expected: previous_block.id + 1,
+                found: block.id,
+            });
+        }
+
+        let expected_difficulty = difficulty::next(previous_blocks);
+        if block.difficulty != expected_difficulty {
+            return Err(BlockchainError::UnexpectedDifficulty {
+                id: block.id,
+                expected: expected_difficulty,
+                found: block.difficulty,
+            });
+        }
+
+        if !difficulty::meets(&block.hash, block.difficulty) {
+            return Err(BlockchainError::InsufficientProofOfWork { id: block.id });
+        }
+
+        if block.previous_hash != previous_block.hash {
+            return Err(BlockchainError::PreviousHashMismatch { id: block.id });
+        }
+
+        self.validate_timestamp(block, previous_block)?;
+
+        if block.calculate_hash() != block.hash {
+            return Err(BlockchainError::HashMismatch { id: block.id });
+        }
+
+        if block.calculate_merkle_root() != block.merkle_root {
+            return Err(BlockchainError::MerkleRootMismatch { id: block.id });
+        }
+
+        self.validate_transactions(block)?;
+
+        info!("Block #{} is {}", block.id, "valid".green());
+        Ok(())
+    }
+
+    /// Difficulty the next block on top of the tip has to be mined at.
+    pub fn next_difficulty(&self) -> u64 {
+        difficulty::next(&self.blocks)
+    }
+
+    /// Re-validates the whole active chain from genesis.
+    pub fn validate(&self) -> Result<(), BlockchainError> {
+        self.replay(&self.blocks).map(|_| ())
+    }
+
+    /// Checks a transaction submitted for mining doesn't need the chain to be rejected.
+    fn validate_submitted(transaction: &Transaction) -> Result<(), BlockchainError> {
+        if transaction.is_coinbase() {
+            return Err(BlockchainError::CoinbaseSubmitted {
+                hash: transaction.hash(),
+            });
+        }
+
+        if !transaction.is_valid() {
+            return Err(BlockchainError::InvalidTransaction {
+                hash: transaction.hash(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// What `address` can still spend: its confirmed balance minus what it already has
+    /// pending. Pending incoming funds don't count, they may never confirm.
+    pub fn available(&self, address: &str) -> u64 {
+        self.balance(address)
+            .saturating_sub(self.mempool.pending_outgoing(address))
+    }
+
+    /// Checks every transaction queued from now on against `policy` too, after the policies
+    /// added before.
+    pub fn add_mempool_policy(&mut self, policy: Box<dyn MempoolPolicy>) {
+        info!("Enforcing the {} mempool policy", policy.name());
+        self.policies.push(policy);
+    }
+
+    /// Runs the mempool policies on a transaction, stopping at the first rejecting it. Returns
+    /// the notes of the others, each prefixed with the name of its policy.
+    fn check_policies(&self, transaction: &Transaction) -> Result<Vec<String>, BlockchainError> {
+        let mut notes = Vec::new();
+        for policy in &self.policies {
+            match policy.check(transaction, self) {
+                Verdict::Accept => {}
+                Verdict::Annotate(note) => notes.push(format!("{}: {}", policy.name(), note)),
+                Verdict::Reject(reason) => {
+                    return Err(BlockchainError::PolicyRejected {
+                        hash: transaction.hash(),
+                        policy: policy.name().to_string(),
+                        reason,
+                    })
+                }
+            }
+        }
+        Ok(notes)
+    }
+
+    /// Evicts pending transactions unconfirmed for `ttl` or longer, see [`Mempool::expire`],
+    /// so abandoned ones don't linger. Each eviction is logged; the evicted transactions are
+    /// returned so their senders can be told they failed.
+    pub fn expire_transactions(&mut self, ttl: Duration) -> Vec<Transaction> {
+        let at = self.adjusted_time();
+        let expired = self.mempool.expire(ttl);
+        for transaction in &expired {
+            warn!(
+                "Transaction {} expired from the mempool unconfirmed",
+                transaction.hash()
+            );
+        }
+
+        if !expired.is_empty() {
+            let input = self.decisions.is_some().then(|| Input::Expired {
+                transactions: expired.clone(),
+            });
+            self.record(at, input, || String::from("evicted"));
+        }
+        expired
+    }
+
+    /// Drops `transactions` from the mempool, as the expiry timer did when a decision log is
+    /// replayed.
+    pub(crate) fn evict(&mut self, transactions: &[Transaction]) {
+        self.mempool.remove(transactions);
+    }
+
+    /// Records to the decision log, if any, that `input` came in at `at` and led to `outcome`.
+    /// `input` is only built when there is a log. Failing to record doesn't fail the decision.
+    fn record(&mut self, at: i64, input: Option<Input>, outcome: impl FnOnce() -> String) {
+        let (Some(log), Some(input)) = (self.decisions.as_mut(), input) else {
+            return;
+        };
+
+        let decision = Decision {
+            at,
+            input,
+            outcome: outcome(),
+        };
+        if let Err(err) = log.append(&decision) {
+            warn!(
+                "Failed to record a decision to {}: {}",
+                log.path().display(),
+                err
+            );
+        }
+    }
+
+    /// Records every input the chain decides on to `log` from now on, starting with the chain
+    /// as it is, see [`decisions::replay`].
+    pub fn set_decision_log(&mut self, log: DecisionLog) {
+        self.decisions = Some(log);
+        let input = Input::Start {
+            height: self.height(),
+            tip: self.tip().map(|tip| tip.hash.clone()),
+        };
+        self.record(self.adjusted_time(), Some(input), || {
+            String::from("started")
+        });
+    }
+
+    /// Queues a transaction for mining if it carries the next nonce of its sender, the sender
+    /// can afford it on top of what the sender already has pending, and our mempool policies
+    /// accept it. It may replace the last transaction its sender has pending instead, see
+    /// [`Blockchain::next_nonce`]. What the policies reject isn't
+    /// recorded to the decision log, it never got to the chain.
+    pub fn submit_transaction(&mut self, transaction: Transaction) -> Result<(), BlockchainError> {
+        let at = self.adjusted_time();
+        let input = self.decisions.is_some().then(|| Input::Transaction {
+            transaction: transaction.clone(),
+        });
+        let result = self.queue_transaction(transaction);
+        let input =
+            input.filter(|_| !matches!(result, Err(BlockchainError::PolicyRejected { .. })));
+        self.record(at, input, || {
+            decisions::outcome(&result, |()| String::from("queued"))
+        });
+        result
+    }
+
+    fn queue_transaction(&mut self, transaction: Transaction) -> Result<(), BlockchainError> {
+        Self::validate_submitted(&transaction)?;
+        let hash = transaction.hash();
+        if self.transaction_index.contains_key(&hash) {
+            return Err(BlockchainError::AlreadyConfirmed { hash });
+        }
+
+        let replaced = self.check_nonce(&transaction)?;
+        let available = self.available(&transaction.sender)
+            + replaced.as_ref().map_or(0, |replaced| replaced.amount);
+        if transaction.amount > available {
+            return Err(BlockchainError::InsufficientFunds {
+                hash,
+                amount: transaction.amount,
+                available,
+            });
+        }
+
+        let notes = self.check_policies(&transaction)?;
+        if let Some(replaced) = &replaced {
+            self.mempool.remove(std::slice::from_ref(replaced));
+        }
+        if let Err(err) = self.mempool.add(transaction) {
+            if let Some(replaced) = replaced {
+                let _ = self.mempool.add(replaced);
+            }
+            return Err(err);
+        }
+        if let Some(replaced) = replaced {
+            info!(
+                "Transaction {} replaced pending transaction {}",
+                hash,
+                replaced.hash()
+            );
+        }
+        self.mempool.annotate(&hash, notes);
+        Ok(())
+    }
+
+    /// Checks the nonce of a transaction about to be queued: it has to be the next one of its
+    /// sender, see [`Blockchain::next_nonce`], so the mempool never holds a transaction that
+    /// can't be mined before others are. Transactions further ahead are refused rather than
+    /// held back. A transaction may also take the nonce of the last pending transaction of
+    /// its sender if nothing pending depends on that one, which it then replaces; that pending
+    /// transaction is returned.
+    fn check_nonce(
+        &self,
+        transaction: &Transaction,
+    ) -> Result<Option<Transaction>, BlockchainError> {
+        let hash = transaction.hash();
+        let (confirmed, next) = (
+            self.nonce(&transaction.sender),
+            self.next_nonce(&transaction.sender),
+        );
+        if transaction.nonce == next {
+            return Ok(None);
+        }
+        if transaction.nonce < confirmed || transaction.nonce > next {
+            return Err(BlockchainError::UnexpectedNonce {
+                hash,
+                expected: next,
+                found: transaction.nonce,
+            });
+        }
+
+        let Some(pending) = self.mempool.iter().find(|pending| {
+            pending.sender == transaction.sender && pending.nonce == transaction.nonce
+        }) else {
+            return Ok(None);
+        };
+        let pending_hash = pending.hash();
+        if pending_hash == hash {
+            return Err(BlockchainError::AlreadyPending { hash });
+        }
+        let depended_on = self
+            .mempool
+            .stats(&pending_hash)
+            .is_some_and(|stats| stats.descendant_count > 1);
+        if transaction.nonce + 1 != next || depended_on {
+            return Err(BlockchainError::NonceInUse {
+                hash,
+                nonce: transaction.nonce,
+                pending: pending_hash,
+            });
+        }
+        Ok(Some(pending.clone()))
+    }
+
+    /// Drops the pending transactions whose nonce a block used up with another transaction of
+    /// their sender; they can never be mined any more.
+    fn drop_conflicts(&mut self) {
+        let conflicts: Vec<Transaction> = self
+            .mempool
+            .iter()
+            .filter(|transaction| transaction.nonce < self.nonce(&transaction.sender))
+            .cloned()
+            .collect();
+        for transaction in &conflicts {
+            debug!(
+                "Dropping pending transaction {}, its nonce was used up",
+                transaction.hash()
+            );
+        }
+        self.mempool.remove(&conflicts);
+    }
+
+    /// Queues a group of dependent transactions atomically: either all of them or none. Unlike
+    /// with [`Blockchain::submit_transaction`], a transaction may spend what earlier ones of the
+    /// package pay its sender, so a child can be submitted together with the parent funding it.
+    /// If a mempool policy rejects any of its transactions, the whole package is refused.
+    pub fn submit_package(
+        &mut self,
+        transactions: Vec<Transaction>,
+    ) -> Result<(), BlockchainError> {
+        let at = self.adjusted_time();
+        let input = self.decisions.is_some().then(|| Input::Package {
+            transactions: transactions.clone(),
+        });
+        let result = self.queue_package(transactions);
+        let input =
+            input.filter(|_| !matches!(result, Err(BlockchainError::PolicyRejected { .. })));
+        self.record(at, input, || {
+            decisions::outcome(&result, |()| String::from("queued"))
+        });
+        result
+    }
+
+    fn queue_package(&mut self, transactions: Vec<Transaction>) -> Result<(), BlockchainError> {
+        if transactions.is_empty() || transactions.len() > MAX_PACKAGE_TRANSACTIONS {
+            return Err(BlockchainError::InvalidPackageSize {
+                count: transactions.len(),
+            });
+        }
+
+        SignatureVerifier::shared().verify_all(&transactions);
+
+        let mut hashes = HashSet::new();
+        let mut available: HashMap<&str, u64> = HashMap::new();
+        let mut nonces: HashMap<&str, u64> = HashMap::new();
+        for transaction in &transactions {
+            Self::validate_submitted(transaction)?;
+
+            let hash = transaction.hash();
+            if self.transaction_index.contains_key(&hash) {
+                return Err(BlockchainError::AlreadyConfirmed { hash });
+            }
+            if self.mempool.contains(&hash) || !hashes.insert(hash.clone()) {
+                return Err(BlockchainError::AlreadyPending { hash });
+            }
+
+            // Packages only extend what their senders have pending, they don't replace any of it
+            let nonce = nonces
+                .entry(&transaction.sender)
+                .or_insert_with(|| self.next_nonce(&transaction.sender));
+            if transaction.nonce != *nonce {
+                return Err(BlockchainError::UnexpectedNonce {
+                    hash,
+                    expected: *nonce,
+                    found: transaction.nonce,
+                });
+            }
+            *nonce += 1;
+
+            let sender_available = *available
+                .entry(&transaction.sender)
+                .or_insert_with(|| self.available(&transaction.sender));
+            if transaction.amount > sender_available {
+                return Err(BlockchainError::InsufficientFunds {
+                    hash,
+                    amount: transaction.amount,
+                    available: sender_available,
+                });
+            }
+            available.insert(&transaction.sender, sender_available - transaction.amount);
+
+            let recipient_available = available
+                .entry(&transaction.recipient)
+                .or_insert_with(|| self.available(&transaction.recipient));
+            *recipient_available = recipient_available.saturating_add(transaction.amount);
+        }
+
+        let mut notes = Vec::with_capacity(transactions.len());
+        for transaction in &transactions {
+            notes.push((transaction.hash(), self.check_policies(transaction)?));
+        }
+
+        info!(
+            "Accepting a package of {} transaction(s)",
+            transactions.len()
+        );
+        self.mempool.add_package(transactions)?;
+        for (hash, notes) in notes {
+            self.mempool.annotate(&hash, notes);
+        }
+        Ok(())
+    }
+
+    /// Picks the transactions for the next block: the reward, split between `miner_address`
+    /// and the shares of [`Blockchain::reward_split`], followed by the oldest pending
+    /// transactions that can still be applied on top of the tip, for the block at `height`.
+    fn next_block_transactions(&self, miner_address: &str, height: u64) -> Vec<Transaction> {
+        let mut transactions = self.reward_split.coinbase(miner_address);
+        for coinbase in &mut transactions {
+            coinbase.nonce = height;
+        }
+        if let (Some(coinbase), Some(tag)) = (transactions.first_mut(), &self.miner_tag) {
+            coinbase.signature = tag.clone();
+        }
+        let mut ledger = self.ledger.clone();
+
+        for transaction in self
+            .mempool
+            .select(MAX_BLOCK_TRANSACTIONS - transactions.len())
+        {
+            match ledger.apply_transaction(&transaction) {
+                Ok(()) => transactions.push(transaction),
+                Err(err) => debug!("Skipping pending transaction: {}", err),
+            }
+        }
+
+        transactions
+    }
+
+    /// The block to mine next on top of the tip, paying its reward to `miner_address`, less the
+    /// shares of [`Blockchain::reward_split`].
+    pub fn block_template(&self, miner_address: &str) -> Result<BlockTemplate, BlockchainError> {
+        let tip = self.tip().ok_or(BlockchainError::EmptyChain)?;
+
+        Ok(BlockTemplate {
+            id: tip.id + 1,
+            previous_hash: tip.hash.clone(),
+            difficulty: self.next_difficulty(),
+            timestamp: self.adjusted_time(),
+            transactions: self.next_block_transactions(miner_address, tip.id + 1),
+        })
+    }
+
+    /// Appends `block` to the tip if it's valid there, or starts the chain with it if it's a
+    /// valid genesis block and the chain is empty.
+    pub fn try_add_block(&mut self, block: Block) -> Result<(), BlockchainError> {
+        let at = self.adjusted_time();
+        let input = self.decisions.is_some().then(|| Input::Block {
+            block: block.clone(),
+        });
+        let result = self.add_block(block);
+        self.record(at, input, || {
+            decisions::outcome(&result, |()| String::from("connected"))
+        });
+        result
+    }
+
+    fn add_block(&mut self, block: Block) -> Result<(), BlockchainError> {
+        let Some(previous_block) = self.blocks.last() else {
+            self.validate_genesis(&block)?;
+            self.persist(&block)?;
+            Self::index_block(&mut self.transaction_index, &block);
+            self.blocks.push(block);
+            self.sync_history(0);
+            info!("Genesis block was successfully added to the blockchain");
+            return Ok(());
+        };
+
+        self.validate_block(&block, &self.blocks)?;
+        let mut ledger = self.ledger.clone();
+        ledger.apply_block(&block)?;
+
+        let interval = block.timestamp - previous_block.timestamp;
+        info!(
+            "Block #{} was successfully added to the blockchain (+{}s after block #{})",
+            block.id, interval, previous_block.id
+        );
+        self.persist(&block)?;
+        self.ledger = ledger;
+        self.mempool.remove(&block.transactions);
+        self.drop_conflicts();
+        Self::index_block(&mut self.transaction_index, &block);
+        self.blocks.push(block);
+        self.sync_history(self.blocks.len() - 1);
+        self.update_checkpoint();
+
+        let next_difficulty = self.next_difficulty();
+        let previous_difficulty = self.blocks[self.blocks.len() - 1].difficulty;
+        if next_difficulty != previous_difficulty {
+            info!(
+                "Difficulty was retargeted from {} to {}",
+                previous_difficulty, next_difficulty
+            );
+        }
+        Ok(())
+    }
+
+    /// Appends `blocks`, consecutive blocks extending the tip or starting the chain, all
+    /// together or, if any of them is invalid, none of them. They are stored in a single write,
+    /// which makes this much faster than adding them one by one when syncing or importing.
+    pub fn try_add_blocks(&mut self, blocks: Vec<Block>) -> Result<(), BlockchainError> {
+        let at = self.adjusted_time();
+        let input = self.decisions.is_some().then(|| Input::Blocks {
+            blocks: blocks.clone(),
+        });
+        let result = self.add_blocks(blocks);
+        self.record(at, input, || {
+            decisions::outcome(&result, |()| String::from("connected"))
+        });
+        result
+    }
+
+    fn add_blocks(&mut self, blocks: Vec<Block>) -> Result<(), BlockchainError> {
+        let (Some(first), Some(last)) = (blocks.first(), blocks.last()) else {
+            return Ok(());
+        };
+        let (first_id, last_id) = (first.id, last.id);
+        let start = self.blocks.len();
+
+        let mut ledger = self.ledger.clone();
+        for block in blocks {
+            let result = if self.blocks.is_empty() {
+                self.validate_genesis(&block)
+            } else {
+                self.validate_block(&block, &self.blocks)
+                    .and_then(|()| ledger.apply_block(&block))
+            };
+            if let Err(err) = result {
+                self.blocks.truncate(start);
+                return Err(err);
+            }
+            self.blocks.push(block);
+        }
+
+        if let Some(storage) = self.storage.as_mut() {
+            if let Err(err) = storage.append_all(&self.blocks[start..]) {
+                self.blocks.truncate(start);
+                return Err(err.into());
+            }
+        }
+        self.ledger = ledger;
+        for block in &self.blocks[start..] {
+            self.mempool.remove(&block.transactions);
+            Self::index_block(&mut self.transaction_index, block);
+        }
+        self.drop_conflicts();
+        self.sync_history(start);
+        self.update_checkpoint();
+
+        info!(
+            "Blocks #{} to #{} were successfully added to the blockchain",
+            first_id, last_id
+        );
+        Ok(())
+    }
+
+    /// Fork choice: switches to `candidate`, a full chain from genesis, if it has more
+    /// cumulative work than the active chain. Fails if the candidate is invalid. Our blocks
+    /// past the fork point are rolled back and their transactions go back to the mempool unless
+    /// the candidate already confirms them.
+    pub fn replace_chain(
+        &mut self,
+        candidate: Vec<Block>,
+    ) -> Result<ReplaceChainOutcome, BlockchainError> {
+        let at = self.adjusted_time();
+        let input = self.decisions.is_some().then(|| {
+            let fork = self.fork_index(&candidate);
+            Input::ReplaceChain {
+                fork: fork as u64,
+                blocks: candidate[fork..].to_vec(),
+            }
+        });
+        let result = self.switch_chain(candidate);
+        self.record(at, input, || {
+            decisions::outcome(&result, decisions::describe_replacement)
+        });
+        result
+    }
+
+    /// Number of leading blocks `candidate` has in common with the active chain.
+    fn fork_index(&self, candidate: &[Block]) -> usize {
+        self.blocks
+            .iter()
+            .zip(candidate)
+            .take_while(|(ours, theirs)| ours.hash == theirs.hash)
+            .count()
+    }
+
+    fn switch_chain(
+        &mut self,
+        mut candidate: Vec<Block>,
+    ) -> Result<ReplaceChainOutcome, BlockchainError> {
+        if difficulty::work(&candidate) <= difficulty::work(&self.blocks) {
+            debug!("Candidate chain doesn't have more work than ours, keeping ours");
+            return Ok(ReplaceChainOutcome::NotHeavier);
+        }
+
+        let ledger = self.replay(&candidate)?;
+
+        let fork_index = self.fork_index(&candidate);
+        let connected = candidate.split_off(fork_index);
+
+        if let Some(storage) = self.storage.as_mut() {
+            storage.replace(fork_index, &connected)?;
+        }
+
+        let disconnected = self.blocks.split_off(fork_index);
+        let confirmed: HashSet<String> = connected
+            .iter()
+            .flat_map(|block| &block.transactions)
+            .map(Transaction::hash)
+            .collect();
+        let mut pending: Vec<Transaction> = disconnected
+            .iter()
+            .flat_map(|block| block.transactions.iter().cloned())
+            .filter(|transaction| !transaction.is_coinbase())
+            .collect();
+        pending.extend(self.mempool.drain());
+
+        let outcome = ReplaceChainOutcome::Replaced {
+            fork_id: fork_index as u64,
+            disconnected: disconnected.len(),
+            connected: connected.len(),
+        };
+        for transaction in disconnected.iter().flat_map(|block| &block.transactions) {
+            self.transaction_index.remove(&transaction.hash());
+        }
+        for block in &connected {
+            Self::index_block(&mut self.transaction_index, block);
+        }
+        self.blocks.extend(connected);
+        self.ledger = ledger;
+        self.sync_history(fork_index);
+        self.checkpoint_height = self.checkpoint_height.min(fork_index as u64);
+        self.update_checkpoint();
+
+        for transaction in pending {
+            if confirmed.contains(&transaction.hash()) {
+                continue;
+            }
+            if let Err(err) = self.queue_transaction(transaction) {
+                debug!("Dropping transaction after the reorg: {}", err);
+            }
+        }
+
+        Ok(outcome)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::BlockReader;
+    use crate::vectors;
+    use crate::wallet::{Wallet, DEFAULT_NETWORK};
+
+    #[test]
+    fn confirmations_count_the_blocks_of_the_active_chain() {
+        let mut blocks = vectors::blocks();
+        let unmined = blocks.pop().unwrap();
+        let coinbase = blocks[1].transactions[0].hash();
+        let mut blockchain = Blockchain::new();
+        assert_eq!(blockchain.confirmations(&coinbase), 0);
+
+        blockchain.try_add_blocks(blocks).unwrap();
+        assert_eq!(blockchain.confirmations(&coinbase), 1);
+        assert!(blockchain.try_add_block(unmined.clone()).is_err());
+        assert_eq!(blockchain.confirmations(&unmined.transactions[0].hash()), 0);
+    }
+
+    #[test]
+    fn reorgs_to_heavier_forks_replace_everything_derived_from_the_chain() {
+        let blocks = vectors::blocks();
+        let (genesis, ours, transfer) = (&blocks[0], &blocks[1], &blocks[1].transactions[1]);
+        let fork = vectors::fork();
+        let dir = std::env::temp_dir().join(format!("blockchain-reorg-{}", std::process::id()));
+        let path = dir.join("blocks.jsonl");
+        let mut blockchain = Blockchain::load(&path).unwrap();
+        blockchain.try_add_blocks(blocks[..2].to_vec()).unwrap();
+        let views = blockchain.views();
+
+        let mut candidate = vec![genesis.clone()];
+        candidate.extend(fork.iter().cloned());
+        let outcome = blockchain.replace_chain(candidate.clone()).unwrap();
+        assert!(matches!(
+            outcome,
+            ReplaceChainOutcome::Replaced {
+                fork_id: 1,
+                disconnected: 1,
+                connected: 2,
+            }
+        ));
+
+        let stored: Vec<Block> = BlockReader::blocks(&path)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            stored.iter().map(|block| &block.hash).collect::<Vec<_>>(),
+            candidate
+                .iter()
+                .map(|block| &block.hash)
+                .collect::<Vec<_>>()
+        );
+
+        // The transfer is still valid on the fork, whose first block pays its sender too
+        assert!(blockchain.mempool().contains(&transfer.hash()));
+        assert_eq!(blockchain.confirmations(&transfer.hash()), 0);
+        assert_eq!(blockchain.confirmations(&ours.transactions[0].hash()), 0);
+        assert_eq!(blockchain.confirmations(&fork[0].transactions[0].hash()), 2);
+        assert_eq!(blockchain.confirmations(&fork[1].transactions[0].hash()), 1);
+
+        assert_eq!(blockchain.balance(&transfer.sender), BLOCK_REWARD);
+        assert_eq!(blockchain.balance(&transfer.recipient), BLOCK_REWARD);
+        assert_eq!(blockchain.nonce(&transfer.sender), 0);
+
+        let view = views.latest();
+        assert_eq!(view.height(), 3);
+        assert_eq!(view.tip().map(|tip| &tip.hash), Some(&fork[1].hash));
+        assert!(view.block(&ours.hash).is_none());
+        assert!(view.transaction(&transfer.hash()).is_none());
+        assert_eq!(
+            view.transaction(&fork[1].transactions[0].hash())
+                .map(|(block, _)| block.id),
+            Some(2)
+        );
+
+        drop(blockchain);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn forks_without_more_work_leave_the_chain_alone() {
+        let blocks = vectors::blocks();
+        let fork = vectors::fork();
+        let dir = std::env::temp_dir().join(format!("blockchain-no-reorg-{}", std::process::id()));
+        let path = dir.join("blocks.jsonl");
+        let mut blockchain = Blockchain::load(&path).unwrap();
+        blockchain.try_add_blocks(blocks[..2].to_vec()).unwrap();
+        blockchain.flush().unwrap();
+        let stored = std::fs::read(&path).unwrap();
+        let views = blockchain.views();
+        let ledger = (
+            blockchain.balance(&blocks[1].transactions[1].sender),
+            blockchain.balance(&blocks[1].transactions[1].recipient),
+        );
+
+        // As much work as ours, but no more
+        let candidate = vec![blocks[0].clone(), fork[0].clone()];
+        assert!(matches!(
+            blockchain.replace_chain(candidate),
+            Ok(ReplaceChainOutcome::NotHeavier)
+        ));
+
+        assert_eq!(blockchain.tip().map(|tip| &tip.hash), Some(&blocks[1].hash));
+        assert_eq!(std::fs::read(&path).unwrap(), stored);
+        assert!(blockchain.mempool().is_empty());
+        assert_eq!(
+            blockchain.confirmations(&blocks[1].transactions[1].hash()),
+            1
+        );
+        assert_eq!(blockchain.confirmations(&fork[0].transactions[0].hash()), 0);
+        assert_eq!(
+            (
+                blockchain.balance(&blocks[1].transactions[1].sender),
+                blockchain.balance(&blocks[1].transactions[1].recipient),
+            ),
+            ledger
+        );
+        assert_eq!(
+            views.latest().tip().map(|tip| &tip.hash),
+            Some(&blocks[1].hash)
+        );
+
+        drop(blockchain);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn templates_are_timestamped_at_the_adjusted_time() {
+        let mut blockchain = Blockchain::from_blocks(vectors::blocks()[..2].to_vec()).unwrap();
+        blockchain.set_time_offset(-300);
+
+        let before = blockchain.adjusted_time();
+        let template = blockchain.block_template("miner").unwrap();
+        assert!((before..=blockchain.adjusted_time()).contains(&template.timestamp));
+        assert!(template.timestamp < Utc::now().timestamp() - 200);
+    }
+
+    #[test]
+    fn epochs_answer_difficulty_at_queries() {
+        let blocks = vectors::blocks();
+        let blockchain = Blockchain::from_blocks(blocks[..2].to_vec()).unwrap();
+
+        assert_eq!(blockchain.epochs().len(), 1);
+        assert_eq!(blockchain.difficulty_at(1), Some(blocks[1].difficulty));
+        assert_eq!(
+            blockchain.difficulty_at(2),
+            Some(blockchain.next_difficulty())
+        );
+        assert_eq!(blockchain.difficulty_at(3), None);
+    }
+
+    #[test]
+    fn transactions_carry_the_next_nonce_of_their_sender() {
+        let blocks = vectors::blocks();
+        let mut blockchain = Blockchain::from_blocks(blocks[..2].to_vec()).unwrap();
+        let (sender, recipient) = (
+            Wallet::from_secret_key(DEFAULT_NETWORK, [1; 32]),
+            Wallet::from_secret_key(DEFAULT_NETWORK, [2; 32]),
+        );
+        assert_eq!(blockchain.nonce(&sender.address()), 1);
+
+        // A confirmed transaction can't be queued, nor mined, a second time
+        let confirmed = blocks[1].transactions[1].clone();
+        assert!(matches!(
+            blockchain.submit_transaction(confirmed),
+            Err(BlockchainError::AlreadyConfirmed { .. })
+        ));
+        assert!(matches!(
+            blockchain.ledger.clone().apply_block(&blocks[1]),
+            Err(BlockchainError::UnexpectedNonce {
+                expected: 1,
+                found: 0,
+                ..
+            })
+        ));
+        for nonce in [0, 2] {
+            assert!(matches!(
+                blockchain.submit_transaction(sender.transfer(recipient.address(), 5, nonce)),
+                Err(BlockchainError::UnexpectedNonce { expected: 1, .. })
+            ));
+        }
+
+        blockchain
+            .submit_transaction(sender.transfer(recipient.address(), 5, 1))
+            .unwrap();
+        assert_eq!(blockchain.next_nonce(&sender.address()), 2);
+        let replacement = sender.transfer(recipient.address(), 6, 1);
+        blockchain.submit_transaction(replacement.clone()).unwrap();
+        assert_eq!(blockchain.mempool().len(), 1);
+        assert!(blockchain.mempool().contains(&replacement.hash()));
+
+        let mut block = blocks[1].clone();
+        block.transactions[0].nonce = 2;
+        assert!(matches!(
+            blockchain.validate_transactions(&block),
+            Err(BlockchainError::MalformedCoinbase { id: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn block_batches_connect_all_or_nothing() {
+        let blocks = vectors::blocks();
+        let mut blockchain = Blockchain::new();
+
+        assert!(matches!(
+            blockchain.try_add_blocks(blocks.clone()),
+            Err(BlockchainError::InsufficientProofOfWork { id: 2 })
+        ));
+        assert_eq!(blockchain.height(), 0);
+
+        blockchain
+            .try_add_blocks(blocks[..2].to_vec())
+            .expect("vector chain should be valid");
+        assert_eq!(blockchain.height(), 2);
+        assert_eq!(
+            blockchain.confirmations(&blocks[1].transactions[0].hash()),
+            1
+        );
+    }
+}
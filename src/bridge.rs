@@ -0,0 +1,235 @@
+use chrono::Utc;
+use log::{debug, info};
+
+use crate::block::BlockHeader;
+use crate::blockchain::MAX_FUTURE_BLOCK_TIME_SECS;
+use crate::merkle::{self, MerkleProof};
+use crate::transaction::Transaction;
+use crate::{difficulty, BlockchainError, ReplaceChainOutcome};
+
+/// Checks a header chain from genesis on its own: continuity, proof of work, difficulty
+/// transitions and timestamps, everything but the transactions. Returns the relay following it,
+/// for the tip and work.
+pub fn verify_headers(headers: Vec<BlockHeader>) -> Result<HeaderRelay, BlockchainError> {
+    let mut headers = headers.into_iter();
+    let genesis = headers.next().ok_or(BlockchainError::EmptyChain)?;
+
+    let mut relay = HeaderRelay::new(genesis)?;
+    relay.ingest(headers.collect())?;
+    Ok(relay)
+}
+
+/// Light client of another deployment's chain: follows its headers, checked against the same
+/// proof-of-work and retargeting rules as full blocks, and verifies Merkle proofs that a
+/// transaction was included in one of them. A lock-and-mint bridge mints on this chain once a
+/// lock on the other one is proven and buried deep enough.
+///
+/// The relay trusts nothing but the genesis header it's created with, which pins the
+/// deployment it follows.
+pub struct HeaderRelay {
+    headers: Vec<BlockHeader>,
+}
+
+impl HeaderRelay {
+    /// A relay following the chain that starts with `genesis`.
+    pub fn new(genesis: BlockHeader) -> Result<Self, BlockchainError> {
+        if genesis.id != 0
+            || genesis.difficulty != difficulty::INITIAL_DIFFICULTY
+            || !difficulty::meets(&genesis.hash, genesis.difficulty)
+            || genesis.calculate_hash() != genesis.hash
+        {
+            return Err(BlockchainError::InvalidGenesis);
+        }
+
+        Ok(Self {
+            headers: vec![genesis],
+        })
+    }
+
+    /// Headers of the followed chain, from genesis to the tip.
+    pub fn headers(&self) -> &[BlockHeader] {
+        &self.headers
+    }
+
+    pub fn tip(&self) -> &BlockHeader {
+        &self.headers[self.headers.len() - 1]
+    }
+
+    pub fn height(&self) -> u64 {
+        self.headers.len() as u64
+    }
+
+    /// Total work behind the followed chain, see [`difficulty::work`].
+    pub fn work(&self) -> u64 {
+        Self::work_of(&self.headers)
+    }
+
+    fn work_of(headers: &[BlockHeader]) -> u64 {
+        headers
+            .iter()
+            .fold(0, |work, header| work.saturating_add(header.difficulty))
+    }
+
+    /// Ingests consecutive `headers` relayed from the other chain. They must connect to a
+    /// header we already know; if they fork off before our tip, they're only adopted once the
+    /// branch has more work than ours, like [`crate::Blockchain::replace_chain`] does for full
+    /// chains. Fails without changing anything if any header is invalid.
+    pub fn ingest(
+        &mut self,
+        headers: Vec<BlockHeader>,
+    ) -> Result<ReplaceChainOutcome, BlockchainError> {
+        let Some(first) = headers.first() else {
+            return Ok(ReplaceChainOutcome::NotHeavier);
+        };
+
+        let fork_index = first.id as usize;
+        if fork_index == 0 || fork_index > self.headers.len() {
+            return Err(BlockchainError::UnexpectedId {
+                expected: self.height(),
+                found: first.id,
+            });
+        }
+
+        let mut candidate = self.headers[..fork_index].to_vec();
+        for header in headers {
+            Self::validate_header(&header, &candidate)?;
+            candidate.push(header);
+        }
+
+        if Self::work_of(&candidate) <= self.work() {
+            debug!("Relayed headers don't have more work than ours, keeping ours");
+            return Ok(ReplaceChainOutcome::NotHeavier);
+        }
+
+        let connected = candidate.len() - fork_index;
+        let disconnected = self.headers.len() - fork_index;
+        self.headers = candidate;
+        info!(
+            "Relayed chain is now at height {} (headers from #{} on replaced)",
+            self.height(),
+            fork_index
+        );
+
+        Ok(ReplaceChainOutcome::Replaced {
+            fork_id: fork_index as u64,
+            disconnected,
+            connected,
+        })
+    }
+
+    /// Validates `header` as the successor of `previous_headers`, with the same checks
+    /// [`crate::Blockchain::validate_block`] makes of a block header.
+    fn validate_header(
+        header: &BlockHeader,
+        previous_headers: &[BlockHeader],
+    ) -> Result<(), BlockchainError> {
+        let Some(previous_header) = previous_headers.last() else {
+            return Err(BlockchainError::EmptyChain);
+        };
+
+        if header.id != previous_header.id + 1 {
+            return Err(BlockchainError::UnexpectedId {
+                expected: previous_header.id + 1,
+                found: header.id,
+            });
+        }
+
+        let expected_difficulty = difficulty::next_for_headers(previous_headers);
+        if header.difficulty != expected_difficulty {
+            return Err(BlockchainError::UnexpectedDifficulty {
+                id: header.id,
+                expected: expected_difficulty,
+                found: header.difficulty,
+            });
+        }
+
+        if !difficulty::meets(&header.hash, header.difficulty) {
+            return Err(BlockchainError::InsufficientProofOfWork { id: header.id });
+        }
+
+        if header.previous_hash != previous_header.hash {
+            return Err(BlockchainError::PreviousHashMismatch { id: header.id });
+        }
+
+        if header.calculate_hash() != header.hash {
+            return Err(BlockchainError::HashMismatch { id: header.id });
+        }
+
+        if header.timestamp < previous_header.timestamp {
+            return Err(BlockchainError::TimestampBeforePrevious {
+                id: header.id,
+                timestamp: header.timestamp,
+                previous: previous_header.timestamp,
+            });
+        }
+
+        if header.timestamp > Utc::now().timestamp() + MAX_FUTURE_BLOCK_TIME_SECS {
+            return Err(BlockchainError::TimestampInFuture {
+                id: header.id,
+                timestamp: header.timestamp,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// SPV check: `transaction` is included in the followed block `block_hash`, as shown by
+    /// `proof` from [`crate::Block::transaction_proof`], and that block has at least
+    /// `confirmations` blocks on top of it, itself included.
+    pub fn verify_transaction(
+        &self,
+        block_hash: &str,
+        transaction: &Transaction,
+        proof: &MerkleProof,
+        confirmations: u64,
+    ) -> Result<(), BlockchainError> {
+        let header = self
+            .headers
+            .iter()
+            .find(|header| header.hash == block_hash)
+            .ok_or_else(|| BlockchainError::UnknownBlock {
+                hash: block_hash.to_string(),
+            })?;
+
+        let buried = self.height() - header.id;
+        if buried < confirmations {
+            return Err(BlockchainError::NotEnoughConfirmations {
+                hash: block_hash.to_string(),
+                confirmations: buried,
+                required: confirmations,
+            });
+        }
+
+        if !merkle::verify_proof(&header.merkle_root, transaction.witness_digest(), proof) {
+            return Err(BlockchainError::InvalidInclusionProof {
+                hash: transaction.hash(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors;
+    use crate::Block;
+
+    #[test]
+    fn header_chains_are_verified_from_their_genesis() {
+        let blocks = vectors::blocks();
+        let headers: Vec<BlockHeader> = blocks.iter().map(Block::header).collect();
+
+        let relay = verify_headers(headers[..2].to_vec()).expect("vector headers should verify");
+        assert_eq!(relay.height(), 2);
+        assert!(matches!(
+            verify_headers(headers),
+            Err(BlockchainError::InsufficientProofOfWork { id: 2 })
+        ));
+        assert!(matches!(
+            verify_headers(Vec::new()),
+            Err(BlockchainError::EmptyChain)
+        ));
+    }
+}
@@ -0,0 +1,81 @@
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::network::Message;
+
+/// Dumps messages peers got wrong into a directory, one JSON file each, so the exact bytes can
+/// be replayed in a regression test.
+pub struct MisbehaviorCapture {
+    dir: PathBuf,
+    /// Tells apart captures made within the same millisecond.
+    sequence: AtomicU64,
+}
+
+/// A captured message together with the context it was refused in.
+#[derive(Serialize, Deserialize)]
+pub struct CapturedMessage {
+    /// Listen address of the peer, if it got through its handshake.
+    pub peer: Option<SocketAddr>,
+    /// When the message was refused, in Unix milliseconds.
+    pub received_at: i64,
+    /// Why the message was refused.
+    pub reason: String,
+    /// Height of our chain at that point.
+    pub height: u64,
+    /// The line the peer sent, byte for byte.
+    pub message: String,
+}
+
+impl MisbehaviorCapture {
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        Ok(Self {
+            dir,
+            sequence: AtomicU64::new(0),
+        })
+    }
+
+    /// Writes `message` to a new file in the capture directory and returns its path.
+    pub fn record(
+        &self,
+        peer: Option<SocketAddr>,
+        reason: String,
+        height: u64,
+        message: &str,
+    ) -> io::Result<PathBuf> {
+        let captured = CapturedMessage {
+            peer,
+            received_at: Utc::now().timestamp_millis(),
+            reason,
+            height,
+            message: message.to_string(),
+        };
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+        let path = self
+            .dir
+            .join(format!("{}-{}.json", captured.received_at, sequence));
+
+        fs::write(&path, serde_json::to_vec_pretty(&captured)?)?;
+        Ok(path)
+    }
+}
+
+impl CapturedMessage {
+    /// Reads a capture written by [`MisbehaviorCapture::record`].
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    /// Decodes the captured line the way the network would have.
+    pub fn decode(&self) -> serde_json::Result<Message> {
+        serde_json::from_str(&self.message)
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+
+use rand::Rng;
+use serde::Serialize;
+
+/// Most changes remembered; a cursor older than the oldest of them has to resync.
+pub const MAX_CHANGES: usize = 10_000;
+
+/// What happened to a block of the active chain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Connected,
+    /// A reorg took the block off the active chain. Disconnections come tip first, before the
+    /// blocks of the new chain are connected.
+    Disconnected,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Change {
+    #[serde(rename = "type")]
+    pub kind: ChangeKind,
+    pub id: u64,
+    pub hash: String,
+}
+
+/// A position in a [`ChangeLog`]: the changes from `sequence` on are those not seen yet. The
+/// log of a restarted node starts a new `session`, so cursors handed out before the restart
+/// aren't mistaken for positions in the new one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cursor {
+    pub session: u64,
+    pub sequence: u64,
+}
+
+/// Why a [`Cursor`] can't be followed any further.
+#[derive(Debug, PartialEq, Eq)]
+pub enum CursorError {
+    /// The cursor is of another session of the log, or the changes after it were forgotten;
+    /// mirror the chain anew.
+    Expired,
+    /// The cursor points past the last change, so it was never handed out.
+    Unknown,
+}
+
+/// Every block connected to or disconnected from the active chain, in order, so mirrors of
+/// the chain can follow it from a [`Cursor`] instead of scanning it again. Only the last
+/// [`MAX_CHANGES`] are kept.
+pub struct ChangeLog {
+    session: u64,
+    changes: VecDeque<Change>,
+    /// Sequence number of the next change.
+    next: u64,
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}-{}", self.session, self.sequence)
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = String;
+
+    /// Parses a cursor as formatted, `<session>-<sequence>`.
+    fn from_str(cursor: &str) -> Result<Self, Self::Err> {
+        let malformed = || format!("malformed cursor {}", cursor);
+        let (session, sequence) = cursor.split_once('-').ok_or_else(malformed)?;
+        Ok(Self {
+            session: u64::from_str_radix(session, 16).map_err(|_| malformed())?,
+            sequence: sequence.parse().map_err(|_| malformed())?,
+        })
+    }
+}
+
+impl fmt::Display for CursorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Expired => write!(f, "the changes after the cursor are gone, resync"),
+            Self::Unknown => write!(f, "the cursor is ahead of the change log"),
+        }
+    }
+}
+
+impl Default for ChangeLog {
+    fn default() -> Self {
+        Self {
+            session: rand::thread_rng().gen(),
+            changes: VecDeque::new(),
+            next: 0,
+        }
+    }
+}
+
+impl ChangeLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, kind: ChangeKind, id: u64, hash: String) {
+        self.changes.push_back(Change { kind, id, hash });
+        self.next += 1;
+        if self.changes.len() > MAX_CHANGES {
+            self.changes.pop_front();
+        }
+    }
+
+    /// The cursor after the last change, to follow the chain from where it is now.
+    pub fn cursor(&self) -> Cursor {
+        Cursor {
+            session: self.session,
+            sequence: self.next,
+        }
+    }
+
+    /// At most `limit` changes after `cursor`, oldest first, and the cursor after them.
+    pub fn since(
+        &self,
+        cursor: Cursor,
+        limit: usize,
+    ) -> Result<(Vec<Change>, Cursor), CursorError> {
+        let oldest = self.next - self.changes.len() as u64;
+        if cursor.session != self.session || cursor.sequence < oldest {
+            return Err(CursorError::Expired);
+        }
+        if cursor.sequence > self.next {
+            return Err(CursorError::Unknown);
+        }
+
+        let changes: Vec<Change> = self
+            .changes
+            .iter()
+            .skip((cursor.sequence - oldest) as usize)
+            .take(limit)
+            .cloned()
+            .collect();
+        let next = Cursor {
+            session: self.session,
+            sequence: cursor.sequence + changes.len() as u64,
+        };
+        Ok((changes, next))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors;
+    use crate::Blockchain;
+
+    #[test]
+    fn change_feed_resumes_from_cursors_until_they_expire() {
+        let blocks = vectors::blocks();
+        let views = Blockchain::from_blocks(blocks[..2].to_vec())
+            .unwrap()
+            .views();
+        let end = views.cursor();
+        let start = Cursor { sequence: 0, ..end };
+        let (changes, next) = views.changes_since(start, 1).unwrap();
+        assert_eq!(changes[0].kind, ChangeKind::Connected);
+        assert_eq!(changes[0].hash, blocks[0].hash);
+        let (changes, next) = views.changes_since(next, 10).unwrap();
+        assert_eq!(changes[0].hash, blocks[1].hash);
+        assert_eq!(next, end);
+        assert_eq!(next.to_string().parse(), Ok(next));
+
+        let mut log = ChangeLog::new();
+        let first = log.cursor();
+        for id in 0..=MAX_CHANGES as u64 {
+            log.record(ChangeKind::Connected, id, id.to_string());
+        }
+        assert_eq!(log.since(first, 1), Err(CursorError::Expired));
+        assert_eq!(log.since(end, 1), Err(CursorError::Expired));
+        let ahead = Cursor {
+            sequence: log.cursor().sequence + 1,
+            ..log.cursor()
+        };
+        assert_eq!(log.since(ahead, 1), Err(CursorError::Unknown));
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::ledger::Ledger;
+
+/// How many blocks are connected between two checkpoints of a stored chain.
+pub const CHECKPOINT_INTERVAL: u64 = 100;
+
+/// State derived from the first `height` blocks of a chain, ending with `tip`, so a restarted
+/// node only has to replay the blocks stored after them. See [`crate::Blockchain::load`].
+#[derive(Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub height: u64,
+    pub tip: String,
+    /// Balances and nonces of every address.
+    #[serde(flatten)]
+    pub ledger: Ledger,
+    /// Id of the block each confirmed transaction is in, by transaction id.
+    pub transaction_index: HashMap<String, u64>,
+}
+
+impl Checkpoint {
+    /// The checkpoint stored in `path`, if there is one.
+    pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Option<Self>> {
+        match fs::read(path) {
+            Ok(checkpoint) => Ok(Some(serde_json::from_slice(&checkpoint)?)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Writes the checkpoint to a temporary file first, so a crash halfway through leaves the
+    /// previous one in place.
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let temporary = path.with_extension("json.tmp");
+        fs::write(&temporary, serde_json::to_vec(self)?)?;
+        fs::rename(temporary, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+    use crate::vectors;
+    use crate::Blockchain;
+
+    #[test]
+    fn stored_chains_restart_from_their_checkpoint() {
+        let mut blocks = vectors::blocks();
+        blocks.pop();
+        let miner = blocks[1].transactions[0].recipient.clone();
+        let balance = Blockchain::from_blocks(blocks.clone())
+            .unwrap()
+            .balance(&miner);
+        let dir = env::temp_dir().join(format!("blockchain-checkpoint-{}", process::id()));
+        let (storage_path, checkpoint_path) =
+            (dir.join("blocks.jsonl"), dir.join("checkpoint.json"));
+        let mut blockchain = Blockchain::load(&storage_path).unwrap();
+        blockchain.try_add_blocks(blocks.clone()).unwrap();
+        drop(blockchain);
+
+        // Balances the blocks don't lead to show the checkpoint was taken instead of replaying
+        let mut checkpoint = Checkpoint {
+            height: 1,
+            tip: blocks[0].hash.clone(),
+            ledger: Ledger {
+                balances: [(miner.clone(), 1000)].into(),
+                nonces: Default::default(),
+            },
+            transaction_index: Default::default(),
+        };
+        checkpoint.write(&checkpoint_path).unwrap();
+        let blockchain = Blockchain::load(&storage_path).unwrap();
+        assert_eq!(blockchain.balance(&miner), 1000 + balance);
+        assert_eq!(
+            blockchain.confirmations(&blocks[1].transactions[0].hash()),
+            1
+        );
+        drop(blockchain);
+
+        // A checkpoint of some other chain is ignored
+        checkpoint.tip = blocks[1].hash.clone();
+        checkpoint.write(&checkpoint_path).unwrap();
+        let blockchain = Blockchain::load(&storage_path).unwrap();
+        assert_eq!(blockchain.balance(&miner), balance);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,209 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::storage::BlockReader;
+use crate::transaction::Transaction;
+use crate::{Block, Blockchain, BlockchainError, ReplaceChainOutcome};
+
+/// An input from outside that the chain decided on.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "input", rename_all = "snake_case")]
+pub enum Input {
+    /// The node started on a stored chain of `height` blocks ending with `tip`. Everything
+    /// until the next `Start` happened on top of it.
+    Start { height: u64, tip: Option<String> },
+    /// See [`Blockchain::try_add_block`].
+    Block { block: Block },
+    /// See [`Blockchain::try_add_blocks`].
+    Blocks { blocks: Vec<Block> },
+    /// See [`Blockchain::replace_chain`]; only the blocks of the candidate from the first one
+    /// differing from ours on are kept, `fork` being its id.
+    ReplaceChain { fork: u64, blocks: Vec<Block> },
+    /// See [`Blockchain::submit_transaction`].
+    Transaction { transaction: Transaction },
+    /// See [`Blockchain::submit_package`].
+    Package { transactions: Vec<Transaction> },
+    /// Transactions the expiry timer evicted from the mempool, see
+    /// [`Blockchain::expire_transactions`].
+    Expired { transactions: Vec<Transaction> },
+}
+
+/// An entry of the decision log.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Decision {
+    /// Time of the chain when the input came in, see [`Blockchain::adjusted_time`].
+    pub at: i64,
+    #[serde(flatten)]
+    pub input: Input,
+    /// What the chain made of the input, see [`outcome`].
+    pub outcome: String,
+}
+
+/// Append-only log of every [`Decision`] of a chain, one JSON object per line, see
+/// [`Blockchain::set_decision_log`]. It holds what's needed to [`replay`] them offline.
+pub struct DecisionLog {
+    path: PathBuf,
+    file: File,
+}
+
+impl DecisionLog {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+
+        Ok(Self { path, file })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends `decision` with a single write, so a crash can at worst cut off the last line.
+    pub fn append(&mut self, decision: &Decision) -> io::Result<()> {
+        let mut line = serde_json::to_vec(decision)?;
+        line.push(b'\n');
+        self.file.write_all(&line)
+    }
+}
+
+/// The decisions in the log at `path`, in order, up to a line a crash cut off.
+pub fn read(path: impl AsRef<Path>) -> io::Result<Vec<Decision>> {
+    let mut decisions = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        match serde_json::from_str(&line?) {
+            Ok(decision) => decisions.push(decision),
+            Err(_) => break,
+        }
+    }
+    Ok(decisions)
+}
+
+/// How a decision is summed up in the log, so a replay can tell whether it came to the same.
+pub fn outcome<T>(result: &Result<T, BlockchainError>, describe: impl Fn(&T) -> String) -> String {
+    match result {
+        Ok(value) => describe(value),
+        Err(err) => format!("refused: {}", err),
+    }
+}
+
+/// Summary of what [`Blockchain::replace_chain`] did.
+pub fn describe_replacement(outcome: &ReplaceChainOutcome) -> String {
+    match outcome {
+        ReplaceChainOutcome::Replaced {
+            fork_id,
+            disconnected,
+            connected,
+        } => format!(
+            "replaced {} block(s) from #{} with {}",
+            disconnected, fork_id, connected
+        ),
+        ReplaceChainOutcome::NotHeavier => String::from("not heavier"),
+    }
+}
+
+/// Feeds every decision in the log at `log_path` through the consensus code again, on top of
+/// the blocks stored at `storage_path` each session started from, with the clock set back to
+/// when the decision was taken. `on_decision` is handed every recorded decision and the outcome
+/// of its replay; where they differ, the node decided differently than its code does now.
+///
+/// Fails if the stored chain no longer starts with the chain a session started from, e.g.
+/// after a reorg deeper than that.
+pub fn replay(
+    log_path: impl AsRef<Path>,
+    storage_path: impl AsRef<Path>,
+    mut on_decision: impl FnMut(&Decision, &str),
+) -> Result<(), BlockchainError> {
+    let mut blockchain = Blockchain::new();
+
+    for line in BufReader::new(File::open(log_path)?).lines() {
+        let line = line?;
+        // A crash may have cut off the last line
+        let Ok(decision) = serde_json::from_str::<Decision>(&line) else {
+            break;
+        };
+        blockchain.set_time_offset(decision.at - Utc::now().timestamp());
+
+        let replayed = match &decision.input {
+            Input::Start { height, tip } => {
+                let blocks = BlockReader::blocks(&storage_path)?
+                    .take(*height as usize)
+                    .collect::<Result<Vec<_>, _>>()?;
+                blockchain = Blockchain::from_blocks(blocks)?;
+                blockchain.set_time_offset(decision.at - Utc::now().timestamp());
+                if blockchain.tip().map(|tip| &tip.hash) != tip.as_ref() {
+                    return Err(BlockchainError::DecisionLogMismatch { height: *height });
+                }
+                decision.outcome.clone()
+            }
+            Input::Block { block } => {
+                let result = blockchain.try_add_block(block.clone());
+                outcome(&result, |()| String::from("connected"))
+            }
+            Input::Blocks { blocks } => {
+                let result = blockchain.try_add_blocks(blocks.clone());
+                outcome(&result, |()| String::from("connected"))
+            }
+            Input::ReplaceChain { fork, blocks } => {
+                let fork = (*fork as usize).min(blockchain.blocks().len());
+                let mut candidate = blockchain.blocks()[..fork].to_vec();
+                candidate.extend(blocks.iter().cloned());
+                let result = blockchain.replace_chain(candidate);
+                outcome(&result, describe_replacement)
+            }
+            Input::Transaction { transaction } => {
+                let result = blockchain.submit_transaction(transaction.clone());
+                outcome(&result, |()| String::from("queued"))
+            }
+            Input::Package { transactions } => {
+                let result = blockchain.submit_package(transactions.clone());
+                outcome(&result, |()| String::from("queued"))
+            }
+            Input::Expired { transactions } => {
+                blockchain.evict(transactions);
+                decision.outcome.clone()
+            }
+        };
+        on_decision(&decision, &replayed);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+    use crate::vectors;
+
+    #[test]
+    fn decision_logs_replay_to_the_same_outcomes() {
+        let blocks = vectors::blocks();
+        let dir = env::temp_dir().join(format!("blockchain-decisions-{}", process::id()));
+        let (storage_path, log_path) = (dir.join("blocks.jsonl"), dir.join("decisions.jsonl"));
+        let mut blockchain = Blockchain::load(&storage_path).unwrap();
+        blockchain.set_decision_log(DecisionLog::open(&log_path).unwrap());
+        for block in blocks {
+            let _ = blockchain.try_add_block(block);
+        }
+        drop(blockchain);
+
+        let mut outcomes = Vec::new();
+        replay(&log_path, &storage_path, |decision, replayed| {
+            assert_eq!(decision.outcome, replayed);
+            outcomes.push(replayed.to_string());
+        })
+        .unwrap();
+        assert_eq!(outcomes.len(), 4);
+        assert_eq!(outcomes[1], "connected");
+        assert!(outcomes[3].starts_with("refused"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
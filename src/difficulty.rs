@@ -0,0 +1,188 @@
+use serde::{Deserialize, Serialize};
+
+use crate::block::{Block, BlockHeader};
+
+/// Difficulty of the genesis block, roughly the number of hashes needed to mine a block. It
+/// matches the old fixed prefix of five zero hex digits.
+pub const INITIAL_DIFFICULTY: u64 = 1 << 20;
+/// Block interval the retargeting aims for.
+pub const TARGET_BLOCK_TIME_SECS: i64 = 10;
+/// Number of blocks between two retargets.
+pub const RETARGET_INTERVAL: u64 = 10;
+/// Bound on how much a single retarget may change the difficulty, in either direction.
+const MAX_ADJUSTMENT_FACTOR: u64 = 4;
+
+/// Whether a hex-encoded hash satisfies `difficulty`: its first 64 bits, read as a number,
+/// must not exceed `u64::MAX / difficulty`.
+pub fn meets(hash: &str, difficulty: u64) -> bool {
+    hash.get(..16)
+        .and_then(|prefix| u64::from_str_radix(prefix, 16).ok())
+        .is_some_and(|value| value <= u64::MAX / difficulty.max(1))
+}
+
+/// A retarget interval of the chain: [`RETARGET_INTERVAL`] blocks mined at the same
+/// difficulty.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Epoch {
+    /// Id of the first block of the epoch, a multiple of [`RETARGET_INTERVAL`].
+    pub start: u64,
+    pub difficulty: u64,
+    /// Difficulty of the previous epoch, `None` for the first one.
+    pub retargeted_from: Option<u64>,
+    /// Timestamp of the first block of the epoch.
+    pub started_at: i64,
+}
+
+/// Brings `epochs`, the epochs of `blocks` before the blocks from `from` on changed, up to date
+/// with `blocks`.
+pub fn sync_epochs(epochs: &mut Vec<Epoch>, blocks: &[Block], from: usize) {
+    epochs.retain(|epoch| epoch.start < from as u64);
+
+    let next_start = epochs.len() * RETARGET_INTERVAL as usize;
+    for block in blocks
+        .iter()
+        .skip(next_start)
+        .step_by(RETARGET_INTERVAL as usize)
+    {
+        epochs.push(Epoch {
+            start: block.id,
+            difficulty: block.difficulty,
+            retargeted_from: epochs.last().map(|epoch| epoch.difficulty),
+            started_at: block.timestamp,
+        });
+    }
+}
+
+/// Difficulty the chain demands for the block following `blocks`.
+///
+/// It stays the same within a retarget interval. At every interval boundary it is scaled by
+/// how far the blocks of the last interval were from `TARGET_BLOCK_TIME_SECS` apart.
+pub fn next(blocks: &[Block]) -> u64 {
+    let Some(last_block) = blocks.last() else {
+        return INITIAL_DIFFICULTY;
+    };
+    let first_block = &blocks[blocks.len().saturating_sub(RETARGET_INTERVAL as usize)];
+
+    retarget(
+        last_block.id,
+        last_block.difficulty,
+        first_block.timestamp,
+        last_block.timestamp,
+    )
+}
+
+/// Same as [`next`] for a chain of headers.
+pub fn next_for_headers(headers: &[BlockHeader]) -> u64 {
+    let Some(last_header) = headers.last() else {
+        return INITIAL_DIFFICULTY;
+    };
+    let first_header = &headers[headers.len().saturating_sub(RETARGET_INTERVAL as usize)];
+
+    retarget(
+        last_header.id,
+        last_header.difficulty,
+        first_header.timestamp,
+        last_header.timestamp,
+    )
+}
+
+/// Difficulty following the block `last_id` mined at `last_difficulty`, given the timestamps
+/// of the first and last block of the interval it ends.
+fn retarget(last_id: u64, last_difficulty: u64, first_timestamp: i64, last_timestamp: i64) -> u64 {
+    if !(last_id + 1).is_multiple_of(RETARGET_INTERVAL) {
+        return last_difficulty;
+    }
+
+    let expected_secs = (RETARGET_INTERVAL as i64 - 1) * TARGET_BLOCK_TIME_SECS;
+    let actual_secs = (last_timestamp - first_timestamp).max(1);
+
+    let retargeted = last_difficulty as u128 * expected_secs as u128 / actual_secs as u128;
+    let min = (last_difficulty / MAX_ADJUSTMENT_FACTOR).max(1) as u128;
+    let max = last_difficulty.saturating_mul(MAX_ADJUSTMENT_FACTOR) as u128;
+
+    retargeted.clamp(min, max) as u64
+}
+
+/// Total work behind a chain. Since a block's difficulty is the expected number of hashes needed
+/// to mine it, the work is simply the sum of the difficulties (saturating, which is far beyond
+/// anything reachable).
+pub fn work(blocks: &[Block]) -> u64 {
+    blocks
+        .iter()
+        .fold(0, |work, block| work.saturating_add(block.difficulty))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A chain of `len` blocks mined at `difficulty`, `interval` seconds apart.
+    fn chain(len: u64, difficulty: u64, interval: i64) -> Vec<Block> {
+        (0..len)
+            .map(|id| Block {
+                id,
+                hash: format!("{:064x}", id),
+                previous_hash: String::new(),
+                timestamp: id as i64 * interval,
+                difficulty,
+                merkle_root: String::new(),
+                transactions: Vec::new(),
+                nonce: 0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn difficulty_is_retargeted_at_every_interval_only() {
+        assert_eq!(next(&[]), INITIAL_DIFFICULTY);
+        // Blocks twice as far apart as targeted halve the difficulty, but only at the boundary
+        let slow = chain(RETARGET_INTERVAL, 1_000, 2 * TARGET_BLOCK_TIME_SECS);
+        assert_eq!(next(&slow[..RETARGET_INTERVAL as usize - 1]), 1_000);
+        assert_eq!(next(&slow), 500);
+        let on_target = chain(RETARGET_INTERVAL, 1_000, TARGET_BLOCK_TIME_SECS);
+        assert_eq!(next(&on_target), 1_000);
+
+        let headers: Vec<BlockHeader> = slow.iter().map(Block::header).collect();
+        assert_eq!(next_for_headers(&headers), next(&slow));
+    }
+
+    #[test]
+    fn retargets_change_the_difficulty_by_four_times_at_most() {
+        let last_id = RETARGET_INTERVAL - 1;
+        assert_eq!(retarget(last_id, 1_000, 0, 0), 4_000);
+        assert_eq!(retarget(last_id, 1_000, 10, 0), 4_000);
+        assert_eq!(retarget(last_id, 1_000, 0, 1_000_000), 250);
+        assert_eq!(retarget(last_id, u64::MAX, 0, 0), u64::MAX);
+        assert_eq!(retarget(last_id, 2, 0, 1_000_000), 1);
+    }
+
+    #[test]
+    fn work_sums_the_difficulties() {
+        assert_eq!(work(&[]), 0);
+        assert_eq!(work(&chain(3, 1_000, 1)), 3_000);
+        assert_eq!(work(&chain(2, u64::MAX, 1)), u64::MAX);
+    }
+
+    #[test]
+    fn epochs_follow_the_chain_from_where_it_changed() {
+        let mut blocks = chain(2 * RETARGET_INTERVAL + 1, 1_000, 1);
+        let mut epochs = Vec::new();
+        sync_epochs(&mut epochs, &blocks, 0);
+        assert_eq!(
+            epochs.iter().map(|epoch| epoch.start).collect::<Vec<_>>(),
+            [0, RETARGET_INTERVAL, 2 * RETARGET_INTERVAL]
+        );
+        assert_eq!(epochs[0].retargeted_from, None);
+        assert_eq!(epochs[1].retargeted_from, Some(1_000));
+
+        // A reorg to a shorter chain, retargeted differently, rebuilds the epochs it changed
+        blocks.truncate(RETARGET_INTERVAL as usize + 5);
+        for block in &mut blocks[RETARGET_INTERVAL as usize..] {
+            block.difficulty = 250;
+        }
+        sync_epochs(&mut epochs, &blocks, RETARGET_INTERVAL as usize);
+        assert_eq!(epochs.len(), 2);
+        assert_eq!(epochs[1].difficulty, 250);
+        assert_eq!(epochs[1].retargeted_from, Some(1_000));
+    }
+}
@@ -0,0 +1,503 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::hashing::{self, ADDRESS_STATUS_TAG, ADDRESS_TAG};
+use crate::mempool::Mempool;
+use crate::network::Network;
+use crate::transaction::Transaction;
+use crate::view::ChainView;
+use crate::{Block, ChainHandle};
+
+/// Version of the Electrum protocol whose method names and semantics the server follows.
+pub const PROTOCOL_VERSION: &str = "1.4";
+/// Most headers a single `blockchain.block.headers` call returns.
+pub const MAX_HEADERS: u64 = 2016;
+/// How often subscribed clients are checked for a new tip or address status to be notified of.
+const NOTIFY_INTERVAL: Duration = Duration::from_millis(500);
+
+/// JSON-RPC error codes, those of the spec and the one Electrum servers use for requests the
+/// chain refused.
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const REFUSED: i64 = 1;
+
+/// What clients subscribe to and query addresses by, the counterpart of Electrum's script
+/// hash: a hash of the bare address, so servers never need to be told the address itself.
+pub fn address_hash(address: &str) -> String {
+    hashing::tagged_hash(ADDRESS_TAG, address)
+}
+
+/// A transaction involving an address, at the id of the block confirming it, or 0 while it's
+/// pending, as Electrum reports it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct HistoryItem {
+    pub tx_hash: String,
+    pub height: u64,
+}
+
+/// The transactions of the active chain by the [`address_hash`] of the addresses they
+/// involve, kept up to date with the [`ChainView`]s it's given.
+#[derive(Default)]
+pub struct AddressIndex {
+    /// Hashes of the indexed blocks, to find where the chain changed after a reorg.
+    indexed: Vec<String>,
+    history: HashMap<String, Vec<HistoryItem>>,
+    /// The address behind each hash with a history.
+    addresses: HashMap<String, String>,
+}
+
+/// A request of a client, one JSON-RPC object per line.
+#[derive(Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Vec<Value>,
+}
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+/// What one client is subscribed to.
+#[derive(Default)]
+struct Subscriptions {
+    /// Tip last reported to a `blockchain.headers.subscribe` client.
+    tip: Option<String>,
+    headers: bool,
+    /// Status last reported for every subscribed address hash.
+    addresses: HashMap<String, Option<String>>,
+}
+
+impl AddressIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Indexes the blocks of `view` not indexed yet, first forgetting those a reorg
+    /// disconnected.
+    pub fn update(&mut self, view: &ChainView) {
+        if self.indexed.len() as u64 == view.height()
+            && self.indexed.last() == view.tip().map(|tip| &tip.hash)
+        {
+            return;
+        }
+
+        let common = self
+            .indexed
+            .iter()
+            .zip(view.blocks())
+            .take_while(|(hash, block)| **hash == block.hash)
+            .count();
+        if common < self.indexed.len() {
+            self.indexed.truncate(common);
+            self.history.retain(|_, history| {
+                history.retain(|item| item.height < common as u64);
+                !history.is_empty()
+            });
+            self.addresses
+                .retain(|hash, _| self.history.contains_key(hash));
+        }
+
+        for block in view.blocks_from(common as u64) {
+            self.index_block(block);
+        }
+    }
+
+    fn index_block(&mut self, block: &Block) {
+        for transaction in &block.transactions {
+            let tx_hash = transaction.hash();
+            for address in involved(transaction) {
+                let hash = address_hash(address);
+                self.addresses
+                    .entry(hash.clone())
+                    .or_insert_with(|| address.to_string());
+                self.history.entry(hash).or_default().push(HistoryItem {
+                    tx_hash: tx_hash.clone(),
+                    height: block.id,
+                });
+            }
+        }
+        self.indexed.push(block.hash.clone());
+    }
+
+    /// The address behind `hash`, if any transaction of the chain involves it.
+    pub fn address(&self, hash: &str) -> Option<&str> {
+        self.addresses.get(hash).map(String::as_str)
+    }
+
+    /// The confirmed transactions involving the address hashed to `hash`, oldest first,
+    /// followed by the pending ones of `mempool`.
+    pub fn history(&self, hash: &str, mempool: &Mempool) -> Vec<HistoryItem> {
+        let mut history = self.history.get(hash).cloned().unwrap_or_default();
+        history.extend(
+            mempool
+                .iter()
+                .filter(|transaction| {
+                    involved(transaction).any(|address| address_hash(address) == hash)
+                })
+                .map(|transaction| HistoryItem {
+                    tx_hash: transaction.hash(),
+                    height: 0,
+                }),
+        );
+        history
+    }
+
+    /// A hash of the [`AddressIndex::history`] of `hash` that changes whenever it does, `None`
+    /// while it's empty, so clients only fetch the history once it changed.
+    pub fn status(&self, hash: &str, mempool: &Mempool) -> Option<String> {
+        let history = self.history(hash, mempool);
+        if history.is_empty() {
+            return None;
+        }
+        let preimage: String = history
+            .iter()
+            .map(|item| format!("{}:{}:", item.tx_hash, item.height))
+            .collect();
+        Some(hashing::tagged_hash(ADDRESS_STATUS_TAG, preimage))
+    }
+}
+
+/// The addresses `transaction` pays or is paid by, not counting the coinbase sender.
+fn involved(transaction: &Transaction) -> impl Iterator<Item = &str> {
+    let sender = (!transaction.is_coinbase()).then_some(transaction.sender.as_str());
+    sender
+        .into_iter()
+        .chain(std::iter::once(transaction.recipient.as_str()))
+}
+
+impl RpcError {
+    fn invalid_params(message: impl Into<String>) -> Self {
+        Self {
+            code: INVALID_PARAMS,
+            message: message.into(),
+        }
+    }
+}
+
+/// Serves light wallets an Electrum-style protocol on `addr`: newline-delimited JSON-RPC over
+/// TCP with address subscriptions by [`address_hash`], address histories and balances, block
+/// headers and transaction lookup and broadcast. Methods are named after their Electrum
+/// counterparts, with `address` where Electrum has `scripthash`:
+///
+/// - `server.version`, `server.ping`
+/// - `blockchain.headers.subscribe`, notifying of every new tip
+/// - `blockchain.block.header [height]`, `blockchain.block.headers [start, count]`
+/// - `blockchain.address.subscribe [hash]`, notifying `[hash, status]` as the status changes
+/// - `blockchain.address.unsubscribe [hash]`
+/// - `blockchain.address.get_history [hash]`, `blockchain.address.get_balance [hash]`
+/// - `blockchain.transaction.get [tx_hash]`, `blockchain.transaction.broadcast [transaction]`
+///
+/// Headers and transactions are the JSON objects of the HTTP API rather than raw bytes.
+pub async fn serve(
+    addr: SocketAddr,
+    blockchain: ChainHandle,
+    network: Arc<Network>,
+) -> io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("Serving light wallets on {}", addr);
+    let index = Arc::new(Mutex::new(AddressIndex::new()));
+
+    loop {
+        let (stream, client) = listener.accept().await?;
+        debug!("Light wallet {} connected", client);
+        tokio::spawn(serve_client(
+            stream,
+            blockchain.clone(),
+            network.clone(),
+            index.clone(),
+        ));
+    }
+}
+
+/// Answers the requests of one client and notifies it of what it subscribed to, until it goes
+/// away.
+async fn serve_client(
+    stream: TcpStream,
+    blockchain: ChainHandle,
+    network: Arc<Network>,
+    index: Arc<Mutex<AddressIndex>>,
+) {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    let mut subscriptions = Subscriptions::default();
+    let mut interval = tokio::time::interval(NOTIFY_INTERVAL);
+    let views = blockchain.views();
+
+    loop {
+        let replies = tokio::select! {
+            line = lines.next_line() => match line {
+                Ok(Some(line)) => {
+                    vec![answer(&line, &blockchain, &network, &index, &mut subscriptions)]
+                }
+                Ok(None) => return,
+                Err(err) => {
+                    warn!("Failed to read from a light wallet: {}", err);
+                    return;
+                }
+            },
+            _ = interval.tick() => {
+                let view = views.latest();
+                let mut index = index.lock().unwrap();
+                index.update(&view);
+                notifications(&view, &index, blockchain.read().mempool(), &mut subscriptions)
+            }
+        };
+
+        for reply in replies {
+            let mut line = reply.to_string();
+            line.push('\n');
+            if writer.write_all(line.as_bytes()).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// The response to the request on `line`.
+fn answer(
+    line: &str,
+    blockchain: &ChainHandle,
+    network: &Network,
+    index: &Mutex<AddressIndex>,
+    subscriptions: &mut Subscriptions,
+) -> Value {
+    let request: Request = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(err) => {
+            return json!({
+                "jsonrpc": "2.0",
+                "id": null,
+                "error": { "code": -32700, "message": format!("malformed request: {}", err) },
+            })
+        }
+    };
+
+    match call(&request, blockchain, network, index, subscriptions) {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": request.id, "result": result }),
+        Err(err) => json!({
+            "jsonrpc": "2.0",
+            "id": request.id,
+            "error": { "code": err.code, "message": err.message },
+        }),
+    }
+}
+
+fn call(
+    request: &Request,
+    blockchain: &ChainHandle,
+    network: &Network,
+    index: &Mutex<AddressIndex>,
+    subscriptions: &mut Subscriptions,
+) -> Result<Value, RpcError> {
+    let view = blockchain.views().latest();
+    let param = |position: usize| {
+        request
+            .params
+            .get(position)
+            .ok_or_else(|| RpcError::invalid_params(format!("missing parameter #{}", position)))
+    };
+    let string_param = |position: usize| {
+        param(position)?.as_str().map(String::from).ok_or_else(|| {
+            RpcError::invalid_params(format!("parameter #{} isn't a string", position))
+        })
+    };
+    let number_param = |position: usize| {
+        param(position)?.as_u64().ok_or_else(|| {
+            RpcError::invalid_params(format!("parameter #{} isn't a number", position))
+        })
+    };
+
+    match request.method.as_str() {
+        "server.version" => Ok(json!([
+            format!("blockchain {}", env!("CARGO_PKG_VERSION")),
+            PROTOCOL_VERSION
+        ])),
+        "server.ping" => Ok(Value::Null),
+        "blockchain.headers.subscribe" => {
+            subscriptions.headers = true;
+            subscriptions.tip = view.tip().map(|tip| tip.hash.clone());
+            Ok(tip_notification(&view))
+        }
+        "blockchain.block.header" => {
+            let height = number_param(0)?;
+            view.block_at(height)
+                .map(|block| json!(block.header()))
+                .ok_or_else(|| RpcError::invalid_params(format!("no block at height {}", height)))
+        }
+        "blockchain.block.headers" => {
+            let start = number_param(0)?;
+            let count = number_param(1)?.min(MAX_HEADERS) as usize;
+            let headers: Vec<_> = view
+                .blocks_from(start)
+                .take(count)
+                .map(Block::header)
+                .collect();
+            Ok(json!({ "count": headers.len(), "headers": headers, "max": MAX_HEADERS }))
+        }
+        "blockchain.address.subscribe" => {
+            let hash = string_param(0)?;
+            let mut index = index.lock().unwrap();
+            index.update(&view);
+            let status = index.status(&hash, blockchain.read().mempool());
+            subscriptions.addresses.insert(hash, status.clone());
+            Ok(json!(status))
+        }
+        "blockchain.address.unsubscribe" => {
+            let hash = string_param(0)?;
+            Ok(json!(subscriptions.addresses.remove(&hash).is_some()))
+        }
+        "blockchain.address.get_history" => {
+            let hash = string_param(0)?;
+            let mut index = index.lock().unwrap();
+            index.update(&view);
+            Ok(json!(index.history(&hash, blockchain.read().mempool())))
+        }
+        "blockchain.address.get_balance" => {
+            let hash = string_param(0)?;
+            let mut index = index.lock().unwrap();
+            index.update(&view);
+            let blockchain = blockchain.read();
+            let (confirmed, unconfirmed) = index.address(&hash).map_or((0, 0), |address| {
+                let pending: i64 = blockchain
+                    .mempool()
+                    .iter()
+                    .map(|transaction| {
+                        let mut delta = 0;
+                        if transaction.recipient == address {
+                            delta += transaction.amount as i64;
+                        }
+                        if transaction.sender == address {
+                            delta -= transaction.amount as i64;
+                        }
+                        delta
+                    })
+                    .sum();
+                (blockchain.balance(address), pending)
+            });
+            Ok(json!({ "confirmed": confirmed, "unconfirmed": unconfirmed }))
+        }
+        "blockchain.transaction.get" => {
+            let tx_hash = string_param(0)?;
+            if let Some((_, transaction)) = view.transaction(&tx_hash) {
+                return Ok(json!(transaction));
+            }
+            blockchain
+                .read()
+                .mempool()
+                .iter()
+                .find(|transaction| transaction.hash() == tx_hash)
+                .map(|transaction| json!(transaction))
+                .ok_or_else(|| RpcError::invalid_params(format!("no transaction {}", tx_hash)))
+        }
+        "blockchain.transaction.broadcast" => {
+            let transaction: Transaction =
+                serde_json::from_value(param(0)?.clone()).map_err(|err| {
+                    RpcError::invalid_params(format!("malformed transaction: {}", err))
+                })?;
+            let tx_hash = transaction.hash();
+            blockchain
+                .submit_transaction(transaction.clone())
+                .map_err(|err| RpcError {
+                    code: REFUSED,
+                    message: err.to_string(),
+                })?;
+            network.announce_local(vec![transaction]);
+            Ok(json!(tx_hash))
+        }
+        method => Err(RpcError {
+            code: METHOD_NOT_FOUND,
+            message: format!("unknown method {}", method),
+        }),
+    }
+}
+
+/// The height and header of the tip, as `blockchain.headers.subscribe` reports it.
+fn tip_notification(view: &ChainView) -> Value {
+    match view.tip() {
+        Some(tip) => json!({ "height": tip.id, "header": tip.header() }),
+        None => Value::Null,
+    }
+}
+
+/// Notifications of what changed for `subscriptions` since they were last notified.
+fn notifications(
+    view: &ChainView,
+    index: &AddressIndex,
+    mempool: &Mempool,
+    subscriptions: &mut Subscriptions,
+) -> Vec<Value> {
+    let mut notifications = Vec::new();
+
+    let tip = view.tip().map(|tip| tip.hash.clone());
+    if subscriptions.headers && tip != subscriptions.tip {
+        subscriptions.tip = tip;
+        notifications.push(json!({
+            "jsonrpc": "2.0",
+            "method": "blockchain.headers.subscribe",
+            "params": [tip_notification(view)],
+        }));
+    }
+
+    for (hash, reported) in &mut subscriptions.addresses {
+        let status = index.status(hash, mempool);
+        if status != *reported {
+            notifications.push(json!({
+                "jsonrpc": "2.0",
+                "method": "blockchain.address.subscribe",
+                "params": [hash, status],
+            }));
+            *reported = status;
+        }
+    }
+    notifications
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors;
+    use crate::Blockchain;
+
+    #[test]
+    fn address_index_follows_the_chain_across_reorgs() {
+        let blocks = vectors::blocks();
+        let payee = blocks[1].transactions[1].recipient.clone();
+        let hash = address_hash(&payee);
+        let mempool = Mempool::new();
+        let mut index = AddressIndex::new();
+
+        let genesis = Blockchain::from_blocks(blocks[..1].to_vec()).unwrap();
+        index.update(&genesis.views().latest());
+        assert_eq!(index.status(&hash, &mempool), None);
+
+        let chain = Blockchain::from_blocks(blocks[..2].to_vec()).unwrap();
+        index.update(&chain.views().latest());
+        assert_eq!(
+            index.history(&hash, &mempool),
+            vec![HistoryItem {
+                tx_hash: blocks[1].transactions[1].hash(),
+                height: 1,
+            }]
+        );
+        assert_eq!(index.address(&hash), Some(payee.as_str()));
+        assert!(index.status(&hash, &mempool).is_some());
+
+        // Back to the genesis block alone, as after a reorg to a chain without the payment
+        index.update(&genesis.views().latest());
+        assert!(index.history(&hash, &mempool).is_empty());
+        assert_eq!(index.address(&hash), None);
+    }
+}
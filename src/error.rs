@@ -0,0 +1,324 @@
+use std::{error, fmt, io};
+
+/// Why the blockchain refused a block, a chain or a transaction, or failed to store it.
+#[derive(Debug)]
+pub enum BlockchainError {
+    /// Reading or writing the block storage failed.
+    Io(io::Error),
+    /// A chain snapshot couldn't be encoded or decoded.
+    Encoding(String),
+    /// A [`crate::fixtures::FixtureSpec`] asks for chains that can't be generated.
+    InvalidFixture(String),
+    /// The operation needs at least a genesis block.
+    EmptyChain,
+    /// The genesis block doesn't match what the chain expects of it.
+    InvalidGenesis,
+    /// The block doesn't come right after the tip.
+    UnexpectedId { expected: u64, found: u64 },
+    /// The block claims a different difficulty than the chain demands at its height.
+    UnexpectedDifficulty { id: u64, expected: u64, found: u64 },
+    /// The block hash doesn't satisfy the difficulty of the block.
+    InsufficientProofOfWork { id: u64 },
+    /// The block doesn't point at the hash of its predecessor.
+    PreviousHashMismatch { id: u64 },
+    /// The stored hash of the block isn't the hash of its header.
+    HashMismatch { id: u64 },
+    /// The Merkle root in the header doesn't commit to the transactions of the block.
+    MerkleRootMismatch { id: u64 },
+    /// The block is timestamped before its predecessor.
+    TimestampBeforePrevious {
+        id: u64,
+        timestamp: i64,
+        previous: i64,
+    },
+    /// The block is timestamped too far ahead of our clock.
+    TimestampInFuture { id: u64, timestamp: i64 },
+    /// The block holds more transactions than a block may.
+    TooManyTransactions { id: u64, count: usize },
+    /// A coinbase transaction is misplaced, doesn't carry the height of its block as nonce or
+    /// carries too long a miner tag, or the coinbase transactions pay the wrong reward.
+    MalformedCoinbase { id: u64, hash: String },
+    /// The block includes the same transaction more than once.
+    DuplicateTransaction { id: u64, hash: String },
+    /// The transaction is malformed or its signature doesn't verify.
+    InvalidTransaction { hash: String },
+    /// Coinbase transactions can only be created by miners, in their own blocks.
+    CoinbaseSubmitted { hash: String },
+    /// The transaction is already waiting in the mempool.
+    AlreadyPending { hash: String },
+    /// The transaction is already confirmed by a block of the active chain.
+    AlreadyConfirmed { hash: String },
+    /// The transaction doesn't carry the nonce its sender has to use next, see
+    /// [`crate::transaction::Transaction::nonce`]. A pending transaction may also take the
+    /// nonce of the last one its sender has pending, see [`BlockchainError::NonceInUse`].
+    UnexpectedNonce {
+        hash: String,
+        expected: u64,
+        found: u64,
+    },
+    /// Another pending transaction of the sender carries the same nonce and can't be replaced,
+    /// since it isn't the sender's last one or others depend on it.
+    NonceInUse {
+        hash: String,
+        nonce: u64,
+        pending: String,
+    },
+    /// Queuing the transaction would make its chain of pending ancestors too long or too
+    /// large, see [`crate::mempool::MAX_ANCESTORS`].
+    TooManyAncestors {
+        hash: String,
+        count: usize,
+        size: usize,
+    },
+    /// Queuing the transaction would give the pending `ancestor` too many or too large
+    /// descendants, see [`crate::mempool::MAX_DESCENDANTS`].
+    TooManyDescendants { hash: String, ancestor: String },
+    /// A package must hold at least one and at most
+    /// [`crate::blockchain::MAX_PACKAGE_TRANSACTIONS`] transactions.
+    InvalidPackageSize { count: usize },
+    /// No header with that hash is known to the relay.
+    UnknownBlock { hash: String },
+    /// The block including the transaction isn't buried deep enough yet.
+    NotEnoughConfirmations {
+        hash: String,
+        confirmations: u64,
+        required: u64,
+    },
+    /// The Merkle proof doesn't show the transaction is included in the block.
+    InvalidInclusionProof { hash: String },
+    /// No work with that id was handed out recently.
+    UnknownWork { work_id: u64 },
+    /// The sender can't afford the transaction.
+    InsufficientFunds {
+        hash: String,
+        amount: u64,
+        available: u64,
+    },
+    /// Crediting the transaction would overflow the balance of its recipient.
+    BalanceOverflow { hash: String },
+    /// The miner tag is longer than [`crate::transaction::MAX_MINER_TAG_LEN`] bytes.
+    MinerTagTooLong { tag: String },
+    /// A mempool policy of ours keeps the transaction out, see [`crate::policy::MempoolPolicy`].
+    PolicyRejected {
+        hash: String,
+        policy: String,
+        reason: String,
+    },
+    /// The shares of a [`crate::reward::RewardSplit`] add up to more than the whole reward.
+    InvalidRewardSplit { percent: u64 },
+    /// The address isn't a public key prefixed with a network, see
+    /// [`crate::wallet::encode_address`].
+    MalformedAddress { address: String },
+    /// The address belongs to another network than the wallet paying it.
+    WrongNetwork { address: String, expected: String },
+    /// The stored chain no longer starts with the `height` blocks a session of the decision log
+    /// started from.
+    DecisionLogMismatch { height: u64 },
+    /// The block doesn't pay a share of the reward split we follow its cut.
+    RewardShareUnpaid {
+        id: u64,
+        role: String,
+        owed: u64,
+        paid: u64,
+    },
+}
+
+impl fmt::Display for BlockchainError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "block storage failed: {}", err),
+            Self::Encoding(reason) => write!(f, "malformed chain snapshot: {}", reason),
+            Self::InvalidFixture(reason) => write!(f, "invalid fixture: {}", reason),
+            Self::EmptyChain => write!(f, "the blockchain has no genesis block yet"),
+            Self::InvalidGenesis => write!(f, "genesis block is invalid"),
+            Self::UnexpectedId { expected, found } => {
+                write!(f, "expected block #{} but got block #{}", expected, found)
+            }
+            Self::UnexpectedDifficulty {
+                id,
+                expected,
+                found,
+            } => write!(
+                f,
+                "block #{} has difficulty {} instead of {}",
+                id, found, expected
+            ),
+            Self::InsufficientProofOfWork { id } => {
+                write!(f, "hash of block #{} doesn't meet its difficulty", id)
+            }
+            Self::PreviousHashMismatch { id } => write!(
+                f,
+                "block #{} doesn't point at the hash of its predecessor",
+                id
+            ),
+            Self::HashMismatch { id } => {
+                write!(f, "hash of block #{} doesn't match its header", id)
+            }
+            Self::MerkleRootMismatch { id } => write!(
+                f,
+                "merkle root of block #{} doesn't match its transactions",
+                id
+            ),
+            Self::TimestampBeforePrevious {
+                id,
+                timestamp,
+                previous,
+            } => write!(
+                f,
+                "block #{} is timestamped before its predecessor ({} < {})",
+                id, timestamp, previous
+            ),
+            Self::TimestampInFuture { id, timestamp } => write!(
+                f,
+                "block #{} is timestamped too far in the future ({})",
+                id, timestamp
+            ),
+            Self::TooManyTransactions { id, count } => {
+                write!(f, "block #{} has too many transactions ({})", id, count)
+            }
+            Self::MalformedCoinbase { id, hash } => write!(
+                f,
+                "block #{} has a malformed coinbase transaction {}",
+                id, hash
+            ),
+            Self::DuplicateTransaction { id, hash } => write!(
+                f,
+                "block #{} contains transaction {} more than once",
+                id, hash
+            ),
+            Self::InvalidTransaction { hash } => write!(f, "transaction {} is invalid", hash),
+            Self::CoinbaseSubmitted { hash } => write!(
+                f,
+                "transaction {} is a coinbase transaction, only miners can create those",
+                hash
+            ),
+            Self::AlreadyPending { hash } => {
+                write!(f, "transaction {} is already in the mempool", hash)
+            }
+            Self::AlreadyConfirmed { hash } => {
+                write!(f, "transaction {} is already confirmed", hash)
+            }
+            Self::UnexpectedNonce {
+                hash,
+                expected,
+                found,
+            } => write!(
+                f,
+                "transaction {} carries nonce {} but its sender is at nonce {}",
+                hash, found, expected
+            ),
+            Self::NonceInUse {
+                hash,
+                nonce,
+                pending,
+            } => write!(
+                f,
+                "transaction {} can't replace pending transaction {} with nonce {}",
+                hash, pending, nonce
+            ),
+            Self::TooManyAncestors { hash, count, size } => write!(
+                f,
+                "transaction {} would have {} pending ancestor(s) of {} bytes, itself included",
+                hash, count, size
+            ),
+            Self::TooManyDescendants { hash, ancestor } => write!(
+                f,
+                "transaction {} would exceed the descendant limits of pending transaction {}",
+                hash, ancestor
+            ),
+            Self::InvalidPackageSize { count } => {
+                write!(f, "a package can't hold {} transaction(s)", count)
+            }
+            Self::UnknownBlock { hash } => write!(f, "no header of block {} is known", hash),
+            Self::NotEnoughConfirmations {
+                hash,
+                confirmations,
+                required,
+            } => write!(
+                f,
+                "block {} has {} confirmation(s) out of the {} required",
+                hash, confirmations, required
+            ),
+            Self::InvalidInclusionProof { hash } => write!(
+                f,
+                "proof doesn't show transaction {} is included in the block",
+                hash
+            ),
+            Self::UnknownWork { work_id } => write!(f, "no work #{} is outstanding", work_id),
+            Self::InsufficientFunds {
+                hash,
+                amount,
+                available,
+            } => write!(
+                f,
+                "transaction {} spends {} but the sender only has {} available",
+                hash, amount, available
+            ),
+            Self::BalanceOverflow { hash } => write!(
+                f,
+                "transaction {} overflows the balance of its recipient",
+                hash
+            ),
+            Self::MinerTagTooLong { tag } => write!(
+                f,
+                "miner tag {} is longer than {} bytes",
+                tag,
+                crate::transaction::MAX_MINER_TAG_LEN
+            ),
+            Self::PolicyRejected {
+                hash,
+                policy,
+                reason,
+            } => write!(
+                f,
+                "transaction {} is rejected by the {} policy: {}",
+                hash, policy, reason
+            ),
+            Self::InvalidRewardSplit { percent } => write!(
+                f,
+                "reward shares add up to {}% of the reward, more than all of it",
+                percent
+            ),
+            Self::MalformedAddress { address } => write!(
+                f,
+                "{} isn't an address prefixed with its network",
+                address
+            ),
+            Self::WrongNetwork { address, expected } => write!(
+                f,
+                "{} isn't an address of the {} network",
+                address, expected
+            ),
+            Self::DecisionLogMismatch { height } => write!(
+                f,
+                "the stored chain no longer starts with the {} block(s) the decision log started from",
+                height
+            ),
+            Self::RewardShareUnpaid {
+                id,
+                role,
+                owed,
+                paid,
+            } => write!(
+                f,
+                "block #{} pays {} to the {} share instead of {}",
+                id, paid, role, owed
+            ),
+        }
+    }
+}
+
+impl error::Error for BlockchainError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for BlockchainError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
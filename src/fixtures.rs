@@ -0,0 +1,281 @@
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::blockchain::MAX_BLOCK_TRANSACTIONS;
+use crate::difficulty::TARGET_BLOCK_TIME_SECS;
+use crate::miner;
+use crate::snapshot::Format;
+use crate::transaction::Transaction;
+use crate::wallet::{Wallet, DEFAULT_NETWORK};
+use crate::{Block, Blockchain, BlockchainError};
+
+/// Timestamp of the genesis block of fixtures that don't ask for another.
+pub const FIXTURE_GENESIS_TIMESTAMP: i64 = 1_700_000_000;
+
+/// Transactions the blocks of a fixture carry besides their coinbase.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pattern {
+    /// None at all.
+    Empty,
+    /// Transfers between the wallets of the fixture, each paid by the richest one.
+    Transfers,
+    /// Payments from the miner to every other wallet in turn.
+    FanOut,
+}
+
+impl FromStr for Pattern {
+    type Err = String;
+
+    /// Parses `empty`, `transfers` or `fan-out`.
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        match pattern {
+            "empty" => Ok(Self::Empty),
+            "transfers" => Ok(Self::Transfers),
+            "fan-out" => Ok(Self::FanOut),
+            _ => Err(format!(
+                "unknown transaction pattern {}, expected empty, transfers or fan-out",
+                pattern
+            )),
+        }
+    }
+}
+
+/// Shape of the chains to generate with [`generate`]. The same spec always gives the same
+/// blocks, on any machine.
+#[derive(Clone, Debug)]
+pub struct FixtureSpec {
+    /// Blocks of the main chain, the genesis block included.
+    pub blocks: u64,
+    /// Heights of the blocks side chains branch off the main chain after.
+    pub forks: Vec<u64>,
+    /// Blocks of every side chain past its fork point.
+    pub fork_length: u64,
+    pub pattern: Pattern,
+    /// Transactions per block besides the coinbase, see [`Pattern`].
+    pub transactions: usize,
+    /// How many wallets the fixture pays between, the miner's included.
+    pub wallets: usize,
+    /// Picks the keys of the wallets, so specs differing only in their seed get unrelated
+    /// addresses.
+    pub seed: u64,
+    pub genesis_timestamp: i64,
+}
+
+/// Chains generated from a [`FixtureSpec`], each of them valid from genesis. Side chains share
+/// the blocks of the main chain up to their fork point.
+pub struct Fixture {
+    pub main: Vec<Block>,
+    /// Side chains by the height they fork at, in the order of [`FixtureSpec::forks`].
+    pub forks: Vec<(u64, Vec<Block>)>,
+    /// Wallets holding the funds of the fixture; the first one mines the main chain.
+    pub wallets: Vec<Wallet>,
+}
+
+impl Default for FixtureSpec {
+    fn default() -> Self {
+        Self {
+            blocks: 10,
+            forks: Vec::new(),
+            fork_length: 2,
+            pattern: Pattern::Transfers,
+            transactions: 2,
+            wallets: 4,
+            seed: 0,
+            genesis_timestamp: FIXTURE_GENESIS_TIMESTAMP,
+        }
+    }
+}
+
+impl FixtureSpec {
+    fn check(&self) -> Result<(), BlockchainError> {
+        let invalid = |reason: String| Err(BlockchainError::InvalidFixture(reason));
+        if self.blocks == 0 {
+            return invalid(String::from("a chain needs at least its genesis block"));
+        }
+        if self.wallets < 2 {
+            return invalid(format!("{} wallet(s) can't pay each other", self.wallets));
+        }
+        if self.transactions >= MAX_BLOCK_TRANSACTIONS {
+            return invalid(format!(
+                "{} transactions don't fit a block next to its coinbase",
+                self.transactions
+            ));
+        }
+        if let Some(height) = self.forks.iter().find(|&&height| height >= self.blocks) {
+            return invalid(format!(
+                "can't fork at height {} of a chain of {} block(s)",
+                height, self.blocks
+            ));
+        }
+        Ok(())
+    }
+
+    /// Wallet `index` of the fixture, its key derived from the seed.
+    fn wallet(&self, index: usize) -> Wallet {
+        let mut key = [0; 32];
+        key[..8].copy_from_slice(&self.seed.to_le_bytes());
+        key[8..16].copy_from_slice(&(index as u64).to_le_bytes());
+        Wallet::from_secret_key(DEFAULT_NETWORK, key)
+    }
+}
+
+/// Generates the chains `spec` describes, mining every block on a single thread. Blocks are
+/// spaced [`TARGET_BLOCK_TIME_SECS`] apart so the difficulty never rises; side chains are mined
+/// by the last wallet of the fixture and a second later, so they never repeat a block of the
+/// main chain.
+pub fn generate(spec: &FixtureSpec) -> Result<Fixture, BlockchainError> {
+    spec.check()?;
+    let wallets: Vec<Wallet> = (0..spec.wallets).map(|index| spec.wallet(index)).collect();
+
+    let genesis = miner::mine_deterministic(Blockchain::genesis_template(), spec.genesis_timestamp);
+    let mut blockchain = Blockchain::new();
+    blockchain.try_add_block(genesis)?;
+    extend(spec, &wallets, &mut blockchain, 0, spec.blocks - 1, 0)?;
+    let main = blockchain.blocks().to_vec();
+
+    let mut forks = Vec::new();
+    for &height in &spec.forks {
+        let mut side = Blockchain::from_blocks(main[..=height as usize].to_vec())?;
+        extend(
+            spec,
+            &wallets,
+            &mut side,
+            wallets.len() - 1,
+            spec.fork_length,
+            1,
+        )?;
+        forks.push((height, side.blocks().to_vec()));
+    }
+
+    Ok(Fixture {
+        main,
+        forks,
+        wallets,
+    })
+}
+
+/// Mines `count` blocks on top of `blockchain` paying wallet `miner`, each timestamped
+/// `offset` seconds past its slot.
+fn extend(
+    spec: &FixtureSpec,
+    wallets: &[Wallet],
+    blockchain: &mut Blockchain,
+    miner: usize,
+    count: u64,
+    offset: i64,
+) -> Result<(), BlockchainError> {
+    for _ in 0..count {
+        let mut template = blockchain.block_template(&wallets[miner].address())?;
+        let timestamp =
+            spec.genesis_timestamp + template.id as i64 * TARGET_BLOCK_TIME_SECS + offset;
+        for coinbase in &mut template.transactions {
+            coinbase.timestamp = timestamp;
+        }
+
+        let mut balances: Vec<u64> = wallets
+            .iter()
+            .map(|wallet| blockchain.balance(&wallet.address()))
+            .collect();
+        let mut nonces: Vec<u64> = wallets
+            .iter()
+            .map(|wallet| blockchain.nonce(&wallet.address()))
+            .collect();
+        for coinbase in &template.transactions {
+            if let Some(index) = wallets
+                .iter()
+                .position(|wallet| wallet.address() == coinbase.recipient)
+            {
+                balances[index] += coinbase.amount;
+            }
+        }
+
+        for index in 0..spec.transactions {
+            let (sender, recipient) = match spec.pattern {
+                Pattern::Empty => break,
+                Pattern::Transfers => {
+                    let sender = (0..wallets.len())
+                        .max_by_key(|&wallet| (balances[wallet], std::cmp::Reverse(wallet)))
+                        .unwrap_or(0);
+                    (sender, (sender + 1 + index) % wallets.len())
+                }
+                Pattern::FanOut => (miner, (miner + 1 + index) % wallets.len()),
+            };
+            let amount = 1 + (template.id + index as u64) % 5;
+            if sender == recipient || balances[sender] < amount {
+                continue;
+            }
+
+            let mut transaction = Transaction::new(
+                wallets[sender].address(),
+                wallets[recipient].address(),
+                amount,
+            );
+            transaction.timestamp = timestamp;
+            transaction.nonce = nonces[sender];
+            wallets[sender].sign(&mut transaction);
+            nonces[sender] += 1;
+            balances[sender] -= amount;
+            balances[recipient] += amount;
+            template.transactions.push(transaction);
+        }
+
+        blockchain.try_add_block(miner::mine_deterministic(template, timestamp))?;
+    }
+    Ok(())
+}
+
+impl Fixture {
+    /// Writes the main chain to `main.<format>` in `dir` and every side chain to
+    /// `fork-<height>.<format>`, as snapshots `import` takes, see [`Blockchain::export`].
+    pub fn write<P: AsRef<Path>>(&self, dir: P, format: Format) -> Result<(), BlockchainError> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let extension = match format {
+            Format::Json => "json",
+            Format::Bincode => "bin",
+        };
+
+        let chains = std::iter::once((String::from("main"), &self.main)).chain(
+            self.forks
+                .iter()
+                .map(|(height, blocks)| (format!("fork-{}", height), blocks)),
+        );
+        for (name, blocks) in chains {
+            let path = dir.join(format!("{}.{}", name, extension));
+            Blockchain::from_blocks(blocks.clone())?.export(path, format)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixture_specs_are_checked_before_mining() {
+        assert_eq!("fan-out".parse(), Ok(Pattern::FanOut));
+        assert!("zigzag".parse::<Pattern>().is_err());
+
+        for spec in [
+            FixtureSpec {
+                forks: vec![10],
+                ..FixtureSpec::default()
+            },
+            FixtureSpec {
+                wallets: 1,
+                ..FixtureSpec::default()
+            },
+            FixtureSpec {
+                blocks: 0,
+                ..FixtureSpec::default()
+            },
+        ] {
+            assert!(matches!(
+                generate(&spec),
+                Err(BlockchainError::InvalidFixture(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
+
+use crate::transaction::Transaction;
+use crate::view::ChainViews;
+use crate::{Block, Blockchain, BlockchainError};
+
+/// Thread-safe handle to a [`Blockchain`] shared by the miner, the network and the API.
+/// Clones are cheap and all refer to the same chain.
+///
+/// The methods lock the chain for as long as the call takes. Use [`ChainHandle::read`] or
+/// [`ChainHandle::write`] to do several things under the same lock, and
+/// [`ChainHandle::views`] for queries that shouldn't lock it at all.
+#[derive(Clone)]
+pub struct ChainHandle {
+    chain: Arc<RwLock<Blockchain>>,
+    views: ChainViews,
+}
+
+impl ChainHandle {
+    pub fn new(blockchain: Blockchain) -> Self {
+        Self {
+            views: blockchain.views(),
+            chain: Arc::new(RwLock::new(blockchain)),
+        }
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, Blockchain> {
+        self.chain.read().unwrap()
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, Blockchain> {
+        self.chain.write().unwrap()
+    }
+
+    pub fn views(&self) -> ChainViews {
+        self.views.clone()
+    }
+
+    pub fn height(&self) -> u64 {
+        self.read().height()
+    }
+
+    pub fn work(&self) -> u64 {
+        self.read().work()
+    }
+
+    pub fn tip(&self) -> Option<Block> {
+        self.read().tip().cloned()
+    }
+
+    /// See [`Blockchain::try_add_block`].
+    pub fn try_add_block(&self, block: Block) -> Result<(), BlockchainError> {
+        self.write().try_add_block(block)
+    }
+
+    /// See [`Blockchain::try_add_blocks`].
+    pub fn try_add_blocks(&self, blocks: Vec<Block>) -> Result<(), BlockchainError> {
+        self.write().try_add_blocks(blocks)
+    }
+
+    /// See [`Blockchain::submit_transaction`].
+    pub fn submit_transaction(&self, transaction: Transaction) -> Result<(), BlockchainError> {
+        self.write().submit_transaction(transaction)
+    }
+
+    /// See [`Blockchain::submit_package`].
+    pub fn submit_package(&self, transactions: Vec<Transaction>) -> Result<(), BlockchainError> {
+        self.write().submit_package(transactions)
+    }
+
+    /// See [`Blockchain::expire_transactions`].
+    pub fn expire_transactions(&self, ttl: Duration) -> Vec<Transaction> {
+        self.write().expire_transactions(ttl)
+    }
+
+    /// See [`Blockchain::set_time_offset`].
+    pub fn set_time_offset(&self, offset: i64) {
+        self.write().set_time_offset(offset)
+    }
+
+    /// See [`Blockchain::flush`].
+    pub fn flush(&self) -> Result<(), BlockchainError> {
+        self.write().flush()
+    }
+}
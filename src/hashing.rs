@@ -0,0 +1,34 @@
+use sha2::{Digest, Sha256};
+
+/// Tags used to domain-separate the hashes of different kinds of objects, so e.g. a
+/// transaction hash can never be confused with a block hash or a signature preimage.
+pub const BLOCK_TAG: &str = "block";
+pub const TRANSACTION_TAG: &str = "transaction";
+pub const WITNESS_TAG: &str = "witness";
+pub const MERKLE_LEAF_TAG: &str = "merkle-leaf";
+pub const MERKLE_NODE_TAG: &str = "merkle-node";
+pub const SIGNATURE_TAG: &str = "signature";
+pub const ADDRESS_TAG: &str = "address";
+pub const ADDRESS_STATUS_TAG: &str = "address-status";
+
+/// Double SHA-256 of `data` prefixed with the hash of `tag`. Hashing the tag first gives it a
+/// fixed length, so no tag/data split can be mistaken for another.
+pub fn tagged_digest(tag: &str, data: impl AsRef<[u8]>) -> [u8; 32] {
+    let inner = Sha256::new()
+        .chain_update(Sha256::digest(tag))
+        .chain_update(data)
+        .finalize();
+    Sha256::digest(inner).into()
+}
+
+/// Appends `value` to `buf` prefixed with its length as a big-endian `u64`, so the fields of
+/// an encoding can't run into each other.
+pub fn encode_str(buf: &mut Vec<u8>, value: &str) {
+    buf.extend_from_slice(&(value.len() as u64).to_be_bytes());
+    buf.extend_from_slice(value.as_bytes());
+}
+
+/// Hex-encoded [`tagged_digest`].
+pub fn tagged_hash(tag: &str, data: impl AsRef<[u8]>) -> String {
+    hex::encode(tagged_digest(tag, data))
+}
@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::transaction::Transaction;
+use crate::{Block, BlockchainError};
+
+/// What the confirmed transactions of a chain lead to, address by address: how much each holds
+/// and which nonce its next transaction has to carry, see [`Transaction::nonce`].
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Ledger {
+    /// Confirmed balance of every address that has ever received funds.
+    pub balances: HashMap<String, u64>,
+    /// How many transactions every address that sent any has confirmed.
+    #[serde(default)]
+    pub nonces: HashMap<String, u64>,
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Confirmed balance of `address`.
+    pub fn balance(&self, address: &str) -> u64 {
+        self.balances.get(address).copied().unwrap_or(0)
+    }
+
+    /// Nonce the next transaction `address` sends has to carry.
+    pub fn nonce(&self, address: &str) -> u64 {
+        self.nonces.get(address).copied().unwrap_or(0)
+    }
+
+    /// Moves the amount of a transaction from its sender to its recipient and uses up the
+    /// nonce of the sender, failing without touching the ledger if the transaction doesn't
+    /// carry the sender's next nonce or the sender can't afford it. Coinbase transactions only
+    /// credit.
+    pub fn apply_transaction(&mut self, transaction: &Transaction) -> Result<(), BlockchainError> {
+        let recipient_balance = self
+            .balance(&transaction.recipient)
+            .checked_add(transaction.amount)
+            .ok_or_else(|| BlockchainError::BalanceOverflow {
+                hash: transaction.hash(),
+            })?;
+
+        if !transaction.is_coinbase() {
+            let nonce = self.nonce(&transaction.sender);
+            if transaction.nonce != nonce {
+                return Err(BlockchainError::UnexpectedNonce {
+                    hash: transaction.hash(),
+                    expected: nonce,
+                    found: transaction.nonce,
+                });
+            }
+
+            let sender_balance = self.balance(&transaction.sender);
+            if sender_balance < transaction.amount {
+                return Err(BlockchainError::InsufficientFunds {
+                    hash: transaction.hash(),
+                    amount: transaction.amount,
+                    available: sender_balance,
+                });
+            }
+            self.balances.insert(
+                transaction.sender.clone(),
+                sender_balance - transaction.amount,
+            );
+            self.nonces.insert(transaction.sender.clone(), nonce + 1);
+        }
+
+        self.balances
+            .insert(transaction.recipient.clone(), recipient_balance);
+        Ok(())
+    }
+
+    /// Applies every transaction of a block in order; the ledger is left partially updated if
+    /// one of them fails.
+    pub fn apply_block(&mut self, block: &Block) -> Result<(), BlockchainError> {
+        block
+            .transactions
+            .iter()
+            .try_for_each(|transaction| self.apply_transaction(transaction))
+    }
+}
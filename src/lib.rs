@@ -0,0 +1,53 @@
+//! A small proof-of-work blockchain: signed account transfers, a mempool, difficulty
+//! retargeting, append-only block storage, a TCP gossip network and an HTTP API.
+//!
+//! [`Blockchain`] owns the active chain and validates everything that goes into it; blocks are
+//! mined with [`Blockchain::block_template`] and [`miner::Miner::mine`] and connected with
+//! [`Blockchain::try_add_block`]. The miner, the network and the API share it through a
+//! [`ChainHandle`].
+
+pub mod address_book;
+pub mod alerts;
+pub mod annotations;
+pub mod api;
+pub mod block;
+pub mod blockchain;
+pub mod bridge;
+pub mod capture;
+pub mod changes;
+pub mod checkpoint;
+pub mod decisions;
+pub mod difficulty;
+pub mod electrum;
+pub mod error;
+pub mod fixtures;
+pub mod handle;
+pub mod hashing;
+pub mod ledger;
+pub mod mempool;
+pub mod merkle;
+pub mod metrics;
+pub mod miner;
+pub mod network;
+pub mod policy;
+pub mod rebroadcast;
+pub mod reward;
+pub mod snapshot;
+pub mod stats;
+pub mod storage;
+pub mod subscriptions;
+pub mod telemetry;
+pub mod transaction;
+pub mod verifier;
+pub mod view;
+pub mod wallet;
+pub mod watchdog;
+pub mod work;
+
+#[cfg(test)]
+mod vectors;
+
+pub use block::{Block, BlockHeader, BlockTemplate};
+pub use blockchain::{Blockchain, ReplaceChainOutcome};
+pub use error::BlockchainError;
+pub use handle::ChainHandle;
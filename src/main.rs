@@ -1,141 +1,678 @@
-use chrono::{Local, Utc};
-use sha2::{Digest, Sha256};
+use chrono::Local;
 
-use log::{info, warn};
-use std::io::Write;
+use clap::{Parser, Subcommand};
+use log::{debug, info, warn};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
+use serde_json::json;
+use std::cmp::Ordering;
+use std::error::Error;
+use std::io::{IsTerminal, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+use tokio::time::MissedTickBehavior;
 
-const DIFFICULTY_PREFIX: &str = "00000";
+use blockchain::alerts::{self, Alert, AlertMonitor};
+use blockchain::annotations::Annotations;
+use blockchain::api;
+use blockchain::bridge;
+use blockchain::capture::MisbehaviorCapture;
+use blockchain::decisions::{self, Decision, DecisionLog, Input};
+use blockchain::electrum;
+use blockchain::fixtures::{self, FixtureSpec, Pattern};
+use blockchain::miner::{self, CancellationToken, Miner};
+use blockchain::network::{self, Message, Network};
+use blockchain::policy::{Allowlist, RateLimit};
+use blockchain::reward::{MiningReport, RewardShare, RewardSplit};
+use blockchain::snapshot::Format;
+use blockchain::stats::{StatsHistory, StatsSample, SAMPLE_INTERVAL};
+use blockchain::storage::BlockReader;
+use blockchain::telemetry::TelemetryEvent;
+use blockchain::transaction::Transaction;
+use blockchain::wallet::Wallet;
+use blockchain::watchdog::Watchdog;
+use blockchain::work::Work;
+use blockchain::{
+    Block, BlockHeader, Blockchain, BlockchainError, ChainHandle, ReplaceChainOutcome,
+};
 
-struct Blockchain {
-    blocks: Vec<Block>,
+/// Errors the commands fail with; `Send` so chains can run as separate tasks.
+type BoxError = Box<dyn Error + Send + Sync>;
+
+const CHAIN_ID: &str = "main";
+const STORAGE_PATH: &str = "data/blocks.jsonl";
+const LISTEN_ADDR: &str = "127.0.0.1:6000";
+const API_ADDR: &str = "127.0.0.1:8000";
+/// How long a fresh node waits for its peers to hand it a chain before mining its own genesis.
+const INITIAL_SYNC_SECS: u64 = 5;
+/// How often the tip is checked for a competing block while mining.
+const TIP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+const STALL_SECS: u64 = 120;
+const MEMPOOL_TTL_SECS: u64 = 60 * 60;
+/// How often the mempool is checked for expired transactions.
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(10);
+/// How many of the most recent samples `stats watch` draws.
+const SPARKLINE_WIDTH: usize = 60;
+/// How often `loadgen` asks the node what its wallets can spend.
+const LOAD_BALANCE_INTERVAL: Duration = Duration::from_secs(2);
+/// How often `loadgen` logs how many transactions it submitted.
+const LOAD_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+/// Largest amount of a `loadgen` transfer.
+const MAX_LOAD_AMOUNT: u64 = 5;
+
+/// Transfers submitted as one package on every round of the demo loop, as `(sender, recipient,
+/// amount)` indices into the demo wallets. The first wallet collects the block rewards and each
+/// transfer is funded by the one before it.
+const DEMO_TRANSFERS: [(usize, usize, u64); 3] = [(0, 1, 10), (1, 2, 5), (2, 0, 1)];
+
+/// A small proof-of-work blockchain node. Settings are read from the environment, see
+/// `Config`; `run` runs every configured chain, the other commands work on one of them.
+#[derive(Parser)]
+struct Cli {
+    /// Chain to work on, defaults to the first one configured
+    #[arg(long, global = true)]
+    chain: Option<String>,
+    #[command(subcommand)]
+    command: Command,
 }
 
-struct Block {
-    id: u64,
-    hash: String,
-    previous_hash: String,
-    timestamp: i64,
-    data: String,
-    nonce: u64,
+#[derive(Subcommand)]
+enum Command {
+    /// Mines blocks on top of the stored chain without joining the network
+    Mine {
+        /// How many blocks to mine, the genesis block included if the chain is empty
+        #[arg(long, default_value_t = 1)]
+        count: u64,
+    },
+    /// Re-validates the stored chain from genesis
+    Validate,
+    /// Prints the stored blocks from `--from` to `--to`, both included, reading them one at a
+    /// time without validating the chain
+    Show {
+        #[arg(long, default_value_t = 0)]
+        from: u64,
+        /// Defaults to the tip
+        #[arg(long)]
+        to: Option<u64>,
+        /// Only print the headers, without decoding the transactions
+        #[arg(long)]
+        headers: bool,
+    },
+    /// Writes the stored chain to a snapshot file
+    Export {
+        path: PathBuf,
+        /// `json` or `bincode`
+        #[arg(long, default_value = "json")]
+        format: Format,
+    },
+    /// Validates a snapshot file and stores it if it has more work than the stored chain
+    Import { path: PathBuf },
+    /// Joins the network, serves the HTTP API and mines until interrupted with Ctrl-C
+    Run,
+    /// Mines for the node serving its API on `--node`, on nonce ranges it hands out, until
+    /// Ctrl-C
+    Worker {
+        /// HTTP API of the node, defaults to the one configured for the chain
+        #[arg(long)]
+        node: Option<SocketAddr>,
+        /// Address the rewards of the blocks we solve go to
+        #[arg(long)]
+        address: String,
+    },
+    /// Submits random transfers between wallets of its own to the node serving its API on
+    /// `--node`, at `--rate` per second, until Ctrl-C. The wallets are funded first by mining
+    /// `--funding-blocks` blocks for the node, like `worker` does
+    Loadgen {
+        /// HTTP API of the node, defaults to the one configured for the chain
+        #[arg(long)]
+        node: Option<SocketAddr>,
+        /// Transactions per second
+        #[arg(long, default_value_t = 10.0)]
+        rate: f64,
+        /// How many wallets send to each other, at least 2
+        #[arg(long, default_value_t = 8)]
+        wallets: usize,
+        /// Blocks mined before starting, each one paying the reward spread over the wallets
+        #[arg(long, default_value_t = 1)]
+        funding_blocks: u64,
+    },
+    /// Checks the JSON header chain in `path`, as served page by page by `GET /headers/{from}`,
+    /// from genesis on
+    VerifyHeaders { path: PathBuf },
+    /// Feeds the decision log of the chain through the consensus code again, printing every
+    /// decision; fails if any of them comes out differently than it did on the node
+    ReplayDecisions {
+        /// Defaults to the decision log kept next to the blocks
+        path: Option<PathBuf>,
+    },
+    /// Compares the stored chain with the one of another node, reporting the first block they
+    /// disagree on; fails if there is one
+    Compare {
+        /// Base URL of the HTTP API of the other node, e.g. `http://127.0.0.1:8001`
+        #[arg(long)]
+        rpc: String,
+    },
+    /// Reads the stats a running node keeps of itself, or sums up the stored chain
+    Stats {
+        #[command(subcommand)]
+        command: StatsCommand,
+    },
+    /// Accounts for what mining earned an address on the stored chain
+    Mining {
+        #[command(subcommand)]
+        command: MiningCommand,
+    },
+    /// Mines the genesis block for `--timestamp` deterministically, so anyone can reproduce it;
+    /// nodes start from it when pointed at it with `BLOCKCHAIN_GENESIS`
+    GenesisTool {
+        /// Timestamp of the genesis block, in Unix seconds
+        #[arg(long)]
+        timestamp: i64,
+        /// Where to write the block, printed if not given
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Tools for tests of the node
+    Testkit {
+        #[command(subcommand)]
+        command: TestkitCommand,
+    },
 }
 
-impl Block {
-    fn new(id: u64, previous_hash: String, data: String) -> Self {
-        let timestamp = Utc::now().timestamp();
-        let (hash, nonce) = Self::mine(id, previous_hash.clone(), timestamp, data.clone());
+#[derive(Subcommand)]
+enum TestkitCommand {
+    /// Deterministically generates a main chain of `--blocks` blocks, a side chain of
+    /// `--fork-length` blocks branching off after each `--fork` height, and writes them to
+    /// `--output` as snapshots
+    Fixtures {
+        #[arg(long, default_value_t = 10)]
+        blocks: u64,
+        #[arg(long = "fork")]
+        forks: Vec<u64>,
+        #[arg(long, default_value_t = 2)]
+        fork_length: u64,
+        /// `empty`, `transfers` or `fan-out`
+        #[arg(long, default_value = "transfers")]
+        pattern: Pattern,
+        /// Transactions per block besides the coinbase
+        #[arg(long, default_value_t = 2)]
+        transactions: usize,
+        #[arg(long, default_value_t = 4)]
+        wallets: usize,
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+        #[arg(long, default_value_t = fixtures::FIXTURE_GENESIS_TIMESTAMP)]
+        genesis_timestamp: i64,
+        #[arg(long, default_value = "fixtures")]
+        output: PathBuf,
+        /// `json` or `bincode`
+        #[arg(long, default_value = "json")]
+        format: Format,
+    },
+}
 
-        Self {
-            id,
-            hash,
-            previous_hash,
-            timestamp,
-            data,
-            nonce,
-        }
+#[derive(Subcommand)]
+enum MiningCommand {
+    /// Prints how many of the stored blocks from `--since` on paid `--address` a reward, how
+    /// much it earned with them and how much per hash their difficulty called for, and how many
+    /// of its blocks reorgs orphaned
+    Report {
+        #[arg(long)]
+        address: String,
+        #[arg(long, default_value_t = 1)]
+        since: u64,
+    },
+}
+
+#[derive(Subcommand)]
+enum StatsCommand {
+    /// Renders hashrate, block interval, mempool size and peer count live, until Ctrl-C
+    Watch {
+        /// HTTP API of the node, defaults to the one configured for the chain
+        #[arg(long)]
+        api: Option<SocketAddr>,
+    },
+    /// Prints how many of the stored blocks each miner tag mined, or of the `--last` ones
+    Miners {
+        #[arg(long)]
+        last: Option<usize>,
+    },
+}
+
+/// Node settings. The chains to run come from the JSON file at `BLOCKCHAIN_CHAINS` if set,
+/// see [`ChainConfig`], and otherwise make up a single chain read from `BLOCKCHAIN_CHAIN_ID`,
+/// `BLOCKCHAIN_STORAGE`, `BLOCKCHAIN_LISTEN`, the comma-separated `BLOCKCHAIN_PEERS`,
+/// `BLOCKCHAIN_API`, `BLOCKCHAIN_CAPTURE_DIR`, `BLOCKCHAIN_GENESIS`, `BLOCKCHAIN_BLOCKS_ONLY`, the
+/// comma-separated `role:address:percent` shares of `BLOCKCHAIN_REWARD_SPLIT`,
+/// `BLOCKCHAIN_DECISION_LOG`, `BLOCKCHAIN_TELEMETRY_URL`, the comma-separated
+/// `BLOCKCHAIN_SENDER_ALLOWLIST`, `BLOCKCHAIN_SENDER_RATE_LIMIT`, `BLOCKCHAIN_MINER_TAG`,
+/// `BLOCKCHAIN_PUBLIC_API`, `BLOCKCHAIN_NO_REBROADCAST` and `BLOCKCHAIN_ELECTRUM`.
+struct Config {
+    chains: Vec<ChainConfig>,
+    settings: Settings,
+}
+
+/// Settings applying to every chain, read from the `BLOCKCHAIN_STALL_SECS`,
+/// `BLOCKCHAIN_RESTART_MINER_ON_STALL` and `BLOCKCHAIN_MEMPOOL_TTL_SECS` environment variables.
+#[derive(Clone, Copy)]
+struct Settings {
+    /// How long the tip may go without advancing before the watchdog steps in.
+    stall_after: Duration,
+    /// Whether the watchdog restarts the miner with a fresh template when the tip stalls.
+    restart_miner_on_stall: bool,
+    /// How long a transaction may stay unconfirmed before it's evicted from the mempool.
+    mempool_ttl: Duration,
+}
+
+/// One of the independent chains a node runs, an entry of the `BLOCKCHAIN_CHAINS` file.
+#[derive(Deserialize)]
+struct ChainConfig {
+    /// Peers on other chains are dropped, so chains sharing a network never mix.
+    chain_id: String,
+    /// Defaults to `data/<chain_id>/blocks.jsonl`.
+    storage_path: Option<String>,
+    listen_addr: SocketAddr,
+    /// Where the HTTP API is served.
+    api_addr: SocketAddr,
+    #[serde(default)]
+    peers: Vec<SocketAddr>,
+    /// Where invalid messages from peers are dumped, if set.
+    capture_dir: Option<String>,
+    /// Genesis block published for the chain, see `genesis-tool`. An empty chain starts from it
+    /// instead of mining its own.
+    genesis_path: Option<String>,
+    /// Only exchange blocks with peers, neither accepting nor relaying their transactions.
+    #[serde(default)]
+    blocks_only: bool,
+    /// Cuts of the reward of the blocks we mine paid to addresses other than the miner's; blocks
+    /// of peers not paying them aren't relayed.
+    #[serde(default)]
+    reward_split: Vec<RewardShare>,
+    /// Record every input the chain decides on to its decision log, see `replay-decisions`.
+    #[serde(default)]
+    decision_log: bool,
+    /// Where anonymized telemetry of the chain is posted, see `Telemetry::export`. Nothing is
+    /// sent unless set.
+    telemetry_url: Option<String>,
+    /// Only take transactions from these senders into the mempool, if any are set.
+    #[serde(default)]
+    sender_allowlist: Vec<String>,
+    /// Most transactions per minute taken into the mempool from any one sender, if set.
+    sender_rate_limit: Option<usize>,
+    /// What the blocks we mine identify their miner as, e.g. the name of a pool.
+    miner_tag: Option<String>,
+    /// Only serve the read-only routes of the API, rate limited, see `api::public_router`.
+    #[serde(default)]
+    public_api: bool,
+    /// Announce transactions submitted to us only once rather than until they're confirmed.
+    #[serde(default)]
+    no_rebroadcast: bool,
+    /// Where light wallets are served, see `electrum::serve`, if anywhere.
+    electrum_addr: Option<SocketAddr>,
+}
+
+impl Config {
+    fn from_env() -> Result<Self, BoxError> {
+        let chains = match std::env::var("BLOCKCHAIN_CHAINS") {
+            Ok(path) => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+            Err(_) => vec![ChainConfig::from_env()?],
+        };
+        let stall_after = match std::env::var("BLOCKCHAIN_STALL_SECS") {
+            Ok(secs) => Duration::from_secs(secs.parse()?),
+            Err(_) => Duration::from_secs(STALL_SECS),
+        };
+        let restart_miner_on_stall = match std::env::var("BLOCKCHAIN_RESTART_MINER_ON_STALL") {
+            Ok(restart) => restart.parse()?,
+            Err(_) => false,
+        };
+        let mempool_ttl = match std::env::var("BLOCKCHAIN_MEMPOOL_TTL_SECS") {
+            Ok(secs) => Duration::from_secs(secs.parse()?),
+            Err(_) => Duration::from_secs(MEMPOOL_TTL_SECS),
+        };
+
+        Ok(Self {
+            chains,
+            settings: Settings {
+                stall_after,
+                restart_miner_on_stall,
+                mempool_ttl,
+            },
+        })
     }
 
-    fn hash(id: u64, previous_hash: String, timestamp: i64, data: String, nonce: u64) -> String {
-        let unified_block_data = format!("{}{}{}{}{}", id, previous_hash, timestamp, data, nonce);
+    /// The chain with id `chain_id`, or the first one configured.
+    fn chain(&self, chain_id: Option<&str>) -> Result<&ChainConfig, BoxError> {
+        let chain = match chain_id {
+            Some(chain_id) => self.chains.iter().find(|chain| chain.chain_id == chain_id),
+            None => self.chains.first(),
+        };
 
-        let mut hasher = Sha256::new();
-        hasher.update(unified_block_data);
-        format!("{:x}", hasher.finalize())
+        chain.ok_or_else(|| format!("no chain {} is configured", chain_id.unwrap_or("")).into())
     }
+}
 
-    fn mine(id: u64, previous_hash: String, timestamp: i64, data: String) -> (String, u64) {
-        let mut nonce = 0;
+impl ChainConfig {
+    fn from_env() -> Result<Self, BoxError> {
+        let chain_id =
+            std::env::var("BLOCKCHAIN_CHAIN_ID").unwrap_or_else(|_| String::from(CHAIN_ID));
+        let storage_path =
+            std::env::var("BLOCKCHAIN_STORAGE").unwrap_or_else(|_| String::from(STORAGE_PATH));
+        let listen_addr = std::env::var("BLOCKCHAIN_LISTEN")
+            .unwrap_or_else(|_| String::from(LISTEN_ADDR))
+            .parse()?;
+        let peers = std::env::var("BLOCKCHAIN_PEERS")
+            .unwrap_or_default()
+            .split(',')
+            .filter(|peer| !peer.is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()?;
+        let api_addr = std::env::var("BLOCKCHAIN_API")
+            .unwrap_or_else(|_| String::from(API_ADDR))
+            .parse()?;
+        let capture_dir = std::env::var("BLOCKCHAIN_CAPTURE_DIR").ok();
+        let genesis_path = std::env::var("BLOCKCHAIN_GENESIS").ok();
+        let blocks_only = match std::env::var("BLOCKCHAIN_BLOCKS_ONLY") {
+            Ok(blocks_only) => blocks_only.parse()?,
+            Err(_) => false,
+        };
+        let reward_split = std::env::var("BLOCKCHAIN_REWARD_SPLIT")
+            .unwrap_or_default()
+            .split(',')
+            .filter(|share| !share.is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()?;
+        let decision_log = match std::env::var("BLOCKCHAIN_DECISION_LOG") {
+            Ok(decision_log) => decision_log.parse()?,
+            Err(_) => false,
+        };
+        let telemetry_url = std::env::var("BLOCKCHAIN_TELEMETRY_URL").ok();
+        let sender_allowlist = std::env::var("BLOCKCHAIN_SENDER_ALLOWLIST")
+            .unwrap_or_default()
+            .split(',')
+            .filter(|sender| !sender.is_empty())
+            .map(String::from)
+            .collect();
+        let sender_rate_limit = std::env::var("BLOCKCHAIN_SENDER_RATE_LIMIT")
+            .ok()
+            .map(|limit| limit.parse())
+            .transpose()?;
+        let miner_tag = std::env::var("BLOCKCHAIN_MINER_TAG").ok();
+        let public_api = match std::env::var("BLOCKCHAIN_PUBLIC_API") {
+            Ok(public_api) => public_api.parse()?,
+            Err(_) => false,
+        };
+        let electrum_addr = std::env::var("BLOCKCHAIN_ELECTRUM")
+            .ok()
+            .map(|addr| addr.parse())
+            .transpose()?;
+        let no_rebroadcast = match std::env::var("BLOCKCHAIN_NO_REBROADCAST") {
+            Ok(no_rebroadcast) => no_rebroadcast.parse()?,
+            Err(_) => false,
+        };
 
-        loop {
-            let hash = Self::hash(id, previous_hash.clone(), timestamp, data.clone(), nonce);
+        Ok(Self {
+            chain_id,
+            storage_path: Some(storage_path),
+            listen_addr,
+            api_addr,
+            peers,
+            capture_dir,
+            genesis_path,
+            blocks_only,
+            reward_split,
+            decision_log,
+            telemetry_url,
+            sender_allowlist,
+            sender_rate_limit,
+            miner_tag,
+            public_api,
+            no_rebroadcast,
+            electrum_addr,
+        })
+    }
 
-            if hash.as_str().starts_with(DIFFICULTY_PREFIX) {
-                info!("Block #{} was successfully mined", id);
-                return (hash, nonce);
-            }
+    /// Loads the stored chain, starting it from the published genesis block if it's empty and
+    /// one is configured.
+    fn load(&self) -> Result<Blockchain, BoxError> {
+        let mut blockchain = Blockchain::load(self.storage_path())?;
+        blockchain.set_reward_split(RewardSplit::new(self.reward_split.clone())?);
+        blockchain.set_miner_tag(self.miner_tag.clone())?;
+        if !self.sender_allowlist.is_empty() {
+            let allowlist = Allowlist::new(self.sender_allowlist.iter().cloned());
+            blockchain.add_mempool_policy(Box::new(allowlist));
+        }
+        if let Some(max) = self.sender_rate_limit {
+            let rate_limit = RateLimit::new(max, Duration::from_secs(60));
+            blockchain.add_mempool_policy(Box::new(rate_limit));
+        }
 
-            nonce += 1;
+        if let (None, Some(path)) = (blockchain.tip(), &self.genesis_path) {
+            let genesis_block: Block = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+            info!("Starting from the genesis block {}", genesis_block.hash);
+            blockchain.try_add_block(genesis_block)?;
         }
+        Ok(blockchain)
     }
-}
 
-impl Blockchain {
-    fn new() -> Self {
-        Self { blocks: Vec::new() }
+    fn storage_path(&self) -> String {
+        self.storage_path
+            .clone()
+            .unwrap_or_else(|| format!("data/{}/blocks.jsonl", self.chain_id))
     }
 
-    fn create_genesis(&mut self) {
-        let timestamp = Utc::now().timestamp();
-        let (hash, nonce) = Block::mine(
-            0,
-            String::from("genesis"),
-            timestamp,
-            String::from("genesis"),
-        );
+    /// Anchor peers of the chain are kept next to its blocks.
+    fn anchors_path(&self) -> PathBuf {
+        Path::new(&self.storage_path()).with_file_name("anchors.json")
+    }
 
-        let genesis_block = Block {
-            id: 0,
-            hash,
-            previous_hash: String::from("genesis"),
-            timestamp,
-            data: String::from("genesis"),
-            nonce,
-        };
+    /// So are the annotations of its operator.
+    fn annotations_path(&self) -> PathBuf {
+        Path::new(&self.storage_path()).with_file_name("annotations.json")
+    }
+
+    /// And its decision log.
+    fn decision_log_path(&self) -> PathBuf {
+        Path::new(&self.storage_path()).with_file_name("decisions.jsonl")
+    }
 
-        self.blocks.push(genesis_block);
-        info!("Genesis block was successfully created and added to the blockchain");
+    /// And how close the block abandoned on shutdown came to being solved.
+    fn abandoned_path(&self) -> PathBuf {
+        Path::new(&self.storage_path()).with_file_name("abandoned.json")
     }
+}
+
+/// Cancels `cancel` as soon as the tip of the chain is no longer `previous_hash`, i.e. a
+/// competing block for the height being mined got connected. Stops once `cancel` is cancelled.
+fn watch_tip(
+    blockchain: ChainHandle,
+    previous_hash: String,
+    cancel: CancellationToken,
+) -> JoinHandle<()> {
+    thread::spawn(move || {
+        while !cancel.is_cancelled() {
+            thread::sleep(TIP_POLL_INTERVAL);
+            if blockchain
+                .read()
+                .tip()
+                .is_some_and(|tip| tip.hash != previous_hash)
+            {
+                cancel.cancel();
+            }
+        }
+    })
+}
 
-    fn is_block_valid(&self, block: &Block, previous_block: &Block) -> bool {
-        if (block.id == previous_block.id + 1)
-            && block.hash.starts_with(DIFFICULTY_PREFIX)
-            && (block.previous_hash == previous_block.hash)
-            && (Block::hash(
-                block.id,
-                block.previous_hash.clone(),
-                block.timestamp,
-                block.data.clone(),
-                block.nonce,
-            ) == block.hash)
-        {
-            info!("Block #{} is valid", block.id);
-            return true;
+/// Cancels `shutdown`, and with it the round being mined, once Ctrl-C is pressed.
+fn shutdown_on_ctrl_c(shutdown: CancellationToken, round: Arc<Mutex<CancellationToken>>) {
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            info!("Shutting down, the block being mined is abandoned");
+            // Mining reports how close it got, see `save_abandoned`, once its threads stop
+            shutdown.cancel();
+            round.lock().unwrap().cancel();
         }
+    });
+}
 
-        warn!("Block #{} is invalid", block.id);
-        false
+/// Writes the attempt at the block `miner` abandoned, if any, to `path` so how close it came to
+/// being solved outlives the process.
+fn save_abandoned(miner: &Miner, path: &Path) -> Result<(), BoxError> {
+    if let Some(attempt) = miner.abandoned() {
+        info!(
+            "Block #{} was abandoned at hash {} (nonce {}), saved to {}",
+            attempt.id,
+            attempt.hash,
+            attempt.nonce,
+            path.display()
+        );
+        std::fs::write(path, serde_json::to_vec(&attempt)?)?;
     }
+    Ok(())
+}
 
-    fn is_chain_valid(&self) -> bool {
-        for block_index in 1..self.blocks.len() {
-            if !self.is_block_valid(&self.blocks[block_index], &self.blocks[block_index - 1]) {
-                warn!("Blockchain is invalid");
-                return false;
-            }
+/// Runs the demo miner: submits a few transfers between the demo `wallets`, the first of
+/// which collects the rewards, then mines them into a block on top of the current tip and
+/// announces it to `network`, if any. Stops after `count` blocks, if given, or once `shutdown`
+/// is cancelled, and flushes the chain. The token of the round being mined is kept in `round`
+/// so it can be cancelled from outside. Only fails if the chain can't be stored.
+fn mine_blocks(
+    blockchain: ChainHandle,
+    network: Option<Arc<Network>>,
+    miner: &Miner,
+    wallets: &[Wallet],
+    round: Arc<Mutex<CancellationToken>>,
+    shutdown: CancellationToken,
+    count: Option<u64>,
+) -> Result<(), BlockchainError> {
+    let broadcast = |message: &Message| {
+        if let Some(network) = &network {
+            network.broadcast(message, None);
         }
+    };
+    let record = |event: TelemetryEvent| {
+        if let Some(network) = &network {
+            network.telemetry().record(event);
+        }
+    };
+    let mut mined = 0;
 
-        info!("Blockchain is valid");
-        true
+    if blockchain.read().tip().is_none() {
+        let genesis_template = Blockchain::genesis_template();
+        if let Some(genesis_block) = miner.mine(genesis_template, &shutdown) {
+            match blockchain.try_add_block(genesis_block) {
+                Ok(()) => mined += 1,
+                Err(err) => warn!("Dropping our genesis block: {}", err),
+            }
+        }
     }
 
-    fn try_add_block(&mut self, block: Block) {
-        let previous_block = self
-            .blocks
-            .last()
-            .expect("should be at least one block in the blockchain");
+    let miner_address = wallets[0].address();
+    info!("Mining rewards go to {}", wallets[0].encoded_address());
 
-        if self.is_block_valid(&block, previous_block) {
-            self.blocks.push(block);
-            info!("Block was successfully added to the blockchain");
-        } else {
-            warn!(
-                "Block is invalid, cannot push block #{} to the blockchain",
-                block.id
+    while !shutdown.is_cancelled() && count.is_none_or(|count| mined < count) {
+        let template = {
+            let mut blockchain = blockchain.write();
+
+            let transactions: Vec<_> = DEMO_TRANSFERS
+                .iter()
+                .map(|&(sender, recipient, amount)| {
+                    let nonce = blockchain.next_nonce(&wallets[sender].address());
+                    wallets[sender].transfer(wallets[recipient].address(), amount, nonce)
+                })
+                .collect();
+            let announcement = Message::NewPackage {
+                transactions: transactions.clone(),
+            };
+            match blockchain.submit_package(transactions) {
+                Ok(()) => broadcast(&announcement),
+                Err(err) => warn!("Demo transfers were refused: {}", err),
+            }
+
+            let template = blockchain.block_template(&miner_address)?;
+            info!(
+                "Mining block #{} with {} of {} pending transaction(s)",
+                template.id,
+                template
+                    .transactions
+                    .iter()
+                    .filter(|transaction| !transaction.is_coinbase())
+                    .count(),
+                blockchain.mempool().len()
             );
+            template
+        };
+
+        let cancel = CancellationToken::new();
+        *round.lock().unwrap() = cancel.clone();
+        // Ctrl-C may have cancelled the previous round just before this one was registered
+        if shutdown.is_cancelled() {
+            cancel.cancel();
+        }
+        let watcher = watch_tip(
+            blockchain.clone(),
+            template.previous_hash.clone(),
+            cancel.clone(),
+        );
+        let started_at = Instant::now();
+        let new_block = miner.mine(template, &cancel);
+        cancel.cancel();
+        let _ = watcher.join();
+
+        let Some(new_block) = new_block else {
+            if !shutdown.is_cancelled() {
+                info!("Mining was cancelled, starting over on the current tip");
+                record(TelemetryEvent::MiningCancelled);
+            }
+            continue;
+        };
+        let announcement = Message::new_block(new_block.clone());
+
+        let mut blockchain = blockchain.write();
+        match blockchain.try_add_block(new_block) {
+            Ok(()) => {
+                mined += 1;
+                broadcast(&announcement);
+                record(TelemetryEvent::BlockMined {
+                    elapsed: started_at.elapsed(),
+                });
+            }
+            Err(BlockchainError::Io(err)) => return Err(BlockchainError::Io(err)),
+            Err(err) => warn!("Dropping the block we mined: {}", err),
+        }
+
+        if blockchain.height().is_multiple_of(10) {
+            if let Err(err) = blockchain.validate() {
+                warn!("Blockchain is invalid: {}", err);
+            }
+            if let Some(network) = &network {
+                info!("Block propagation: {}", network.propagation_report());
+            }
+            for wallet in wallets {
+                info!(
+                    "Balance of {}: {}",
+                    wallet.encoded_address(),
+                    blockchain.balance(&wallet.address())
+                );
+            }
+            blockchain.flush()?;
         }
     }
+
+    blockchain.flush()
 }
 
-fn main() {
+#[tokio::main]
+async fn main() -> Result<(), BoxError> {
+    // Colors are only meant for humans watching the log; keep piped output plain
+    if !std::io::stderr().is_terminal() {
+        colored::control::set_override(false);
+    }
+
     pretty_env_logger::formatted_timed_builder()
         .format(|buf, record| {
             writeln!(
@@ -149,24 +686,903 @@ fn main() {
         .filter(None, log::LevelFilter::Info)
         .init();
 
-    let mut blockchain = Blockchain::new();
-    blockchain.create_genesis();
+    let cli = Cli::parse();
+    let config = Config::from_env()?;
+    match cli.command {
+        Command::Run => return run(config).await,
+        Command::GenesisTool { timestamp, output } => return genesis_tool(timestamp, output),
+        Command::VerifyHeaders { path } => return verify_headers(path),
+        Command::Testkit {
+            command:
+                TestkitCommand::Fixtures {
+                    blocks,
+                    forks,
+                    fork_length,
+                    pattern,
+                    transactions,
+                    wallets,
+                    seed,
+                    genesis_timestamp,
+                    output,
+                    format,
+                },
+        } => {
+            let spec = FixtureSpec {
+                blocks,
+                forks,
+                fork_length,
+                pattern,
+                transactions,
+                wallets,
+                seed,
+                genesis_timestamp,
+            };
+            fixtures::generate(&spec)?.write(output, format)?;
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let chain = config.chain(cli.chain.as_deref())?;
+    match cli.command {
+        Command::Mine { count } => mine(chain, count).await,
+        Command::Validate => {
+            Blockchain::load(chain.storage_path())?.validate()?;
+            Ok(())
+        }
+        Command::Show { from, to, headers } => show(chain, from, to, headers),
+        Command::Export { path, format } => {
+            Blockchain::load(chain.storage_path())?.export(path, format)?;
+            Ok(())
+        }
+        Command::Import { path } => import(chain, path),
+        Command::ReplayDecisions { path } => {
+            replay_decisions(chain, path.unwrap_or_else(|| chain.decision_log_path()))
+        }
+        Command::Compare { rpc } => compare(chain, rpc.trim_end_matches('/')).await,
+        Command::Worker { node, address } => {
+            work_for(node.unwrap_or(chain.api_addr), address).await
+        }
+        Command::Stats {
+            command: StatsCommand::Watch { api },
+        } => watch_stats(api.unwrap_or(chain.api_addr)).await,
+        Command::Stats {
+            command: StatsCommand::Miners { last },
+        } => miner_distribution(chain, last),
+        Command::Mining {
+            command: MiningCommand::Report { address, since },
+        } => mining_report(chain, &address, since),
+        Command::Loadgen {
+            node,
+            rate,
+            wallets,
+            funding_blocks,
+        } => {
+            loadgen(
+                node.unwrap_or(chain.api_addr),
+                rate,
+                wallets,
+                funding_blocks,
+            )
+            .await
+        }
+        Command::Run
+        | Command::GenesisTool { .. }
+        | Command::VerifyHeaders { .. }
+        | Command::Testkit { .. } => {
+            unreachable!("handled above")
+        }
+    }
+}
+
+/// Fresh wallets on the network of chain `chain_id` for the demo miner to send between, one
+/// for each wallet [`DEMO_TRANSFERS`] refers to.
+fn demo_wallets(chain_id: &str) -> Vec<Wallet> {
+    (0..3).map(|_| Wallet::generate_on(chain_id)).collect()
+}
+
+/// Mines `count` blocks on the stored chain, or fewer if interrupted.
+async fn mine(chain: &ChainConfig, count: u64) -> Result<(), BoxError> {
+    let blockchain = ChainHandle::new(chain.load()?);
+    let wallets = demo_wallets(&chain.chain_id);
+    let round = Arc::new(Mutex::new(CancellationToken::new()));
+    let shutdown = CancellationToken::new();
+    shutdown_on_ctrl_c(shutdown.clone(), round.clone());
+
+    let miner = Arc::new(Miner::default());
+    {
+        let miner = miner.clone();
+        tokio::task::spawn_blocking(move || {
+            mine_blocks(
+                blockchain,
+                None,
+                &miner,
+                &wallets,
+                round,
+                shutdown,
+                Some(count),
+            )
+        })
+        .await??;
+    }
+    save_abandoned(&miner, &chain.abandoned_path())
+}
+
+/// Prints a summary of each stored block from `from` to `to`, and its transactions, along
+/// with their annotations.
+fn show(chain: &ChainConfig, from: u64, to: Option<u64>, headers: bool) -> Result<(), BoxError> {
+    let annotations = Annotations::open(chain.annotations_path())?;
+    let print_annotations = |hash: &str, indent: &str| {
+        for annotation in annotations.get(hash) {
+            match &annotation.note {
+                Some(note) => println!("{}[{}] {}", indent, annotation.label, note),
+                None => println!("{}[{}]", indent, annotation.label),
+            }
+        }
+    };
+    let in_range = |id: u64| id >= from && to.is_none_or(|to| id <= to);
+    let past_range = |id: u64| to.is_some_and(|to| id > to);
+
+    if headers {
+        for header in BlockReader::headers(chain.storage_path())? {
+            let header = header?;
+            if past_range(header.id) {
+                break;
+            }
+            if in_range(header.id) {
+                println!(
+                    "#{} {} at {}, difficulty {}",
+                    header.id, header.hash, header.timestamp, header.difficulty
+                );
+                print_annotations(&header.hash, "  ");
+            }
+        }
+        return Ok(());
+    }
+
+    for block in BlockReader::blocks(chain.storage_path())? {
+        let block = block?;
+        if past_range(block.id) {
+            break;
+        }
+        if !in_range(block.id) {
+            continue;
+        }
+        let miner = block
+            .miner_tag()
+            .map(|tag| format!(", mined by {}", tag))
+            .unwrap_or_default();
+        println!(
+            "#{} {} at {}, difficulty {}, {} transaction(s){}",
+            block.id,
+            block.hash,
+            block.timestamp,
+            block.difficulty,
+            block.transactions.len(),
+            miner
+        );
+        print_annotations(&block.hash, "  ");
+        for transaction in &block.transactions {
+            println!(
+                "  {} -> {}: {}",
+                transaction.sender, transaction.recipient, transaction.amount
+            );
+            print_annotations(&transaction.hash(), "    ");
+        }
+    }
+    Ok(())
+}
 
+/// Prints how many blocks each miner tag mined, most first, out of the stored blocks or the
+/// `last` ones. The genesis block has no miner.
+fn miner_distribution(chain: &ChainConfig, last: Option<usize>) -> Result<(), BoxError> {
+    let mut miners = Vec::new();
+    for block in BlockReader::blocks(chain.storage_path())? {
+        let block = block?;
+        if block.id > 0 {
+            miners.push(block.miner_tag().map(String::from));
+        }
+    }
+    let miners = &miners[miners.len().saturating_sub(last.unwrap_or(usize::MAX))..];
+
+    let mut counts: Vec<(Option<&str>, usize)> = Vec::new();
+    for miner in miners {
+        match counts.iter_mut().find(|(tag, _)| *tag == miner.as_deref()) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((miner.as_deref(), 1)),
+        }
+    }
+    counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+
+    println!("{} block(s)", miners.len());
+    for (tag, count) in counts {
+        println!(
+            "  {}: {} ({:.1}%)",
+            tag.unwrap_or("untagged"),
+            count,
+            100.0 * count as f64 / miners.len() as f64
+        );
+    }
+    Ok(())
+}
+
+/// Prints what the stored blocks from `since` on paid `address` in rewards, see
+/// [`MiningCommand::Report`]. Blocks a reorg disconnected are gone from the stored chain, so
+/// they're counted from the decision log, if the chain keeps one.
+fn mining_report(chain: &ChainConfig, address: &str, since: u64) -> Result<(), BoxError> {
+    let blocks = BlockReader::blocks(chain.storage_path())?.collect::<Result<Vec<_>, _>>()?;
+    let log_path = chain.decision_log_path();
+    let decisions = if log_path.exists() {
+        Some(decisions::read(log_path)?)
+    } else {
+        None
+    };
+
+    print!(
+        "{}",
+        MiningReport::new(address, since, &blocks, decisions.as_deref())
+    );
+    Ok(())
+}
+
+/// Stores the chain in the snapshot at `path` in place of ours if it has more work.
+fn import(chain: &ChainConfig, path: PathBuf) -> Result<(), BoxError> {
+    let imported = Blockchain::import(path)?;
+    let mut blockchain = Blockchain::load(chain.storage_path())?;
+
+    // A snapshot extending the stored chain only needs its new blocks connected
+    let stored = blockchain.blocks().len();
+    let extends_stored = imported.blocks().len() > stored
+        && imported
+            .blocks()
+            .iter()
+            .zip(blockchain.blocks())
+            .all(|(theirs, ours)| theirs.hash == ours.hash);
+    if extends_stored {
+        let blocks = imported.blocks()[stored..].to_vec();
+        let connected = blocks.len();
+        blockchain.try_add_blocks(blocks)?;
+        info!("Stored {} imported block(s)", connected);
+        blockchain.flush()?;
+        return Ok(());
+    }
+
+    match blockchain.replace_chain(imported.blocks().to_vec())? {
+        ReplaceChainOutcome::Replaced { connected, .. } => {
+            info!("Stored {} imported block(s)", connected)
+        }
+        ReplaceChainOutcome::NotHeavier => {
+            warn!("The stored chain has at least as much work as the snapshot, keeping it")
+        }
+    }
+    blockchain.flush()?;
+    Ok(())
+}
+
+/// Fetches nonce ranges from the node serving its API on `node`, mines them and submits the
+/// solutions, until Ctrl-C.
+async fn work_for(node: SocketAddr, address: String) -> Result<(), BoxError> {
+    let client = reqwest::Client::new();
+    let miner = Arc::new(Miner::default());
+    let cancel = CancellationToken::new();
+    shutdown_on_ctrl_c(cancel.clone(), Arc::new(Mutex::new(cancel.clone())));
+
+    while !cancel.is_cancelled() {
+        solve_work(&client, node, &address, &miner, &cancel).await?;
+    }
+    Ok(())
+}
+
+/// Mines one range of nonces handed out by the node serving its API on `node`, paying
+/// `address`, and submits the solution if there is one. Returns whether the node accepted it.
+async fn solve_work(
+    client: &reqwest::Client,
+    node: SocketAddr,
+    address: &str,
+    miner: &Arc<Miner>,
+    cancel: &CancellationToken,
+) -> Result<bool, BoxError> {
+    let url = format!("http://{}/work", node);
+    let work: Work = client
+        .get(&url)
+        .query(&[("address", address)])
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    debug!(
+        "Mining nonces {}..{} of block #{}",
+        work.nonce_start, work.nonce_end, work.id
+    );
+
+    let (miner, cancel) = (miner.clone(), cancel.clone());
+    let (work, nonce) = tokio::task::spawn_blocking(move || {
+        let nonce = miner.mine_work(&work, &cancel);
+        (work, nonce)
+    })
+    .await?;
+    let Some(nonce) = nonce else {
+        return Ok(false);
+    };
+
+    let response = client
+        .post(&url)
+        .json(&json!({ "work_id": work.work_id, "nonce": nonce }))
+        .send()
+        .await?;
+    if response.status().is_success() {
+        info!("Solved block #{} with nonce {}", work.id, nonce);
+        Ok(true)
+    } else {
+        warn!(
+            "Node refused our solution for block #{}: {}",
+            work.id,
+            response.text().await?
+        );
+        Ok(false)
+    }
+}
+
+/// What `address` can spend according to the node serving its API on `node`, and the nonce
+/// its next transaction has to carry.
+async fn available(
+    client: &reqwest::Client,
+    node: SocketAddr,
+    address: &str,
+) -> Result<(u64, u64), BoxError> {
+    #[derive(Deserialize)]
+    struct Balance {
+        available: u64,
+        next_nonce: u64,
+    }
+
+    let balance: Balance = client
+        .get(format!("http://{}/balances/{}", node, address))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok((balance.available, balance.next_nonce))
+}
+
+/// Submits `transaction` to the node serving its API on `node`. A refusal isn't an error, it
+/// comes back as the reason the node gave.
+async fn submit(
+    client: &reqwest::Client,
+    node: SocketAddr,
+    transaction: &Transaction,
+) -> Result<Result<(), String>, BoxError> {
+    let response = client
+        .post(format!("http://{}/transactions", node))
+        .json(transaction)
+        .send()
+        .await?;
+    if response.status().is_success() {
+        Ok(Ok(()))
+    } else {
+        Ok(Err(response.text().await?))
+    }
+}
+
+/// Funds `wallet_count` fresh wallets by mining `funding_blocks` blocks for the node serving
+/// its API on `node`, then submits random transfers between them at `rate` per second until
+/// Ctrl-C. Transfers only come from wallets the node says can afford them; the others wait for
+/// the transfers they received to confirm.
+async fn loadgen(
+    node: SocketAddr,
+    rate: f64,
+    wallet_count: usize,
+    funding_blocks: u64,
+) -> Result<(), BoxError> {
+    if !(rate.is_finite() && rate > 0.0) {
+        return Err("the rate must be a positive number".into());
+    }
+    if wallet_count < 2 {
+        return Err("at least 2 wallets are needed".into());
+    }
+
+    let client = reqwest::Client::new();
+    let wallets: Vec<Wallet> = (0..wallet_count).map(|_| Wallet::generate()).collect();
+    let cancel = CancellationToken::new();
+    shutdown_on_ctrl_c(cancel.clone(), Arc::new(Mutex::new(cancel.clone())));
+
+    let funder = wallets[0].address();
+    info!("Mining {} block(s) paying {}", funding_blocks, funder);
+    let miner = Arc::new(Miner::default());
+    let mut funded = 0;
+    while funded < funding_blocks {
+        if cancel.is_cancelled() {
+            return Ok(());
+        }
+        if solve_work(&client, node, &funder, &miner, &cancel).await? {
+            funded += 1;
+        }
+    }
+
+    let (funds, first_nonce) = available(&client, node, &funder).await?;
+    let share = funds / wallet_count as u64;
+    for (nonce, wallet) in (first_nonce..).zip(&wallets[1..]) {
+        let transfer = wallets[0].transfer(wallet.address(), share, nonce);
+        submit(&client, node, &transfer)
+            .await?
+            .map_err(|err| format!("funding transfer was refused: {}", err))?;
+    }
+    info!(
+        "Sent {} to each of {} wallet(s), submitting {} transaction(s) per second",
+        share,
+        wallet_count - 1,
+        rate
+    );
+
+    let mut rng = StdRng::from_entropy();
+    let mut spendable = vec![0; wallet_count];
+    let mut nonces = vec![0; wallet_count];
+    let mut ticks = tokio::time::interval(Duration::from_secs_f64(1.0 / rate));
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut refreshed_at: Option<Instant> = None;
+    let mut reported_at = Instant::now();
+    let (mut submitted, mut refused, mut starved) = (0, 0, 0);
+
+    while !cancel.is_cancelled() {
+        ticks.tick().await;
+
+        if refreshed_at.is_none_or(|at| at.elapsed() >= LOAD_BALANCE_INTERVAL) {
+            for (index, wallet) in wallets.iter().enumerate() {
+                (spendable[index], nonces[index]) =
+                    available(&client, node, &wallet.address()).await?;
+            }
+            refreshed_at = Some(Instant::now());
+        }
+
+        let senders: Vec<usize> = (0..wallet_count)
+            .filter(|&wallet| spendable[wallet] > 0)
+            .collect();
+        if senders.is_empty() {
+            starved += 1;
+        } else {
+            let sender = senders[rng.gen_range(0..senders.len())];
+            let recipient = (sender + rng.gen_range(1..wallet_count)) % wallet_count;
+            let amount = rng.gen_range(1..=spendable[sender].min(MAX_LOAD_AMOUNT));
+            let transfer =
+                wallets[sender].transfer(wallets[recipient].address(), amount, nonces[sender]);
+
+            match submit(&client, node, &transfer).await? {
+                Ok(()) => {
+                    submitted += 1;
+                    spendable[sender] -= amount;
+                    nonces[sender] += 1;
+                }
+                Err(err) => {
+                    debug!("Transfer {} was refused: {}", transfer.hash(), err);
+                    refused += 1;
+                }
+            }
+        }
+
+        if reported_at.elapsed() >= LOAD_REPORT_INTERVAL {
+            info!(
+                "Submitted {} transaction(s) ({:.1}/s), {} refused, {} skipped for lack of funds",
+                submitted,
+                submitted as f64 / reported_at.elapsed().as_secs_f64(),
+                refused,
+                starved
+            );
+            reported_at = Instant::now();
+            (submitted, refused, starved) = (0, 0, 0);
+        }
+    }
+    Ok(())
+}
+
+/// Hash of block `id` of the node serving its API at `rpc`.
+async fn remote_hash(client: &reqwest::Client, rpc: &str, id: u64) -> Result<String, BoxError> {
+    let header: BlockHeader = client
+        .get(format!("{}/blocks/{}", rpc, id))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    Ok(header.hash)
+}
+
+/// Finds the first block the stored chain and the one of the node serving its API at `rpc`
+/// disagree on, bisecting over the heights both have so only a few blocks are fetched.
+async fn compare(chain: &ChainConfig, rpc: &str) -> Result<(), BoxError> {
+    let hashes = BlockReader::headers(chain.storage_path())?
+        .map(|header| header.map(|header| header.hash))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let client = reqwest::Client::new();
+    let remote_tip: BlockHeader = client
+        .get(format!("{}/tip", rpc))
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+    let remote_height = remote_tip.id + 1;
+    println!(
+        "Local height {}, remote height {}",
+        hashes.len(),
+        remote_height
+    );
+
+    // Both chains agree below `agreed` and disagree at `disagreed`, if it's below `common`
+    let common = (hashes.len() as u64).min(remote_height);
+    let (mut agreed, mut disagreed) = (0, common);
+    while agreed < disagreed {
+        let middle = agreed + (disagreed - agreed) / 2;
+        if remote_hash(&client, rpc, middle).await? == hashes[middle as usize] {
+            agreed = middle + 1;
+        } else {
+            disagreed = middle;
+        }
+    }
+
+    if disagreed < common {
+        let remote = remote_hash(&client, rpc, disagreed).await?;
+        println!(
+            "Chains diverge at block #{}: {} here, {} there",
+            disagreed, hashes[disagreed as usize], remote
+        );
+        return Err(format!("chains diverge at block #{}", disagreed).into());
+    }
+
+    println!("Chains agree on their first {} block(s)", common);
+    match (hashes.len() as u64).cmp(&remote_height) {
+        Ordering::Less => println!(
+            "The remote node is {} block(s) ahead",
+            remote_height - common
+        ),
+        Ordering::Greater => println!("We are {} block(s) ahead", hashes.len() as u64 - common),
+        Ordering::Equal => {}
+    }
+    Ok(())
+}
+
+/// Polls the stats of the node serving its API on `api` and redraws them every sample, until
+/// Ctrl-C.
+async fn watch_stats(api: SocketAddr) -> Result<(), BoxError> {
+    let client = reqwest::Client::new();
+    let (url, alerts_url) = (
+        format!("http://{}/stats", api),
+        format!("http://{}/alerts", api),
+    );
+    let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+        }
+
+        let samples: Vec<StatsSample> = client
+            .get(&url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let alerts: Vec<Alert> = client
+            .get(&alerts_url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let banner = if alerts.is_empty() {
+            String::new()
+        } else {
+            format!("{}\n\n", alerts::banner(&alerts))
+        };
+        // Clear the screen and draw from the top left corner
+        print!("\x1b[2J\x1b[H{}{}", banner, render_stats(api, &samples));
+        std::io::stdout().flush()?;
+    }
+}
+
+/// The latest value of every stat next to a sparkline of its recent history.
+fn render_stats(api: SocketAddr, samples: &[StatsSample]) -> String {
+    let Some(latest) = samples.last() else {
+        return format!("Node {} has no stats yet\n", api);
+    };
+    let recent = &samples[samples.len().saturating_sub(SPARKLINE_WIDTH)..];
+    let series =
+        |value: fn(&StatsSample) -> f64| sparkline(&recent.iter().map(value).collect::<Vec<_>>());
+
+    let block_interval = match latest.block_interval_secs {
+        Some(secs) => format!("{:.1}s", secs),
+        None => String::from("-"),
+    };
+    let rows = [
+        (
+            "hashrate",
+            format!("{:.0} H/s", latest.hashrate),
+            series(|sample| sample.hashrate),
+        ),
+        (
+            "block interval",
+            block_interval,
+            series(|sample| sample.block_interval_secs.unwrap_or(0.0)),
+        ),
+        (
+            "mempool",
+            latest.mempool_size.to_string(),
+            series(|sample| sample.mempool_size as f64),
+        ),
+        (
+            "peers",
+            latest.peer_count.to_string(),
+            series(|sample| sample.peer_count as f64),
+        ),
+        (
+            "alerts",
+            latest.alert_count.to_string(),
+            series(|sample| sample.alert_count as f64),
+        ),
+    ];
+
+    let mut rendered = format!(
+        "Node {} at height {}, last {}s\n\n",
+        api,
+        latest.height,
+        recent.len()
+    );
+    for (label, value, sparkline) in rows {
+        rendered += &format!("{:<16}{:>14}  {}\n", label, value, sparkline);
+    }
+    rendered
+}
+
+/// Renders `values` as bars scaled between their minimum and maximum.
+fn sparkline(values: &[f64]) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let range = (max - min).max(f64::EPSILON);
+
+    values
+        .iter()
+        .map(|value| BARS[((value - min) / range * (BARS.len() - 1) as f64).round() as usize])
+        .collect()
+}
+
+/// One-line summary of the input of a decision.
+fn describe_input(input: &Input) -> String {
+    match input {
+        Input::Start { height, .. } => format!("start at height {}", height),
+        Input::Block { block } => format!("block #{} {}", block.id, block.hash),
+        Input::Blocks { blocks } => format!(
+            "{} block(s) from #{}",
+            blocks.len(),
+            blocks.first().map_or(0, |block| block.id)
+        ),
+        Input::ReplaceChain { fork, blocks } => {
+            format!("chain forking at #{} with {} block(s)", fork, blocks.len())
+        }
+        Input::Transaction { transaction } => format!("transaction {}", transaction.hash()),
+        Input::Package { transactions } => {
+            format!("package of {} transaction(s)", transactions.len())
+        }
+        Input::Expired { transactions } => {
+            format!("expiry of {} transaction(s)", transactions.len())
+        }
+    }
+}
+
+/// Replays the decision log at `path` on top of the stored chain, see [`decisions::replay`].
+fn replay_decisions(chain: &ChainConfig, path: PathBuf) -> Result<(), BoxError> {
+    let (mut replayed, mut diverged) = (0, 0);
+    decisions::replay(
+        path,
+        chain.storage_path(),
+        |decision: &Decision, outcome| {
+            replayed += 1;
+            println!(
+                "{} {}: {}",
+                decision.at,
+                describe_input(&decision.input),
+                decision.outcome
+            );
+            if outcome != decision.outcome {
+                diverged += 1;
+                println!("  replays differently: {}", outcome);
+            }
+        },
+    )?;
+
+    println!(
+        "Replayed {} decision(s), {} of them differently",
+        replayed, diverged
+    );
+    if diverged > 0 {
+        return Err(format!("{} decision(s) replay differently", diverged).into());
+    }
+    Ok(())
+}
+
+/// Verifies the header chain in `path` and prints its tip.
+fn verify_headers(path: PathBuf) -> Result<(), BoxError> {
+    let headers: Vec<BlockHeader> = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    let relay = bridge::verify_headers(headers)?;
+
+    println!(
+        "{} valid header(s), tip #{} {}, work {}",
+        relay.height(),
+        relay.tip().id,
+        relay.tip().hash,
+        relay.work()
+    );
+    Ok(())
+}
+
+/// Mines the genesis block for `timestamp` and writes it to `output`, or prints it.
+fn genesis_tool(timestamp: i64, output: Option<PathBuf>) -> Result<(), BoxError> {
+    let genesis_block = miner::mine_deterministic(Blockchain::genesis_template(), timestamp);
+    let encoded = serde_json::to_string_pretty(&genesis_block)?;
+    info!(
+        "Genesis block for timestamp {}: {} (nonce {})",
+        timestamp, genesis_block.hash, genesis_block.nonce
+    );
+
+    match output {
+        Some(path) => std::fs::write(path, encoded + "\n")?,
+        None => println!("{}", encoded),
+    }
+    Ok(())
+}
+
+/// Evicts transactions pending for `ttl` or longer from the mempool, forever.
+async fn expire_transactions(blockchain: ChainHandle, ttl: Duration) {
+    let mut interval = tokio::time::interval(EXPIRY_INTERVAL);
     loop {
-        let previous_block = blockchain
-            .blocks
-            .last()
-            .expect("should be at least one block in the blockchain");
-        let new_block = Block::new(
-            previous_block.id + 1,
-            previous_block.hash.clone(),
-            String::from("Hello"),
+        interval.tick().await;
+        blockchain.expire_transactions(ttl);
+    }
+}
+
+/// Runs every configured chain until Ctrl-C, each as its own task on the shared runtime. Fails
+/// as soon as one of them does.
+async fn run(config: Config) -> Result<(), BoxError> {
+    let mut chains = JoinSet::new();
+    for chain in config.chains {
+        chains.spawn(run_chain(chain, config.settings));
+    }
+
+    while let Some(result) = chains.join_next().await {
+        result??;
+    }
+    Ok(())
+}
+
+/// Runs a full node of `chain` until Ctrl-C: syncs with the peers, serves the HTTP API and
+/// mines.
+async fn run_chain(chain: ChainConfig, settings: Settings) -> Result<(), BoxError> {
+    info!("Running chain {}", chain.chain_id);
+    let mut blockchain = chain.load()?;
+    if chain.decision_log {
+        let log = DecisionLog::open(chain.decision_log_path())?;
+        info!("Recording decisions to {}", log.path().display());
+        blockchain.set_decision_log(log);
+    }
+    let fresh = blockchain.tip().is_none();
+    let blockchain = ChainHandle::new(blockchain);
+
+    let round = Arc::new(Mutex::new(CancellationToken::new()));
+    let shutdown = CancellationToken::new();
+    shutdown_on_ctrl_c(shutdown.clone(), round.clone());
+
+    let annotations = Annotations::open(chain.annotations_path())?;
+    let capture = chain
+        .capture_dir
+        .as_ref()
+        .map(MisbehaviorCapture::open)
+        .transpose()?;
+    let services = if chain.blocks_only {
+        network::SERVICE_BLOCKS
+    } else {
+        network::ALL_SERVICES
+    };
+    let network = Network::new(
+        chain.chain_id.clone(),
+        chain.listen_addr,
+        services,
+        blockchain.clone(),
+        capture,
+        Some(chain.anchors_path()),
+        !chain.no_rebroadcast,
+    );
+    network.start(chain.peers.clone()).await?;
+
+    if fresh && !chain.peers.is_empty() {
+        info!("Waiting for peers to share their chain");
+        tokio::time::sleep(Duration::from_secs(INITIAL_SYNC_SECS)).await;
+    }
+
+    let mut monitor = AlertMonitor::new(
+        blockchain.clone(),
+        network.clone(),
+        PathBuf::from(chain.storage_path()),
+    );
+    monitor.check();
+    let active = network.alerts().active();
+    if !active.is_empty() {
+        warn!("Starting with alerts up:\n{}", alerts::banner(&active));
+    }
+    tokio::spawn(monitor.run());
+
+    let watchdog = Watchdog::new(blockchain.clone(), network.clone(), settings.stall_after);
+    let stalled_round = round.clone();
+    tokio::spawn(watchdog.run(move || {
+        if settings.restart_miner_on_stall {
+            info!("Restarting the miner");
+            stalled_round.lock().unwrap().cancel();
+        }
+    }));
+
+    tokio::spawn(expire_transactions(
+        blockchain.clone(),
+        settings.mempool_ttl,
+    ));
+
+    if let Some(url) = &chain.telemetry_url {
+        tokio::spawn(
+            network
+                .telemetry()
+                .export(chain.chain_id.clone(), url.clone()),
         );
+    }
 
-        blockchain.try_add_block(new_block);
+    let miner = Arc::new(Miner::default());
+    let stats = Arc::new(StatsHistory::new());
+    tokio::spawn(
+        stats
+            .clone()
+            .run(blockchain.clone(), network.clone(), miner.clone()),
+    );
 
-        if blockchain.blocks.len() % 10 == 0 {
-            blockchain.is_chain_valid();
+    let abandoned_path = chain.abandoned_path();
+    let mining = {
+        let (blockchain, network, miner) = (blockchain.clone(), network.clone(), miner.clone());
+        let wallets = demo_wallets(&chain.chain_id);
+        tokio::task::spawn_blocking(move || {
+            mine_blocks(
+                blockchain,
+                Some(network),
+                &miner,
+                &wallets,
+                round,
+                shutdown,
+                None,
+            )
+        })
+    };
+
+    let light_wallets = {
+        let (blockchain, network) = (blockchain.clone(), network.clone());
+        async move {
+            match chain.electrum_addr {
+                Some(addr) => electrum::serve(addr, blockchain, network).await,
+                None => std::future::pending().await,
+            }
         }
+    };
+
+    tokio::select! {
+        result = mining => result??,
+        result = light_wallets => result?,
+        result = api::serve(
+            chain.api_addr,
+            blockchain,
+            network,
+            stats,
+            annotations,
+            chain.public_api,
+        ) => result?,
     }
+    save_abandoned(&miner, &abandoned_path)
 }
@@ -0,0 +1,402 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+use log::info;
+
+use crate::error::BlockchainError;
+use crate::transaction::Transaction;
+
+/// Most pending transactions a pending transaction may depend on, itself included.
+pub const MAX_ANCESTORS: usize = 25;
+/// Most pending transactions that may depend on a pending transaction, itself included.
+pub const MAX_DESCENDANTS: usize = 25;
+/// Most bytes, see [`Transaction::size`], a transaction and its pending ancestors may take up.
+pub const MAX_ANCESTOR_SIZE: usize = 101_000;
+/// Most bytes a transaction and its pending descendants may take up.
+pub const MAX_DESCENDANT_SIZE: usize = 101_000;
+
+/// Pending transactions waiting to be included in a block, oldest first.
+///
+/// A pending transaction may depend on every earlier one paying its sender or sent by its
+/// sender, since a block can only include it once those are applied. The pool keeps track of these chains and bounds
+/// their length and size, so no unconfirmed chain can grow without limit.
+#[derive(Default)]
+pub struct Mempool {
+    transactions: VecDeque<Transaction>,
+    entries: HashMap<String, Entry>,
+}
+
+/// Dependencies of a pending transaction on the others, by hash.
+struct Entry {
+    added_at: Instant,
+    size: usize,
+    ancestors: HashSet<String>,
+    descendants: HashSet<String>,
+    /// What the mempool policies noted when accepting the transaction.
+    notes: Vec<String>,
+}
+
+/// Counts and sizes of the dependency chains a pending transaction is part of, each including
+/// the transaction itself.
+pub struct ChainStats {
+    pub ancestor_count: usize,
+    pub ancestor_size: usize,
+    pub descendant_count: usize,
+    pub descendant_size: usize,
+}
+
+impl Mempool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues a transaction unless it's invalid, already queued or would exceed the ancestor
+    /// or descendant limits. Whether its sender can afford it is up to the chain, see
+    /// [`crate::Blockchain::submit_transaction`].
+    pub fn add(&mut self, transaction: Transaction) -> Result<(), BlockchainError> {
+        let hash = transaction.hash();
+
+        if !transaction.is_valid() {
+            return Err(BlockchainError::InvalidTransaction { hash });
+        }
+
+        if self.entries.contains_key(&hash) {
+            return Err(BlockchainError::AlreadyPending { hash });
+        }
+
+        let size = transaction.size();
+        let mut ancestors = HashSet::new();
+        for parent in &self.transactions {
+            if parent.recipient != transaction.sender && parent.sender != transaction.sender {
+                continue;
+            }
+            let parent_hash = parent.hash();
+            if let Some(entry) = self.entries.get(&parent_hash) {
+                ancestors.extend(entry.ancestors.iter().cloned());
+            }
+            ancestors.insert(parent_hash);
+        }
+
+        let ancestor_size = size + self.total_size(&ancestors);
+        if ancestors.len() + 1 > MAX_ANCESTORS || ancestor_size > MAX_ANCESTOR_SIZE {
+            return Err(BlockchainError::TooManyAncestors {
+                hash,
+                count: ancestors.len() + 1,
+                size: ancestor_size,
+            });
+        }
+
+        for ancestor in &ancestors {
+            if self.stats(ancestor).is_some_and(|stats| {
+                stats.descendant_count + 1 > MAX_DESCENDANTS
+                    || stats.descendant_size + size > MAX_DESCENDANT_SIZE
+            }) {
+                return Err(BlockchainError::TooManyDescendants {
+                    hash,
+                    ancestor: ancestor.clone(),
+                });
+            }
+        }
+
+        for ancestor in &ancestors {
+            if let Some(entry) = self.entries.get_mut(ancestor) {
+                entry.descendants.insert(hash.clone());
+            }
+        }
+        self.entries.insert(
+            hash.clone(),
+            Entry {
+                added_at: Instant::now(),
+                size,
+                ancestors,
+                descendants: HashSet::new(),
+                notes: Vec::new(),
+            },
+        );
+        self.transactions.push_back(transaction);
+        info!("Transaction {} was added to the mempool", hash);
+        Ok(())
+    }
+
+    /// Queues all of `transactions` in order, or none of them if any can't be queued.
+    pub fn add_package(&mut self, transactions: Vec<Transaction>) -> Result<(), BlockchainError> {
+        let mut added = Vec::with_capacity(transactions.len());
+
+        for transaction in transactions {
+            let queued = transaction.clone();
+            if let Err(err) = self.add(transaction) {
+                self.remove(&added);
+                return Err(err);
+            }
+            added.push(queued);
+        }
+
+        Ok(())
+    }
+
+    /// Returns up to `max` of the oldest pending transactions without removing them; they stay
+    /// queued until a block including them is added to the chain.
+    pub fn select(&self, max: usize) -> Vec<Transaction> {
+        self.transactions.iter().take(max).cloned().collect()
+    }
+
+    /// Drops the transactions that were just confirmed in a block.
+    pub fn remove(&mut self, transactions: &[Transaction]) {
+        let confirmed: HashSet<String> = transactions.iter().map(Transaction::hash).collect();
+
+        self.transactions
+            .retain(|transaction| !confirmed.contains(&transaction.hash()));
+        self.entries.retain(|hash, _| !confirmed.contains(hash));
+        for entry in self.entries.values_mut() {
+            entry.ancestors.retain(|hash| !confirmed.contains(hash));
+            entry.descendants.retain(|hash| !confirmed.contains(hash));
+        }
+    }
+
+    /// Evicts the transactions that have been pending for `ttl` or longer, along with their
+    /// pending descendants, which can't be mined without them. Returns the evicted
+    /// transactions, oldest first.
+    pub fn expire(&mut self, ttl: Duration) -> Vec<Transaction> {
+        let expired: HashSet<&String> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.added_at.elapsed() >= ttl)
+            .flat_map(|(hash, entry)| std::iter::once(hash).chain(&entry.descendants))
+            .collect();
+        let transactions: Vec<Transaction> = self
+            .transactions
+            .iter()
+            .filter(|transaction| expired.contains(&transaction.hash()))
+            .cloned()
+            .collect();
+
+        self.remove(&transactions);
+        transactions
+    }
+
+    /// Empties the pool, returning its transactions oldest first.
+    pub fn drain(&mut self) -> Vec<Transaction> {
+        self.entries.clear();
+        self.transactions.drain(..).collect()
+    }
+
+    /// The pending transactions, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &Transaction> {
+        self.transactions.iter()
+    }
+
+    pub fn contains(&self, hash: &str) -> bool {
+        self.entries.contains_key(hash)
+    }
+
+    /// Notes the mempool policies attached to the pending transaction `hash`, see
+    /// [`crate::policy::Verdict::Annotate`].
+    pub fn notes(&self, hash: &str) -> &[String] {
+        self.entries
+            .get(hash)
+            .map_or(&[], |entry| entry.notes.as_slice())
+    }
+
+    pub fn annotate(&mut self, hash: &str, notes: Vec<String>) {
+        if let Some(entry) = self.entries.get_mut(hash) {
+            entry.notes.extend(notes);
+        }
+    }
+
+    /// Dependency chains of the pending transaction `hash`.
+    pub fn stats(&self, hash: &str) -> Option<ChainStats> {
+        let entry = self.entries.get(hash)?;
+
+        Some(ChainStats {
+            ancestor_count: entry.ancestors.len() + 1,
+            ancestor_size: entry.size + self.total_size(&entry.ancestors),
+            descendant_count: entry.descendants.len() + 1,
+            descendant_size: entry.size + self.total_size(&entry.descendants),
+        })
+    }
+
+    fn total_size(&self, hashes: &HashSet<String>) -> usize {
+        hashes
+            .iter()
+            .filter_map(|hash| self.entries.get(hash))
+            .map(|entry| entry.size)
+            .sum()
+    }
+
+    /// Total amount the address is already spending in pending transactions.
+    pub fn pending_outgoing(&self, address: &str) -> u64 {
+        self.transactions
+            .iter()
+            .filter(|transaction| transaction.sender == address)
+            .map(|transaction| transaction.amount)
+            .sum()
+    }
+
+    /// Highest nonce of the transactions the address has pending, if it has any.
+    pub fn last_nonce(&self, address: &str) -> Option<u64> {
+        self.transactions
+            .iter()
+            .filter(|transaction| transaction.sender == address)
+            .map(|transaction| transaction.nonce)
+            .max()
+    }
+
+    pub fn len(&self) -> usize {
+        self.transactions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.transactions.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wallet::{Wallet, DEFAULT_NETWORK};
+
+    fn wallet(seed: u8) -> Wallet {
+        Wallet::from_secret_key(DEFAULT_NETWORK, [seed; 32])
+    }
+
+    /// An address `len` characters long, for transactions of about that many bytes.
+    fn long_address(len: usize) -> String {
+        "f".repeat(len)
+    }
+
+    #[test]
+    fn mempool_expires_stale_transactions_and_their_descendants() {
+        let (a, b, c, d) = (wallet(1), wallet(2), wallet(3), wallet(4));
+        let parent = a.transfer(b.address(), 10, 0);
+        let child = b.transfer(c.address(), 5, 0);
+        let unrelated = d.transfer(long_address(64), 1, 0);
+        let mut mempool = Mempool::new();
+        for transaction in [&parent, &child, &unrelated] {
+            mempool.add(transaction.clone()).unwrap();
+        }
+
+        let ttl = Duration::from_secs(60);
+        assert!(mempool.expire(ttl).is_empty());
+        assert_eq!(mempool.len(), 3);
+
+        // Only the parent is stale, but its child can't be mined without it
+        let stale = Instant::now().checked_sub(2 * ttl).unwrap();
+        mempool.entries.get_mut(&parent.hash()).unwrap().added_at = stale;
+        let expired: Vec<String> = mempool.expire(ttl).iter().map(Transaction::hash).collect();
+        assert_eq!(expired, [parent.hash(), child.hash()]);
+        assert_eq!(mempool.len(), 1);
+        assert!(mempool.contains(&unrelated.hash()));
+
+        assert_eq!(mempool.expire(Duration::ZERO).len(), 1);
+        assert!(mempool.is_empty());
+    }
+
+    #[test]
+    fn chains_of_pending_transactions_are_bounded_in_length() {
+        let (sender, recipient) = (wallet(1), wallet(2).address());
+        let mut mempool = Mempool::new();
+        for nonce in 0..MAX_ANCESTORS as u64 {
+            mempool
+                .add(sender.transfer(recipient.clone(), 1, nonce))
+                .unwrap();
+        }
+
+        let too_long = sender.transfer(recipient, 1, MAX_ANCESTORS as u64);
+        assert!(matches!(
+            mempool.add(too_long),
+            Err(BlockchainError::TooManyAncestors { count, .. }) if count == MAX_ANCESTORS + 1
+        ));
+        assert_eq!(mempool.len(), MAX_ANCESTORS);
+    }
+
+    #[test]
+    fn pending_transactions_are_bounded_in_descendants() {
+        let (a, b, c, d) = (wallet(1), wallet(2), wallet(3), wallet(4));
+        let mut mempool = Mempool::new();
+        let root = a.transfer(b.address(), 10, 0);
+        mempool.add(root.clone()).unwrap();
+
+        // Two branches off the root, neither long enough to hit the ancestor limit
+        mempool.add(b.transfer(c.address(), 5, 0)).unwrap();
+        for nonce in 0..12 {
+            mempool.add(c.transfer(long_address(64), 1, nonce)).unwrap();
+        }
+        mempool.add(a.transfer(d.address(), 5, 1)).unwrap();
+        for nonce in 0..10 {
+            mempool.add(d.transfer(long_address(64), 1, nonce)).unwrap();
+        }
+        assert_eq!(
+            mempool
+                .stats(&root.hash())
+                .map(|stats| stats.descendant_count),
+            Some(MAX_DESCENDANTS)
+        );
+
+        let one_too_many = d.transfer(long_address(64), 1, 10);
+        assert!(matches!(
+            mempool.add(one_too_many),
+            Err(BlockchainError::TooManyDescendants { ancestor, .. }) if ancestor == root.hash()
+        ));
+    }
+
+    #[test]
+    fn chains_of_pending_transactions_are_bounded_in_size() {
+        // Two of these fit a chain, three don't
+        let (a, b) = (wallet(1), wallet(2));
+        let large = |wallet: &Wallet, nonce| wallet.transfer(long_address(50_000), 1, nonce);
+        let mut mempool = Mempool::new();
+        mempool.add(large(&a, 0)).unwrap();
+        mempool.add(large(&a, 1)).unwrap();
+        assert!(matches!(
+            mempool.add(large(&a, 2)),
+            Err(BlockchainError::TooManyAncestors { count: 3, size, .. }) if size > MAX_ANCESTOR_SIZE
+        ));
+
+        // Descendants in branches of their own still add up for the root
+        let mut mempool = Mempool::new();
+        let root = a.transfer(b.address(), 10, 0);
+        mempool.add(root.clone()).unwrap();
+        mempool.add(b.transfer(long_address(50_300), 1, 0)).unwrap();
+        assert!(matches!(
+            mempool.add(a.transfer(long_address(50_300), 1, 1)),
+            Err(BlockchainError::TooManyDescendants { ancestor, .. }) if ancestor == root.hash()
+        ));
+        assert_eq!(mempool.len(), 2);
+    }
+
+    #[test]
+    fn failed_packages_leave_the_mempool_as_it_was() {
+        let (sender, recipient) = (wallet(1), wallet(2).address());
+        let mut mempool = Mempool::new();
+        let first = sender.transfer(recipient.clone(), 1, 0);
+        mempool.add(first.clone()).unwrap();
+        for nonce in 1..MAX_ANCESTORS as u64 - 2 {
+            mempool
+                .add(sender.transfer(recipient.clone(), 1, nonce))
+                .unwrap();
+        }
+
+        // The last transaction of the package is one past the ancestor limit
+        let package: Vec<_> = (MAX_ANCESTORS as u64 - 2..=MAX_ANCESTORS as u64)
+            .map(|nonce| sender.transfer(recipient.clone(), 1, nonce))
+            .collect();
+        assert!(matches!(
+            mempool.add_package(package.clone()),
+            Err(BlockchainError::TooManyAncestors { .. })
+        ));
+        assert_eq!(mempool.len(), MAX_ANCESTORS - 2);
+        assert!(package
+            .iter()
+            .all(|transaction| !mempool.contains(&transaction.hash())));
+        assert_eq!(
+            mempool
+                .stats(&first.hash())
+                .map(|stats| stats.descendant_count),
+            Some(MAX_ANCESTORS - 2)
+        );
+
+        // Nothing of the package is left behind to count against what's queued next
+        mempool.add_package(package[..2].to_vec()).unwrap();
+        assert_eq!(mempool.len(), MAX_ANCESTORS);
+    }
+}
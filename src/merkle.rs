@@ -0,0 +1,110 @@
+use serde::{Deserialize, Serialize};
+
+use crate::hashing::{self, MERKLE_LEAF_TAG, MERKLE_NODE_TAG};
+
+/// Proof that an item is the `index`-th of `leaf_count` items under a Merkle root.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub index: usize,
+    pub leaf_count: usize,
+    /// Hex-encoded sibling hashes from the leaf level up. Levels where the node has no sibling
+    /// are skipped, see [`root`].
+    pub siblings: Vec<String>,
+}
+
+fn leaf_hash(item: &[u8]) -> [u8; 32] {
+    hashing::tagged_digest(MERKLE_LEAF_TAG, item)
+}
+
+fn node_hash(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    hashing::tagged_digest(
+        MERKLE_NODE_TAG,
+        [left.as_slice(), right.as_slice()].concat(),
+    )
+}
+
+/// Hashes one level of the tree into the next. An odd node out is carried up as is rather than
+/// paired with itself, so no two different lists of items share a root.
+fn next_level(level: &[[u8; 32]]) -> Vec<[u8; 32]> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => node_hash(left, right),
+            _ => pair[0],
+        })
+        .collect()
+}
+
+/// Hex-encoded Merkle root of `items`. Leaves and inner nodes are hashed under different tags
+/// so a leaf can't pose as an inner node. The root of no items is the node tag hashed over
+/// nothing, a preimage no inner node has.
+pub fn root<T: AsRef<[u8]>>(items: &[T]) -> String {
+    let mut level: Vec<[u8; 32]> = items.iter().map(|item| leaf_hash(item.as_ref())).collect();
+    if level.is_empty() {
+        return hashing::tagged_hash(MERKLE_NODE_TAG, []);
+    }
+
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    hex::encode(level[0])
+}
+
+/// Proof that `items[index]` is included under [`root`] of `items`.
+pub fn generate_proof<T: AsRef<[u8]>>(items: &[T], index: usize) -> Option<MerkleProof> {
+    if index >= items.len() {
+        return None;
+    }
+
+    let mut level: Vec<[u8; 32]> = items.iter().map(|item| leaf_hash(item.as_ref())).collect();
+    let mut position = index;
+    let mut siblings = Vec::new();
+
+    while level.len() > 1 {
+        if let Some(sibling) = level.get(position ^ 1) {
+            siblings.push(hex::encode(sibling));
+        }
+        level = next_level(&level);
+        position /= 2;
+    }
+
+    Some(MerkleProof {
+        index,
+        leaf_count: items.len(),
+        siblings,
+    })
+}
+
+/// Checks that `item` is included under the hex-encoded `root` at the position `proof` claims.
+pub fn verify_proof(root: &str, item: impl AsRef<[u8]>, proof: &MerkleProof) -> bool {
+    if proof.index >= proof.leaf_count {
+        return false;
+    }
+
+    let mut hash = leaf_hash(item.as_ref());
+    let mut position = proof.index;
+    let mut level_len = proof.leaf_count;
+    let mut siblings = proof.siblings.iter();
+
+    while level_len > 1 {
+        if position ^ 1 < level_len {
+            let Some(sibling) = siblings
+                .next()
+                .and_then(|sibling| hex::decode(sibling).ok())
+                .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+            else {
+                return false;
+            };
+
+            hash = if position.is_multiple_of(2) {
+                node_hash(&hash, &sibling)
+            } else {
+                node_hash(&sibling, &hash)
+            };
+        }
+        position /= 2;
+        level_len = level_len.div_ceil(2);
+    }
+
+    siblings.next().is_none() && hex::encode(hash) == root
+}
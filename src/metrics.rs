@@ -0,0 +1,79 @@
+use std::fmt;
+
+use serde::Serialize;
+
+/// Upper bounds, in milliseconds, of the buckets of a latency histogram. Anything slower falls
+/// into an extra overflow bucket.
+const LATENCY_BUCKETS_MS: [u64; 8] = [10, 50, 100, 250, 500, 1_000, 5_000, 30_000];
+
+/// Fixed-bucket histogram of latencies in milliseconds.
+#[derive(Clone, Default, Serialize)]
+pub struct Histogram {
+    counts: [u64; LATENCY_BUCKETS_MS.len() + 1],
+    sum_ms: u64,
+    count: u64,
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn observe(&mut self, latency_ms: u64) {
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|bound| latency_ms <= *bound)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+
+        self.counts[bucket] += 1;
+        self.sum_ms = self.sum_ms.saturating_add(latency_ms);
+        self.count += 1;
+    }
+}
+
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.count == 0 {
+            return write!(f, "no samples");
+        }
+
+        write!(f, "avg {}ms [", self.sum_ms / self.count)?;
+        for (index, count) in self.counts.iter().enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            match LATENCY_BUCKETS_MS.get(index) {
+                Some(bound) => write!(f, "<={}ms: {}", bound, count)?,
+                None => write!(f, ">{}ms: {}", LATENCY_BUCKETS_MS[index - 1], count)?,
+            }
+        }
+        write!(f, "]")
+    }
+}
+
+/// Latencies of the steps a block announced by a peer goes through on this node.
+#[derive(Default)]
+pub struct PropagationMetrics {
+    /// From the peer sending the announcement to us receiving it.
+    pub hop: Histogram,
+    /// From receiving a block to having validated and connected it.
+    pub validation: Histogram,
+    /// From receiving a block to having relayed it to our other peers.
+    pub relay: Histogram,
+}
+
+impl PropagationMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl fmt::Display for PropagationMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "hop: {}; validation: {}; relay: {}",
+            self.hop, self.validation, self.relay
+        )
+    }
+}
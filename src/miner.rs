@@ -0,0 +1,476 @@
+use std::iter::StepBy;
+use std::num::NonZeroUsize;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+use colored::Colorize;
+use log::{debug, info};
+use serde::Serialize;
+
+use crate::block::{Block, BlockTemplate};
+use crate::difficulty;
+use crate::transaction;
+use crate::work::Work;
+
+const TIMESTAMP_REFRESH_SECS: i64 = 10;
+/// How many nonces a worker tries between looking at the clock and checking whether it should
+/// stop.
+const CHECK_INTERVAL: u64 = 10_000;
+
+/// Shared flag to abort mining, e.g. once a competing block for the same height arrives.
+/// Clones refer to the same flag.
+#[derive(Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Proof-of-work search spread over several threads. Each worker tries its own stride of the
+/// nonce space, so no nonce is tried twice.
+pub struct Miner {
+    threads: usize,
+    /// Hashes tried since the miner was created, over all blocks.
+    hashes: AtomicU64,
+    /// Closest the last block came to being solved, if its mining was abandoned.
+    abandoned: Mutex<Option<Attempt>>,
+}
+
+/// A nonce tried for a block, kept when it gave the lowest hash of a search.
+#[derive(Clone, Debug, Serialize)]
+pub struct Attempt {
+    pub id: u64,
+    pub hash: String,
+    pub nonce: u64,
+    pub timestamp: i64,
+}
+
+impl Miner {
+    /// A miner using `threads` workers, at least one.
+    pub fn new(threads: usize) -> Self {
+        Self {
+            threads: threads.max(1),
+            hashes: AtomicU64::new(0),
+            abandoned: Mutex::new(None),
+        }
+    }
+
+    /// Lowest hash reached for the last block mined if its mining was cancelled, for
+    /// diagnostics. Cleared as soon as the next block is mined.
+    pub fn abandoned(&self) -> Option<Attempt> {
+        self.abandoned.lock().unwrap().clone()
+    }
+
+    /// Hashes tried so far, updated while mining; sampling it over time gives the hashrate.
+    pub fn hashes(&self) -> u64 {
+        self.hashes.load(Ordering::Relaxed)
+    }
+
+    /// Mines the block described by `template`, blocking until a worker finds a nonce or
+    /// `cancel` is cancelled, in which case `None` is returned.
+    ///
+    /// Every worker refreshes its timestamp every `TIMESTAMP_REFRESH_SECS` so a block that takes
+    /// long to solve doesn't end up with a stale timestamp. On cancellation, the lowest hash
+    /// the workers got to is still checked against the difficulty, so a solution found just as
+    /// `cancel` fired isn't thrown away; otherwise it's kept as [`Miner::abandoned`].
+    pub fn mine(&self, template: BlockTemplate, cancel: &CancellationToken) -> Option<Block> {
+        let merkle_root = transaction::merkle_root(&template.transactions);
+        let started_at = Instant::now();
+        *self.abandoned.lock().unwrap() = None;
+        let solved = AtomicBool::new(false);
+        let hashes = AtomicU64::new(0);
+
+        let best = thread::scope(|scope| {
+            let (template, merkle_root) = (&template, &merkle_root);
+            let (solved, hashes) = (&solved, &hashes);
+
+            let workers: Vec<_> = (0..self.threads as u64)
+                .map(|worker| {
+                    scope.spawn(move || {
+                        self.search(template, merkle_root, worker, solved, cancel, hashes)
+                    })
+                })
+                .collect();
+
+            workers
+                .into_iter()
+                .filter_map(|worker| worker.join().ok().flatten())
+                .min_by(|a, b| a.hash.cmp(&b.hash))
+        });
+
+        let hashes = hashes.into_inner();
+        let Attempt {
+            hash,
+            nonce,
+            timestamp,
+            ..
+        } = match best {
+            Some(best) if difficulty::meets(&best.hash, template.difficulty) => best,
+            Some(best) => {
+                info!(
+                    "Mining of block #{} was cancelled after {} hashes, the lowest hash was {} at nonce {}",
+                    template.id, hashes, best.hash, best.nonce
+                );
+                *self.abandoned.lock().unwrap() = Some(best);
+                return None;
+            }
+            None => {
+                info!(
+                    "Mining of block #{} was cancelled after {} hashes",
+                    template.id, hashes
+                );
+                return None;
+            }
+        };
+
+        let elapsed = started_at.elapsed().as_secs_f64();
+        info!(
+            "Block #{} was successfully mined: {} ({} hashes on {} thread(s), {:.0} H/s)",
+            template.id,
+            highlight_hash(&hash),
+            hashes,
+            self.threads,
+            hashes as f64 / elapsed.max(f64::EPSILON)
+        );
+
+        Some(Block {
+            id: template.id,
+            hash,
+            previous_hash: template.previous_hash,
+            timestamp,
+            difficulty: template.difficulty,
+            merkle_root,
+            transactions: template.transactions,
+            nonce,
+        })
+    }
+
+    /// Searches the nonce range of `work` handed out by a node, see [`crate::work::WorkQueue`],
+    /// returning the nonce that solves it if any. The range is split over the threads the same
+    /// way [`Miner::mine`] splits the nonce space.
+    pub fn mine_work(&self, work: &Work, cancel: &CancellationToken) -> Option<u64> {
+        let solved = AtomicBool::new(false);
+
+        thread::scope(|scope| {
+            let solved = &solved;
+            let workers: Vec<_> = (0..self.threads as u64)
+                .map(|worker| {
+                    scope.spawn(move || {
+                        let mut tried: u64 = 0;
+                        let nonces = stride(work.nonce_start..work.nonce_end, worker, self.threads);
+
+                        for nonce in nonces {
+                            if tried.is_multiple_of(CHECK_INTERVAL)
+                                && (solved.load(Ordering::Relaxed) || cancel.is_cancelled())
+                            {
+                                break;
+                            }
+                            tried += 1;
+
+                            let hash = Block::hash(
+                                work.id,
+                                &work.previous_hash,
+                                work.timestamp,
+                                work.difficulty,
+                                &work.merkle_root,
+                                nonce,
+                            );
+                            if difficulty::meets(&hash, work.difficulty) {
+                                solved.store(true, Ordering::Relaxed);
+                                self.hashes.fetch_add(tried, Ordering::Relaxed);
+                                return Some(nonce);
+                            }
+                        }
+
+                        self.hashes.fetch_add(tried, Ordering::Relaxed);
+                        None
+                    })
+                })
+                .collect();
+
+            workers
+                .into_iter()
+                .filter_map(|worker| worker.join().ok().flatten())
+                .next()
+        })
+    }
+
+    /// Work of a single worker: tries the nonces `first_nonce`, `first_nonce + threads`, ...
+    /// until one satisfies the difficulty or another worker or `cancel` stops it. Returns the
+    /// attempt with the lowest hash, which is the solution if there is one.
+    fn search(
+        &self,
+        template: &BlockTemplate,
+        merkle_root: &str,
+        first_nonce: u64,
+        solved: &AtomicBool,
+        cancel: &CancellationToken,
+        hashes: &AtomicU64,
+    ) -> Option<Attempt> {
+        let started_at = Instant::now();
+        let mut timestamp = template.timestamp;
+        let mut nonce = first_nonce;
+        let mut tried: u64 = 0;
+        // Part of `tried` already added to the miner's total
+        let mut reported: u64 = 0;
+        let mut best: Option<Attempt> = None;
+
+        loop {
+            if tried.is_multiple_of(CHECK_INTERVAL) {
+                self.hashes.fetch_add(tried - reported, Ordering::Relaxed);
+                reported = tried;
+                if solved.load(Ordering::Relaxed) || cancel.is_cancelled() {
+                    break;
+                }
+
+                let now = template.timestamp + started_at.elapsed().as_secs() as i64;
+                if now - timestamp >= TIMESTAMP_REFRESH_SECS {
+                    debug!(
+                        "Refreshing timestamp of block #{} ({} -> {})",
+                        template.id, timestamp, now
+                    );
+                    timestamp = now;
+                }
+            }
+
+            let hash = Block::hash(
+                template.id,
+                &template.previous_hash,
+                timestamp,
+                template.difficulty,
+                merkle_root,
+                nonce,
+            );
+            tried += 1;
+
+            let meets = difficulty::meets(&hash, template.difficulty);
+            if best.as_ref().is_none_or(|best| hash < best.hash) {
+                best = Some(Attempt {
+                    id: template.id,
+                    hash,
+                    nonce,
+                    timestamp,
+                });
+            }
+            if meets {
+                solved.store(true, Ordering::Relaxed);
+                break;
+            }
+
+            nonce += self.threads as u64;
+        }
+
+        hashes.fetch_add(tried, Ordering::Relaxed);
+        self.hashes.fetch_add(tried - reported, Ordering::Relaxed);
+        best
+    }
+}
+
+impl Default for Miner {
+    /// A miner with one worker per available core.
+    fn default() -> Self {
+        Self::new(
+            thread::available_parallelism()
+                .map(NonZeroUsize::get)
+                .unwrap_or(1),
+        )
+    }
+}
+
+/// Nonces of `range` that worker `worker` out of `threads` tries: every `threads`-th one from its
+/// own offset, so together the workers try each nonce once.
+fn stride(range: Range<u64>, worker: u64, threads: usize) -> StepBy<Range<u64>> {
+    (range.start + worker..range.end).step_by(threads)
+}
+
+/// Mines `template` at the fixed `timestamp` on a single thread, trying nonces from zero up, so
+/// the same template and timestamp give the same block on any machine. Used to produce genesis
+/// blocks anyone can reproduce.
+pub fn mine_deterministic(template: BlockTemplate, timestamp: i64) -> Block {
+    let merkle_root = transaction::merkle_root(&template.transactions);
+    let mut nonce = 0;
+    let hash = loop {
+        let hash = Block::hash(
+            template.id,
+            &template.previous_hash,
+            timestamp,
+            template.difficulty,
+            &merkle_root,
+            nonce,
+        );
+        if difficulty::meets(&hash, template.difficulty) {
+            break hash;
+        }
+        nonce += 1;
+    };
+
+    Block {
+        id: template.id,
+        hash,
+        previous_hash: template.previous_hash,
+        timestamp,
+        difficulty: template.difficulty,
+        merkle_root,
+        transactions: template.transactions,
+        nonce,
+    }
+}
+
+/// Renders a hash with its leading zeros highlighted, so it's easy to see what the proof of
+/// work actually achieved.
+fn highlight_hash(hash: &str) -> String {
+    let rest = hash.trim_start_matches('0');
+    let zeros = &hash[..hash.len() - rest.len()];
+    format!("{}{}", zeros.green().bold(), rest.dimmed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::BLOCK_REWARD;
+    use crate::transaction::Transaction;
+
+    /// A template every hash solves at `difficulty` 1, or about one in `difficulty` above.
+    fn template(difficulty: u64) -> BlockTemplate {
+        BlockTemplate {
+            id: 1,
+            previous_hash: "0".repeat(64),
+            difficulty,
+            timestamp: 1_700_000_000,
+            transactions: vec![Transaction::coinbase(String::from("miner"), BLOCK_REWARD)],
+        }
+    }
+
+    #[test]
+    fn solutions_found_as_mining_is_cancelled_are_kept() {
+        let miner = Miner::new(4);
+        let cancel = CancellationToken::new();
+
+        let block = thread::scope(|scope| {
+            // Every hash solves the block, so once one is counted there's a solution to keep
+            scope.spawn(|| {
+                while miner.hashes() == 0 {
+                    thread::yield_now();
+                }
+                cancel.cancel();
+            });
+            miner.mine(template(1), &cancel)
+        });
+
+        assert!(cancel.is_cancelled());
+        let block = block.expect("the solution is kept");
+        assert!(difficulty::meets(&block.hash, 1));
+        assert_eq!(block.hash, block.calculate_hash());
+        assert!(miner.abandoned().is_none());
+    }
+
+    #[test]
+    fn workers_together_find_a_solution() {
+        let miner = Miner::new(4);
+        let block = miner
+            .mine(template(256), &CancellationToken::new())
+            .expect("nothing cancels the search");
+
+        assert!(difficulty::meets(&block.hash, 256));
+        assert_eq!(block.hash, block.calculate_hash());
+        assert!(miner.hashes() > 0);
+    }
+
+    #[test]
+    fn workers_try_every_nonce_once() {
+        for threads in 1..=5 {
+            let mut nonces: Vec<_> = (0..threads as u64)
+                .flat_map(|worker| stride(10..37, worker, threads))
+                .collect();
+            nonces.sort_unstable();
+            assert_eq!(nonces, (10..37).collect::<Vec<_>>(), "{} threads", threads);
+        }
+
+        // Nothing solves the work, so all of it is searched
+        let miner = Miner::new(3);
+        let work = Work {
+            work_id: 1,
+            id: 1,
+            previous_hash: "0".repeat(64),
+            timestamp: 0,
+            difficulty: u64::MAX,
+            merkle_root: String::new(),
+            nonce_start: 1_000,
+            nonce_end: 2_000,
+        };
+        assert_eq!(miner.mine_work(&work, &CancellationToken::new()), None);
+        assert_eq!(miner.hashes(), 1_000);
+    }
+
+    #[test]
+    fn work_is_solved_within_its_range() {
+        let miner = Miner::new(3);
+        let work = Work {
+            work_id: 1,
+            id: 1,
+            previous_hash: "0".repeat(64),
+            timestamp: 0,
+            difficulty: 16,
+            merkle_root: String::new(),
+            nonce_start: 500,
+            nonce_end: 10_000,
+        };
+        let nonce = miner
+            .mine_work(&work, &CancellationToken::new())
+            .expect("one in 16 nonces solves the work");
+
+        assert!((work.nonce_start..work.nonce_end).contains(&nonce));
+        let hash = Block::hash(
+            work.id,
+            &work.previous_hash,
+            work.timestamp,
+            work.difficulty,
+            &work.merkle_root,
+            nonce,
+        );
+        assert!(difficulty::meets(&hash, work.difficulty));
+    }
+
+    #[test]
+    fn cancelling_stops_every_worker() {
+        let miner = Miner::new(4);
+        let cancel = CancellationToken::new();
+        let work = Work {
+            work_id: 1,
+            id: 1,
+            previous_hash: "0".repeat(64),
+            timestamp: 0,
+            difficulty: u64::MAX,
+            merkle_root: String::new(),
+            nonce_start: 0,
+            nonce_end: u64::MAX,
+        };
+
+        // Neither search can be solved, so they only return once all of their workers stopped
+        let (block, nonce) = thread::scope(|scope| {
+            scope.spawn(|| {
+                thread::sleep(std::time::Duration::from_millis(50));
+                cancel.cancel();
+            });
+            let block = miner.mine(template(u64::MAX), &cancel);
+            (block, miner.mine_work(&work, &cancel))
+        });
+
+        assert!(block.is_none());
+        assert_eq!(nonce, None);
+        assert_eq!(miner.abandoned().map(|attempt| attempt.id), Some(1));
+    }
+}
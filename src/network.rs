@@ -0,0 +1,1193 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+use crate::address_book::{AddressBook, NetworkGroup};
+use crate::alerts::{AlertKind, Alerts, LONG_FORK_ALERT, LONG_FORK_BLOCKS};
+use crate::capture::MisbehaviorCapture;
+use crate::metrics::PropagationMetrics;
+use crate::rebroadcast::{LocalTransactions, REBROADCAST_INTERVAL};
+use crate::telemetry::{Telemetry, TelemetryEvent};
+use crate::transaction::Transaction;
+use crate::{difficulty, Block, BlockchainError, ChainHandle, ReplaceChainOutcome};
+
+/// Most blocks sent in a single `Blocks` message; a syncing node asks again for the rest.
+const MAX_BLOCKS_PER_MESSAGE: usize = 500;
+/// Most blocks buffered for a sync that doesn't extend our tip, which only connects once all
+/// of its blocks arrived. A peer sending more is dropped; a sync extending our tip connects
+/// every message as it arrives instead.
+const MAX_SYNC_BLOCKS: usize = 20 * MAX_BLOCKS_PER_MESSAGE;
+/// How far below our tip we start asking for blocks when a peer seems to be on another fork.
+/// If the fork is deeper than that, the whole chain is requested.
+const FORK_LOOKBACK: u64 = 10;
+/// Most connections we open to addresses picked from the address book.
+const MAX_OUTBOUND: usize = 8;
+/// Most connections from other nodes we keep open, handshake completed or not.
+const MAX_INBOUND: usize = 32;
+/// Most addresses a `Peers` message may carry; a peer sending more is dropped.
+const MAX_ADDRS_PER_MESSAGE: usize = 250;
+/// How many outbound peers are remembered across restarts and dialed first on startup.
+const MAX_ANCHORS: usize = 2;
+/// How often free outbound slots are filled from the address book.
+const OUTBOUND_INTERVAL: Duration = Duration::from_secs(30);
+/// Fewest peers whose clocks we compare ours to before correcting it.
+const MIN_TIME_SAMPLES: usize = 3;
+/// Past this many seconds of skew from the peers, our clock is reported as off.
+const TIME_OFFSET_WARNING_SECS: i64 = 30;
+/// Most seconds our clock is corrected by. A larger skew is more likely peers lying about
+/// their clocks than ours being that far off, so it's only reported.
+const MAX_TIME_ADJUSTMENT_SECS: i64 = 10 * 60;
+
+/// Service bit of nodes that serve their full chain on `GetBlocks`.
+pub const SERVICE_BLOCKS: u64 = 1 << 0;
+/// Service bit of nodes that accept and relay pending transactions and packages.
+pub const SERVICE_TRANSACTION_RELAY: u64 = 1 << 1;
+/// Everything a full node offers.
+pub const ALL_SERVICES: u64 = SERVICE_BLOCKS | SERVICE_TRANSACTION_RELAY;
+
+/// Messages exchanged between nodes, one JSON object per line.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message {
+    /// First message on every connection, in both directions.
+    Hello {
+        /// Chain the sender is on; peers on other chains are dropped.
+        chain_id: String,
+        /// Bitfield of the `SERVICE_*` the sender offers. Nobody is asked for a service it
+        /// doesn't offer.
+        services: u64,
+        listen_addr: SocketAddr,
+        height: u64,
+        work: u64,
+        /// Clock of the sender when it sent the message, in Unix seconds.
+        timestamp: i64,
+    },
+    GetPeers,
+    Peers {
+        addrs: Vec<SocketAddr>,
+    },
+    /// Asks for the blocks starting at id `from`.
+    GetBlocks {
+        from: u64,
+    },
+    Blocks {
+        blocks: Vec<Block>,
+    },
+    NewBlock {
+        block: Block,
+        /// When the sender put the announcement on the wire, in Unix milliseconds.
+        sent_at: i64,
+    },
+    NewTransaction {
+        transaction: Transaction,
+    },
+    /// Dependent transactions to accept all together or not at all, parents first.
+    NewPackage {
+        transactions: Vec<Transaction>,
+    },
+}
+
+/// Peer-to-peer layer: keeps connections to other nodes, gossips new blocks and transactions
+/// and syncs the chain from peers that are ahead of us.
+pub struct Network {
+    chain_id: String,
+    listen_addr: SocketAddr,
+    blockchain: ChainHandle,
+    /// Services we offer, see [`ALL_SERVICES`].
+    services: u64,
+    /// The connected peers, keyed by the address of the connection as we see it, which they
+    /// can't claim for themselves.
+    peers: Mutex<HashMap<SocketAddr, Peer>>,
+    /// Addresses of the outbound connections, open or being dialed.
+    outbound: Mutex<HashSet<SocketAddr>>,
+    /// Number of inbound connections open, see [`MAX_INBOUND`].
+    inbound: AtomicUsize,
+    /// Addresses we heard about, to pick outbound peers from.
+    addresses: Mutex<AddressBook>,
+    /// Where the anchors, outbound peers we trusted before a restart, are kept, if anywhere.
+    anchors_path: Option<PathBuf>,
+    propagation: Mutex<PropagationMetrics>,
+    telemetry: Arc<Telemetry>,
+    alerts: Arc<Alerts>,
+    /// Where messages of misbehaving peers are dumped, if anywhere.
+    capture: Option<MisbehaviorCapture>,
+    /// Transactions submitted to us, announced again until confirmed, unless rebroadcasting
+    /// is off.
+    local: Option<Mutex<LocalTransactions>>,
+}
+
+/// A peer we completed a handshake with.
+struct Peer {
+    /// Outgoing message queue of the connection.
+    sender: UnboundedSender<Message>,
+    services: u64,
+    /// Address we dialed, if we opened the connection.
+    dialed: Option<SocketAddr>,
+    /// Where the peer can be dialed, if we know: the address we dialed, or the one it claims
+    /// to listen on if that's on the IP it connected from.
+    listen_addr: Option<SocketAddr>,
+    /// Seconds the clock of the peer was ahead of ours during the handshake.
+    time_offset: i64,
+}
+
+impl Message {
+    /// Announcement of a block we just mined or connected.
+    pub fn new_block(block: Block) -> Self {
+        Self::NewBlock {
+            block,
+            sent_at: Utc::now().timestamp_millis(),
+        }
+    }
+
+    /// Services a node must offer to be sent this message.
+    fn required_services(&self) -> u64 {
+        match self {
+            Self::GetBlocks { .. } => SERVICE_BLOCKS,
+            Self::NewTransaction { .. } | Self::NewPackage { .. } => SERVICE_TRANSACTION_RELAY,
+            _ => 0,
+        }
+    }
+}
+
+impl Network {
+    /// Creates the network layer of a node on chain `chain_id` listening on `listen_addr` and
+    /// offering `services`; nothing happens until [`Network::start`] is called. Invalid
+    /// messages from peers are dumped to `capture` and anchors are kept in `anchors_path`, if
+    /// given. Transactions submitted to us are rebroadcast unless `rebroadcast` is off.
+    pub fn new(
+        chain_id: String,
+        listen_addr: SocketAddr,
+        services: u64,
+        blockchain: ChainHandle,
+        capture: Option<MisbehaviorCapture>,
+        anchors_path: Option<PathBuf>,
+        rebroadcast: bool,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            chain_id,
+            listen_addr,
+            services,
+            blockchain,
+            peers: Mutex::new(HashMap::new()),
+            outbound: Mutex::new(HashSet::new()),
+            inbound: AtomicUsize::new(0),
+            addresses: Mutex::new(AddressBook::new()),
+            anchors_path,
+            propagation: Mutex::new(PropagationMetrics::new()),
+            telemetry: Arc::new(Telemetry::new()),
+            alerts: Arc::new(Alerts::new()),
+            capture,
+            local: rebroadcast.then(|| Mutex::new(LocalTransactions::new())),
+        })
+    }
+
+    /// Starts accepting peers, dials the anchors and the bootstrap peers and keeps filling
+    /// outbound slots from the address book and rebroadcasting our transactions.
+    pub async fn start(self: &Arc<Self>, bootstrap_peers: Vec<SocketAddr>) -> io::Result<()> {
+        let listener = TcpListener::bind(self.listen_addr).await?;
+        info!("Listening for peers on {}", self.listen_addr);
+
+        let network = self.clone();
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, addr)) => {
+                        if network.inbound.load(Ordering::Relaxed) >= MAX_INBOUND {
+                            debug!("Refusing connection from {}, no inbound slot left", addr);
+                            continue;
+                        }
+                        debug!("Accepted connection from {}", addr);
+                        network.inbound.fetch_add(1, Ordering::Relaxed);
+                        let network = network.clone();
+                        tokio::spawn(async move {
+                            network.clone().handle_connection(stream, None).await;
+                            network.inbound.fetch_sub(1, Ordering::Relaxed);
+                        });
+                    }
+                    Err(err) => warn!("Failed to accept a peer connection: {}", err),
+                }
+            }
+        });
+
+        for addr in self.load_anchors() {
+            info!("Dialing anchor {}", addr);
+            self.connect(addr);
+        }
+        for addr in bootstrap_peers {
+            self.connect(addr);
+        }
+
+        let network = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(OUTBOUND_INTERVAL);
+            loop {
+                interval.tick().await;
+                network.fill_outbound();
+            }
+        });
+
+        if self.local.is_some() {
+            let network = self.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(REBROADCAST_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    network.rebroadcast();
+                }
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Dials `addr` in the background unless it's us or we're already connected to it.
+    pub fn connect(self: &Arc<Self>, addr: SocketAddr) {
+        let connected = {
+            let peers = self.peers.lock().unwrap();
+            peers.contains_key(&addr) || peers.values().any(|peer| peer.listen_addr == Some(addr))
+        };
+        if addr == self.listen_addr || connected || !self.outbound.lock().unwrap().insert(addr) {
+            return;
+        }
+
+        let network = self.clone();
+        tokio::spawn(async move {
+            match TcpStream::connect(addr).await {
+                Ok(stream) => {
+                    info!("Connected to peer {}", addr);
+                    network.clone().handle_connection(stream, Some(addr)).await;
+                    network.outbound.lock().unwrap().remove(&addr);
+                    network.save_anchors();
+                }
+                Err(err) => {
+                    warn!("Failed to connect to peer {}: {}", addr, err);
+                    network.outbound.lock().unwrap().remove(&addr);
+                    network.addresses.lock().unwrap().remove(addr);
+                }
+            }
+        });
+    }
+
+    /// Dials addresses from the address book until [`MAX_OUTBOUND`] connections are open,
+    /// each to a network group none of the others is in.
+    fn fill_outbound(self: &Arc<Self>) {
+        for _ in 0..MAX_OUTBOUND {
+            let used: HashSet<NetworkGroup> = {
+                let outbound = self.outbound.lock().unwrap();
+                if outbound.len() >= MAX_OUTBOUND {
+                    return;
+                }
+                outbound.iter().copied().map(NetworkGroup::of).collect()
+            };
+
+            let Some(addr) = self.addresses.lock().unwrap().select(&used) else {
+                return;
+            };
+            self.connect(addr);
+        }
+    }
+
+    /// Compares our clock to the ones of the peers, see [`time_adjustment`], and corrects the
+    /// time of the chain by the difference. Done whenever a peer joins or leaves.
+    fn update_time_offset(&self) {
+        let offsets = self
+            .peers
+            .lock()
+            .unwrap()
+            .values()
+            .map(|peer| peer.time_offset)
+            .collect();
+        if let Some(adjustment) = time_adjustment(offsets) {
+            self.blockchain.set_time_offset(adjustment);
+        }
+    }
+
+    /// Anchors saved before the last restart, if any.
+    fn load_anchors(&self) -> Vec<SocketAddr> {
+        let Some(path) = &self.anchors_path else {
+            return Vec::new();
+        };
+
+        match fs::read_to_string(path) {
+            Ok(anchors) => serde_json::from_str(&anchors).unwrap_or_else(|err| {
+                warn!("Ignoring malformed anchors in {}: {}", path.display(), err);
+                Vec::new()
+            }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => {
+                warn!("Failed to read anchors from {}: {}", path.display(), err);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Remembers up to [`MAX_ANCHORS`] of the connected outbound peers, from distinct network
+    /// groups, so a restart reconnects to them before trusting any gossiped address.
+    fn save_anchors(&self) {
+        let Some(path) = &self.anchors_path else {
+            return;
+        };
+
+        // The lock is held while writing so concurrent saves can't land out of order
+        let peers = self.peers.lock().unwrap();
+        let mut groups = HashSet::new();
+        let anchors: Vec<SocketAddr> = peers
+            .values()
+            .filter_map(|peer| peer.dialed)
+            .filter(|addr| groups.insert(NetworkGroup::of(*addr)))
+            .take(MAX_ANCHORS)
+            .collect();
+
+        let result = serde_json::to_vec(&anchors)
+            .map_err(io::Error::other)
+            .and_then(|anchors| fs::write(path, anchors));
+        if let Err(err) = result {
+            warn!("Failed to save anchors to {}: {}", path.display(), err);
+        }
+    }
+
+    /// Sends a message to every connected peer offering the services it needs, except
+    /// `except`.
+    pub fn broadcast(&self, message: &Message, except: Option<SocketAddr>) {
+        let required = message.required_services();
+        for (addr, peer) in self.peers.lock().unwrap().iter() {
+            if Some(*addr) != except && peer.services & required == required {
+                let _ = peer.sender.send(message.clone());
+            }
+        }
+    }
+
+    /// Announces `transactions`, submitted to us together, to every peer relaying them and
+    /// keeps announcing them until a block confirms them, see [`LocalTransactions`].
+    pub fn announce_local(&self, transactions: Vec<Transaction>) {
+        self.broadcast(&Self::announcement(transactions.clone()), None);
+        if let Some(local) = &self.local {
+            local.lock().unwrap().track(transactions, Instant::now());
+        }
+    }
+
+    /// Announces again those of our transactions that are due, see [`LocalTransactions::due`], once
+    /// those settled are forgotten.
+    fn rebroadcast(&self) {
+        let Some(local) = &self.local else {
+            return;
+        };
+        let due = {
+            let mut local = local.lock().unwrap();
+            local.forget_settled(&self.blockchain.read());
+            local.due(Instant::now())
+        };
+        for transactions in due {
+            info!("Rebroadcasting transaction {}", transactions[0].hash());
+            self.broadcast(&Self::announcement(transactions), None);
+        }
+    }
+
+    /// A lone transaction is announced as such, dependent ones as a package.
+    fn announcement(mut transactions: Vec<Transaction>) -> Message {
+        if transactions.len() == 1 {
+            Message::NewTransaction {
+                transaction: transactions.remove(0),
+            }
+        } else {
+            Message::NewPackage { transactions }
+        }
+    }
+
+    /// Whether the peer `addr` offers all of `services`.
+    fn offers(&self, addr: SocketAddr, services: u64) -> bool {
+        self.peers
+            .lock()
+            .unwrap()
+            .get(&addr)
+            .is_some_and(|peer| peer.services & services == services)
+    }
+
+    /// Latency histograms of the blocks announced to us so far.
+    pub fn propagation_report(&self) -> String {
+        self.propagation.lock().unwrap().to_string()
+    }
+
+    /// Where the events of this chain are counted, the network's own and those of the miner.
+    pub fn telemetry(&self) -> Arc<Telemetry> {
+        self.telemetry.clone()
+    }
+
+    /// The alerts up on this chain, see [`crate::alerts::AlertMonitor`].
+    pub fn alerts(&self) -> Arc<Alerts> {
+        self.alerts.clone()
+    }
+
+    /// Number of peers we completed a handshake with.
+    pub fn peer_count(&self) -> usize {
+        self.peers.lock().unwrap().len()
+    }
+
+    /// Asks every peer for the blocks they have past the recent part of our chain, so we
+    /// switch to theirs if it turns out to be heavier.
+    pub fn request_sync(&self) {
+        self.broadcast(
+            &Message::GetBlocks {
+                from: self.sync_start(),
+            },
+            None,
+        );
+    }
+
+    /// Dumps a message `peer` got wrong, if capturing is enabled.
+    fn capture(&self, peer: Option<SocketAddr>, reason: String, line: &str) {
+        let Some(capture) = &self.capture else {
+            return;
+        };
+
+        match capture.record(peer, reason, self.height(), line) {
+            Ok(path) => info!("Captured the offending message in {}", path.display()),
+            Err(err) => warn!("Failed to capture the offending message: {}", err),
+        }
+    }
+
+    fn height(&self) -> u64 {
+        self.blockchain.height()
+    }
+
+    fn work(&self) -> u64 {
+        self.blockchain.work()
+    }
+
+    /// Where to start requesting blocks from a peer that may be on another fork.
+    fn sync_start(&self) -> u64 {
+        self.height().saturating_sub(FORK_LOOKBACK)
+    }
+
+    /// Speaks the protocol over `stream` until it closes; `dialed` is the address we dialed
+    /// if we opened it.
+    async fn handle_connection(self: Arc<Self>, stream: TcpStream, dialed: Option<SocketAddr>) {
+        let observed = match stream.peer_addr() {
+            Ok(observed) => observed,
+            Err(err) => {
+                warn!("Failed to get the address of a peer: {}", err);
+                return;
+            }
+        };
+        let (reader, mut writer) = stream.into_split();
+        let (sender, mut receiver) = mpsc::unbounded_channel::<Message>();
+
+        tokio::spawn(async move {
+            while let Some(message) = receiver.recv().await {
+                let mut line = match serde_json::to_vec(&message) {
+                    Ok(line) => line,
+                    Err(err) => {
+                        warn!("Failed to encode a message for a peer: {}", err);
+                        continue;
+                    }
+                };
+                line.push(b'\n');
+                if writer.write_all(&line).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let _ = sender.send(Message::Hello {
+            chain_id: self.chain_id.clone(),
+            services: self.services,
+            listen_addr: self.listen_addr,
+            height: self.height(),
+            work: self.work(),
+            timestamp: Utc::now().timestamp(),
+        });
+
+        let mut lines = BufReader::new(reader).lines();
+        let mut peer_addr = None;
+        // Blocks received so far in a sync spanning several messages
+        let mut sync_buffer = Vec::new();
+
+        loop {
+            let line = match lines.next_line().await {
+                Ok(Some(line)) => line,
+                Ok(None) => break,
+                Err(err) => {
+                    warn!("Failed to read from peer: {}", err);
+                    break;
+                }
+            };
+
+            let message = match serde_json::from_str::<Message>(&line) {
+                Ok(message) => message,
+                Err(err) => {
+                    warn!("Peer sent a malformed message: {}", err);
+                    self.capture(peer_addr, format!("malformed message: {}", err), &line);
+                    break;
+                }
+            };
+
+            match (peer_addr, message) {
+                (
+                    None,
+                    Message::Hello {
+                        chain_id,
+                        services,
+                        listen_addr,
+                        height,
+                        work,
+                        timestamp,
+                    },
+                ) => {
+                    if listen_addr == self.listen_addr {
+                        debug!("Dropping connection to ourselves");
+                        return;
+                    }
+                    if chain_id != self.chain_id {
+                        warn!(
+                            "Dropping peer {} on chain {} instead of {}",
+                            listen_addr, chain_id, self.chain_id
+                        );
+                        return;
+                    }
+                    // Only the address we dialed, or one on the IP the peer connected from,
+                    // is known to lead back to it
+                    let listen_addr = match dialed {
+                        Some(dialed) => Some(dialed),
+                        None if listen_addr.ip() == observed.ip() => Some(listen_addr),
+                        None => {
+                            debug!(
+                                "Peer {} claims to listen on {}, not learning it",
+                                observed, listen_addr
+                            );
+                            None
+                        }
+                    };
+                    self.peers.lock().unwrap().insert(
+                        observed,
+                        Peer {
+                            sender: sender.clone(),
+                            services,
+                            dialed,
+                            listen_addr,
+                            time_offset: timestamp - Utc::now().timestamp(),
+                        },
+                    );
+
+                    info!("Peer {} joined at height {}", observed, height);
+                    peer_addr = Some(observed);
+                    if let Some(listen_addr) = listen_addr {
+                        self.addresses.lock().unwrap().add(listen_addr);
+                    }
+                    self.update_time_offset();
+                    if dialed.is_some() {
+                        self.save_anchors();
+                    }
+
+                    let _ = sender.send(Message::GetPeers);
+                    if work > self.work() && services & SERVICE_BLOCKS != 0 {
+                        let _ = sender.send(Message::GetBlocks {
+                            from: self.sync_start(),
+                        });
+                    }
+                }
+                (None, _) => {
+                    warn!("Peer sent a message before its handshake");
+                    break;
+                }
+                (Some(addr), message) => {
+                    if let Err(reason) =
+                        self.handle_message(addr, &sender, &mut sync_buffer, message, &line)
+                    {
+                        warn!("Dropping peer {}: {}", addr, reason);
+                        self.capture(Some(addr), reason, &line);
+                        break;
+                    }
+                }
+            }
+        }
+
+        if let Some(addr) = peer_addr {
+            self.peers.lock().unwrap().remove(&addr);
+            info!("Peer {} disconnected", addr);
+            self.update_time_offset();
+        }
+    }
+
+    /// Handles a message of the peer at `addr`. Fails with the reason to drop the peer if it
+    /// broke the protocol.
+    fn handle_message(
+        self: &Arc<Self>,
+        addr: SocketAddr,
+        sender: &UnboundedSender<Message>,
+        sync_buffer: &mut Vec<Block>,
+        message: Message,
+        line: &str,
+    ) -> Result<(), String> {
+        let required = message.required_services();
+        if self.services & required != required {
+            debug!(
+                "Ignoring a message from {} for a service we don't offer",
+                addr
+            );
+            return Ok(());
+        }
+
+        match message {
+            Message::Hello { .. } => warn!("Peer {} repeated its handshake", addr),
+            Message::GetPeers => {
+                let addrs = self
+                    .peers
+                    .lock()
+                    .unwrap()
+                    .values()
+                    .filter_map(|peer| peer.listen_addr)
+                    .take(MAX_ADDRS_PER_MESSAGE)
+                    .collect();
+                let _ = sender.send(Message::Peers { addrs });
+            }
+            Message::Peers { addrs } => {
+                if addrs.len() > MAX_ADDRS_PER_MESSAGE {
+                    return Err(format!("sent {} addresses in one message", addrs.len()));
+                }
+                let mut addresses = self.addresses.lock().unwrap();
+                for gossiped in addrs {
+                    addresses.add_gossiped(gossiped, addr);
+                }
+                drop(addresses);
+                self.fill_outbound();
+            }
+            Message::GetBlocks { from } => {
+                let blocks = self
+                    .blockchain
+                    .read()
+                    .blocks()
+                    .iter()
+                    .skip(from as usize)
+                    .take(MAX_BLOCKS_PER_MESSAGE)
+                    .cloned()
+                    .collect();
+                let _ = sender.send(Message::Blocks { blocks });
+            }
+            Message::Blocks { blocks } => {
+                check_batch(sync_buffer, &blocks)?;
+                let received = blocks.len();
+                sync_buffer.extend(blocks);
+
+                if received == MAX_BLOCKS_PER_MESSAGE {
+                    let from = sync_buffer[sync_buffer.len() - 1].id + 1;
+                    if self.extends_tip(sync_buffer) {
+                        self.sync(addr, sender, std::mem::take(sync_buffer), line);
+                    }
+                    let _ = sender.send(Message::GetBlocks { from });
+                    return Ok(());
+                }
+
+                self.sync(addr, sender, std::mem::take(sync_buffer), line);
+            }
+            Message::NewBlock { block, sent_at } => {
+                let received_at = Instant::now();
+                let hop_ms = (Utc::now().timestamp_millis() - sent_at).max(0) as u64;
+                self.propagation.lock().unwrap().hop.observe(hop_ms);
+
+                let mut blockchain = self.blockchain.write();
+                let extends_tip = match blockchain.tip() {
+                    Some(tip) => block.id == tip.id + 1 && block.previous_hash == tip.hash,
+                    None => block.id == 0,
+                };
+
+                if extends_tip {
+                    let id = block.id;
+                    if let Err(err) = blockchain.try_add_block(block.clone()) {
+                        drop(blockchain);
+                        warn!("Rejected block #{} from {}: {}", id, addr, err);
+                        if !matches!(err, BlockchainError::Io(_)) {
+                            self.telemetry.record(TelemetryEvent::BlockRejected);
+                            self.capture(Some(addr), format!("invalid block: {}", err), line);
+                        }
+                    } else if let Err(err) = blockchain.reward_split().check(&block) {
+                        // Valid, but against our policy: keep it without passing it on
+                        drop(blockchain);
+                        warn!("Not relaying block #{} from {}: {}", id, addr, err);
+                    } else {
+                        drop(blockchain);
+                        let validation_ms = received_at.elapsed().as_millis() as u64;
+                        self.telemetry.record(TelemetryEvent::BlockValidated {
+                            elapsed: received_at.elapsed(),
+                        });
+
+                        self.broadcast(&Message::new_block(block), Some(addr));
+                        let relay_ms = received_at.elapsed().as_millis() as u64;
+
+                        let mut propagation = self.propagation.lock().unwrap();
+                        propagation.validation.observe(validation_ms);
+                        propagation.relay.observe(relay_ms);
+                        debug!(
+                            "Block #{} from {}: {}ms on the wire, validated after {}ms, relayed after {}ms",
+                            id, addr, hop_ms, validation_ms, relay_ms
+                        );
+                    }
+                } else if block.id + 1 >= blockchain.height() && self.offers(addr, SERVICE_BLOCKS) {
+                    drop(blockchain);
+                    debug!(
+                        "Block #{} from {} doesn't extend our tip, syncing",
+                        block.id, addr
+                    );
+                    let _ = sender.send(Message::GetBlocks {
+                        from: self.sync_start(),
+                    });
+                } else {
+                    debug!("Ignoring stale block #{} from {}", block.id, addr);
+                }
+            }
+            Message::NewTransaction { transaction } => {
+                let relayed = Message::NewTransaction {
+                    transaction: transaction.clone(),
+                };
+                let result = self.blockchain.submit_transaction(transaction);
+                match result {
+                    Ok(()) => self.broadcast(&relayed, Some(addr)),
+                    // Gossip delivers the same transaction over several paths and a peer may
+                    // know about funds we don't have yet, so only broken transactions count
+                    Err(
+                        err @ (BlockchainError::InvalidTransaction { .. }
+                        | BlockchainError::CoinbaseSubmitted { .. }),
+                    ) => {
+                        warn!("Peer {} sent an invalid transaction: {}", addr, err);
+                        self.capture(Some(addr), format!("invalid transaction: {}", err), line);
+                    }
+                    Err(err) => debug!("Not relaying transaction from {}: {}", addr, err),
+                }
+            }
+            Message::NewPackage { transactions } => {
+                let relayed = Message::NewPackage {
+                    transactions: transactions.clone(),
+                };
+                let result = self.blockchain.submit_package(transactions);
+                match result {
+                    Ok(()) => self.broadcast(&relayed, Some(addr)),
+                    Err(
+                        err @ (BlockchainError::InvalidTransaction { .. }
+                        | BlockchainError::CoinbaseSubmitted { .. }
+                        | BlockchainError::InvalidPackageSize { .. }),
+                    ) => {
+                        warn!("Peer {} sent an invalid package: {}", addr, err);
+                        self.capture(Some(addr), format!("invalid package: {}", err), line);
+                    }
+                    Err(err) => debug!("Not relaying package from {}: {}", addr, err),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `blocks` start right after our tip.
+    fn extends_tip(&self, blocks: &[Block]) -> bool {
+        let Some(first) = blocks.first() else {
+            return false;
+        };
+        match self.blockchain.tip() {
+            Some(tip) => first.id == tip.id + 1 && first.previous_hash == tip.hash,
+            None => first.id == 0,
+        }
+    }
+
+    /// Connects the blocks a sync with the peer at `addr` brought, switching to its chain if
+    /// they fork off ours and it's heavier.
+    fn sync(
+        self: &Arc<Self>,
+        addr: SocketAddr,
+        sender: &UnboundedSender<Message>,
+        blocks: Vec<Block>,
+        line: &str,
+    ) {
+        let Some(start) = blocks.first().map(|block| block.id as usize) else {
+            return;
+        };
+
+        let mut blockchain = self.blockchain.write();
+        if start > blockchain.blocks().len() {
+            let from = blockchain.height();
+            let _ = sender.send(Message::GetBlocks { from });
+            return;
+        }
+
+        let extends_tip = match blockchain.tip() {
+            Some(tip) => start == blockchain.blocks().len() && blocks[0].previous_hash == tip.hash,
+            None => start == 0,
+        };
+        let started_at = Instant::now();
+        if extends_tip {
+            let count = blocks.len();
+            let result = blockchain.try_add_blocks(blocks);
+            let tip = blockchain.tip().cloned();
+            drop(blockchain);
+            match result {
+                Ok(()) => {
+                    self.telemetry.record(TelemetryEvent::Synced {
+                        blocks: count,
+                        reorg: false,
+                        elapsed: started_at.elapsed(),
+                    });
+                    if let Some(block) = tip {
+                        self.broadcast(&Message::new_block(block), Some(addr));
+                    }
+                }
+                Err(BlockchainError::Io(err)) => {
+                    warn!("Failed to store the blocks of {}: {}", addr, err);
+                }
+                Err(err) => {
+                    warn!("Peer {} sent invalid blocks: {}", addr, err);
+                    self.capture(Some(addr), format!("invalid blocks: {}", err), line);
+                }
+            }
+            return;
+        }
+
+        let mut candidate = blockchain.blocks()[..start].to_vec();
+        candidate.extend(blocks);
+
+        match blockchain.replace_chain(candidate) {
+            Ok(ReplaceChainOutcome::Replaced {
+                fork_id,
+                disconnected,
+                connected,
+            }) => {
+                self.telemetry.record(TelemetryEvent::Synced {
+                    blocks: connected,
+                    reorg: disconnected > 0,
+                    elapsed: started_at.elapsed(),
+                });
+                info!(
+                    "Switched to the chain of {} at height {}: {} block(s) rolled back from #{}, {} connected",
+                    addr,
+                    blockchain.height(),
+                    disconnected,
+                    fork_id,
+                    connected
+                );
+                if disconnected >= LONG_FORK_BLOCKS {
+                    self.alerts.raise_for(
+                        AlertKind::LongFork,
+                        format!(
+                            "a reorg to the chain of {} rolled back {} block(s) from #{}",
+                            addr, disconnected, fork_id
+                        ),
+                        LONG_FORK_ALERT,
+                    );
+                }
+
+                let tip = blockchain.tip().cloned();
+                drop(blockchain);
+                if let Some(block) = tip {
+                    self.broadcast(&Message::new_block(block), Some(addr));
+                }
+            }
+            Ok(ReplaceChainOutcome::NotHeavier) => {
+                debug!("Chain of {} isn't heavier than ours", addr);
+            }
+            Err(BlockchainError::Io(err)) => {
+                warn!("Failed to store the chain of {}: {}", addr, err);
+            }
+            Err(err) if start > 0 => {
+                debug!(
+                    "Fork of {} goes deeper ({}), requesting its whole chain",
+                    addr, err
+                );
+                let _ = sender.send(Message::GetBlocks { from: 0 });
+            }
+            Err(err) => {
+                warn!("Peer {} sent an invalid chain: {}", addr, err);
+                drop(blockchain);
+                self.capture(Some(addr), format!("invalid chain: {}", err), line);
+            }
+        }
+    }
+}
+
+/// Checks a batch of blocks a peer synced to us before buffering it after `buffered`: no more
+/// than a message holds nor than fit the buffer, each block carrying its proof of work and
+/// following the one before it. Everything else is validated once the sync completes.
+fn check_batch(buffered: &[Block], blocks: &[Block]) -> Result<(), String> {
+    if blocks.len() > MAX_BLOCKS_PER_MESSAGE {
+        return Err(format!("sent {} blocks in one message", blocks.len()));
+    }
+    if buffered.len() + blocks.len() > MAX_SYNC_BLOCKS {
+        return Err(format!(
+            "sent more than {} blocks in one sync",
+            MAX_SYNC_BLOCKS
+        ));
+    }
+
+    let mut previous = buffered.last();
+    for block in blocks {
+        if block.calculate_hash() != block.hash || !difficulty::meets(&block.hash, block.difficulty)
+        {
+            return Err(format!(
+                "synced block #{} lacks its proof of work",
+                block.id
+            ));
+        }
+        if let Some(previous) = previous {
+            if block.id != previous.id + 1 || block.previous_hash != previous.hash {
+                return Err(format!(
+                    "synced block #{} doesn't follow block #{}",
+                    block.id, previous.id
+                ));
+            }
+        }
+        previous = Some(block);
+    }
+    Ok(())
+}
+
+/// Seconds to correct our clock by given the `offsets` of the clocks of the peers to it: their
+/// median if it's within [`MAX_TIME_ADJUSTMENT_SECS`], none at all otherwise. Warns if the median
+/// is past [`TIME_OFFSET_WARNING_SECS`]. `None` while there are fewer than [`MIN_TIME_SAMPLES`]
+/// peers to go by.
+fn time_adjustment(mut offsets: Vec<i64>) -> Option<i64> {
+    if offsets.len() < MIN_TIME_SAMPLES {
+        return None;
+    }
+    offsets.sort_unstable();
+    let median = offsets[offsets.len() / 2];
+
+    if median.abs() > TIME_OFFSET_WARNING_SECS {
+        let direction = if median > 0 { "behind" } else { "ahead of" };
+        warn!(
+            "Our clock is {}s {} the median of {} peers, please check it",
+            median.abs(),
+            direction,
+            offsets.len()
+        );
+    }
+    if median.abs() <= MAX_TIME_ADJUSTMENT_SECS {
+        Some(median)
+    } else {
+        Some(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors;
+    use crate::Blockchain;
+
+    /// An address on the loopback interface nothing listens on yet.
+    fn free_addr() -> SocketAddr {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .unwrap()
+    }
+
+    fn node(blocks: Vec<Block>) -> Arc<Network> {
+        let blockchain = ChainHandle::new(Blockchain::from_blocks(blocks).unwrap());
+        Network::new(
+            String::from("test"),
+            free_addr(),
+            ALL_SERVICES,
+            blockchain,
+            None,
+            None,
+            false,
+        )
+    }
+
+    #[tokio::test]
+    async fn nodes_sync_the_chain_of_heavier_peers() {
+        let blocks = vectors::blocks();
+        let ahead = node(blocks[..2].to_vec());
+        let behind = node(blocks[..1].to_vec());
+        ahead.start(Vec::new()).await.unwrap();
+        behind.start(vec![ahead.listen_addr]).await.unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while behind.height() < 2 && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert_eq!(
+            behind.blockchain.tip().map(|block| block.hash),
+            Some(blocks[1].hash.clone())
+        );
+        assert_eq!(behind.peer_count(), 1);
+        assert_eq!(ahead.peer_count(), 1);
+    }
+
+    #[test]
+    fn synced_batches_must_link_and_fit_the_buffer() {
+        let blocks = vectors::blocks();
+        assert!(check_batch(&[], &blocks[..2]).is_ok());
+        assert!(check_batch(&blocks[..1], &blocks[1..2]).is_ok());
+        assert!(check_batch(&blocks[1..2], &blocks[..1]).is_err());
+        // The last vector block was never mined
+        assert!(check_batch(&blocks[..2], &blocks[2..]).is_err());
+        assert!(check_batch(&[], &vec![blocks[0].clone(); MAX_BLOCKS_PER_MESSAGE + 1]).is_err());
+        let buffered = vec![blocks[0].clone(); MAX_SYNC_BLOCKS];
+        assert!(check_batch(&buffered, &blocks[..1]).is_err());
+    }
+
+    /// A connection to `network` from a peer claiming to listen on `listen_addr`, which sends
+    /// `messages` once through the handshake.
+    async fn peer(network: &Network, listen_addr: SocketAddr, messages: &[Message]) -> TcpStream {
+        let mut stream = TcpStream::connect(network.listen_addr).await.unwrap();
+        let hello = Message::Hello {
+            chain_id: String::from("test"),
+            services: ALL_SERVICES,
+            listen_addr,
+            height: 0,
+            work: 0,
+            timestamp: Utc::now().timestamp(),
+        };
+        for message in std::iter::once(&hello).chain(messages) {
+            let mut line = serde_json::to_vec(message).unwrap();
+            line.push(b'\n');
+            stream.write_all(&line).await.unwrap();
+        }
+        stream
+    }
+
+    /// Whether `network` closes `stream` within a few seconds.
+    async fn closes(stream: TcpStream) -> bool {
+        let mut lines = BufReader::new(stream).lines();
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while let Ok(Some(_)) = lines.next_line().await {}
+        })
+        .await
+        .is_ok()
+    }
+
+    async fn wait_for_peers(network: &Network, count: usize) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while network.peer_count() != count && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(network.peer_count(), count);
+    }
+
+    #[tokio::test]
+    async fn peers_syncing_broken_batches_are_dropped() {
+        let blocks = vectors::blocks();
+        let network = node(blocks[..1].to_vec());
+        network.start(Vec::new()).await.unwrap();
+
+        let broken = Message::Blocks {
+            blocks: vec![blocks[1].clone(), blocks[1].clone()],
+        };
+        let stream = peer(&network, free_addr(), &[broken]).await;
+        assert!(closes(stream).await);
+        assert_eq!(network.peer_count(), 0);
+        assert_eq!(network.height(), 1);
+    }
+
+    #[tokio::test]
+    async fn peers_cant_claim_the_address_of_another() {
+        let network = node(vectors::blocks()[..1].to_vec());
+        network.start(Vec::new()).await.unwrap();
+
+        let claimed = free_addr();
+        let _honest = peer(&network, claimed, &[]).await;
+        wait_for_peers(&network, 1).await;
+        let _impostor = peer(&network, claimed, &[]).await;
+        wait_for_peers(&network, 2).await;
+        assert_eq!(network.addresses.lock().unwrap().len(), 1);
+
+        // An address off the IP the peer connects from isn't learned
+        let _elsewhere = peer(&network, SocketAddr::from(([203, 0, 113, 1], 9000)), &[]).await;
+        wait_for_peers(&network, 3).await;
+        assert_eq!(network.addresses.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn peers_gossiping_too_many_addresses_are_dropped() {
+        let network = node(vectors::blocks()[..1].to_vec());
+        network.start(Vec::new()).await.unwrap();
+
+        let flood = Message::Peers {
+            addrs: (0..=MAX_ADDRS_PER_MESSAGE as u16)
+                .map(|port| SocketAddr::from(([10, 0, 0, 1], port)))
+                .collect(),
+        };
+        let stream = peer(&network, free_addr(), &[flood]).await;
+        assert!(closes(stream).await);
+        assert_eq!(network.addresses.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn inbound_connections_are_capped() {
+        let network = node(vectors::blocks()[..1].to_vec());
+        network.start(Vec::new()).await.unwrap();
+
+        let mut open = Vec::new();
+        for _ in 0..MAX_INBOUND {
+            open.push(TcpStream::connect(network.listen_addr).await.unwrap());
+        }
+        let refused = TcpStream::connect(network.listen_addr).await.unwrap();
+        assert!(closes(refused).await);
+        assert_eq!(network.inbound.load(Ordering::Relaxed), MAX_INBOUND);
+    }
+
+    #[test]
+    fn clocks_are_corrected_by_the_median_peer_within_bounds() {
+        assert_eq!(time_adjustment(Vec::new()), None);
+        assert_eq!(time_adjustment(vec![100, 100]), None);
+        assert_eq!(time_adjustment(vec![-5, 300, 2]), Some(2));
+        assert_eq!(time_adjustment(vec![40, -700, 35, 900]), Some(40));
+        assert_eq!(
+            time_adjustment(vec![MAX_TIME_ADJUSTMENT_SECS; 3]),
+            Some(MAX_TIME_ADJUSTMENT_SECS)
+        );
+        assert_eq!(
+            time_adjustment(vec![-MAX_TIME_ADJUSTMENT_SECS - 1; 3]),
+            Some(0)
+        );
+    }
+
+    #[tokio::test]
+    async fn clock_offsets_are_recomputed_as_peers_leave() {
+        let network = node(vectors::blocks()[..1].to_vec());
+        network.start(Vec::new()).await.unwrap();
+
+        // A peer whose clock is `offset` seconds ahead of ours
+        let skewed = |offset: i64| {
+            let listen_addr = network.listen_addr;
+            async move {
+                let mut stream = TcpStream::connect(listen_addr).await.unwrap();
+                let hello = Message::Hello {
+                    chain_id: String::from("test"),
+                    services: ALL_SERVICES,
+                    listen_addr: free_addr(),
+                    height: 0,
+                    work: 0,
+                    timestamp: Utc::now().timestamp() + offset,
+                };
+                let mut line = serde_json::to_vec(&hello).unwrap();
+                line.push(b'\n');
+                stream.write_all(&line).await.unwrap();
+                stream
+            }
+        };
+
+        let _slow = skewed(100).await;
+        let _fast = skewed(200).await;
+        wait_for_peers(&network, 2).await;
+        assert_eq!(network.blockchain.read().time_offset(), 0);
+
+        let fastest = skewed(200).await;
+        wait_for_peers(&network, 3).await;
+        let _slowest = skewed(100).await;
+        wait_for_peers(&network, 4).await;
+        // Allow a second for the clock ticking over while the handshake is on its way
+        assert!((199..=200).contains(&network.blockchain.read().time_offset()));
+
+        drop(fastest);
+        wait_for_peers(&network, 3).await;
+        assert!((99..=100).contains(&network.blockchain.read().time_offset()));
+    }
+}
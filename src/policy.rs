@@ -0,0 +1,151 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::transaction::Transaction;
+use crate::Blockchain;
+
+/// What a [`MempoolPolicy`] made of a transaction.
+pub enum Verdict {
+    Accept,
+    /// Accept the transaction, attaching `note` to its mempool entry, see [`Mempool::notes`].
+    ///
+    /// [`Mempool::notes`]: crate::mempool::Mempool::notes
+    Annotate(String),
+    /// Keep the transaction out of the mempool for `reason`.
+    Reject(String),
+}
+
+/// A rule of this node on which transactions it takes into its mempool, e.g. a rate limit or
+/// an allowlist, registered with [`Blockchain::add_mempool_policy`].
+///
+/// Policies are checked after a transaction passed validation, and only decide over our own
+/// mempool: a block including a transaction some policy would reject is still valid.
+pub trait MempoolPolicy: Send + Sync {
+    /// Names the policy in rejections and notes.
+    fn name(&self) -> &str;
+
+    /// Decides on `transaction`, about to be queued on top of `blockchain`.
+    fn check(&self, transaction: &Transaction, blockchain: &Blockchain) -> Verdict;
+}
+
+/// Only takes transactions sent by one of a fixed set of addresses.
+pub struct Allowlist {
+    senders: HashSet<String>,
+}
+
+/// Takes at most `max` transactions per sender within any `window`. Every transaction the
+/// limit lets through counts, whether the mempool ends up taking it or not.
+pub struct RateLimit {
+    max: usize,
+    window: Duration,
+    /// When each sender's transactions within the window were let through, oldest first.
+    seen: Mutex<HashMap<String, VecDeque<Instant>>>,
+}
+
+impl Allowlist {
+    pub fn new(senders: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            senders: senders.into_iter().collect(),
+        }
+    }
+}
+
+impl MempoolPolicy for Allowlist {
+    fn name(&self) -> &str {
+        "allowlist"
+    }
+
+    fn check(&self, transaction: &Transaction, _: &Blockchain) -> Verdict {
+        if self.senders.contains(&transaction.sender) {
+            Verdict::Accept
+        } else {
+            Verdict::Reject(format!("sender {} isn't allowed", transaction.sender))
+        }
+    }
+}
+
+impl RateLimit {
+    pub fn new(max: usize, window: Duration) -> Self {
+        Self {
+            max,
+            window,
+            seen: Mutex::default(),
+        }
+    }
+}
+
+impl MempoolPolicy for RateLimit {
+    fn name(&self) -> &str {
+        "rate-limit"
+    }
+
+    fn check(&self, transaction: &Transaction, _: &Blockchain) -> Verdict {
+        let now = Instant::now();
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, times| {
+            while times
+                .front()
+                .is_some_and(|time| now.duration_since(*time) >= self.window)
+            {
+                times.pop_front();
+            }
+            !times.is_empty()
+        });
+
+        let times = seen.entry(transaction.sender.clone()).or_default();
+        if times.len() >= self.max {
+            return Verdict::Reject(format!(
+                "sender {} already sent {} transaction(s) within {}s",
+                transaction.sender,
+                times.len(),
+                self.window.as_secs()
+            ));
+        }
+        times.push_back(now);
+        Verdict::Accept
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors;
+    use crate::wallet::Wallet;
+    use crate::BlockchainError;
+
+    #[test]
+    fn mempool_policies_only_judge_otherwise_valid_transactions() {
+        let (sender, stranger) = (Wallet::generate(), Wallet::generate());
+        let transaction = sender.transfer(stranger.address(), 5, 0);
+        let mut blockchain = Blockchain::from_blocks(vectors::blocks()[..2].to_vec()).unwrap();
+        blockchain.add_mempool_policy(Box::new(Allowlist::new([stranger.address()])));
+
+        // The sender can't afford it, so the allowlist never gets to reject it
+        assert!(matches!(
+            blockchain.submit_transaction(transaction.clone()),
+            Err(BlockchainError::InsufficientFunds { .. })
+        ));
+
+        let rate_limit = RateLimit::new(1, Duration::from_secs(60));
+        assert!(matches!(
+            rate_limit.check(&transaction, &blockchain),
+            Verdict::Accept
+        ));
+        assert!(matches!(
+            rate_limit.check(&transaction, &blockchain),
+            Verdict::Reject(_)
+        ));
+
+        let allowlist = Allowlist::new([sender.address()]);
+        assert!(matches!(
+            allowlist.check(&transaction, &blockchain),
+            Verdict::Accept
+        ));
+        let unlisted = stranger.transfer(sender.address(), 5, 0);
+        assert!(matches!(
+            allowlist.check(&unlisted, &blockchain),
+            Verdict::Reject(_)
+        ));
+    }
+}
@@ -0,0 +1,149 @@
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+use crate::transaction::Transaction;
+use crate::Blockchain;
+
+/// How often the transactions we originated are looked at for rebroadcasting.
+pub const REBROADCAST_INTERVAL: Duration = Duration::from_secs(30);
+/// How long after its first announcement a transaction is announced again if still pending;
+/// the delay doubles with every rebroadcast.
+pub const FIRST_REBROADCAST: Duration = Duration::from_secs(60);
+/// Longest delay between two rebroadcasts of a transaction.
+pub const MAX_REBROADCAST_DELAY: Duration = Duration::from_secs(60 * 60);
+/// Most times a transaction is rebroadcast before we stop trying.
+pub const MAX_REBROADCASTS: u32 = 10;
+
+/// Transactions submitted to this node rather than relayed to it, announced again until a
+/// block confirms them so a payment isn't lost to peers missing the first announcement.
+/// Dependent transactions submitted together are tracked, and rebroadcast, as one package.
+#[derive(Default)]
+pub struct LocalTransactions {
+    tracked: Vec<Tracked>,
+}
+
+struct Tracked {
+    transactions: Vec<Transaction>,
+    rebroadcasts: u32,
+    next_at: Instant,
+}
+
+impl LocalTransactions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts tracking `transactions`, announced for the first time at `now`.
+    pub fn track(&mut self, transactions: Vec<Transaction>, now: Instant) {
+        self.tracked.push(Tracked {
+            transactions,
+            rebroadcasts: 0,
+            next_at: now + FIRST_REBROADCAST,
+        });
+    }
+
+    /// Number of packages still tracked.
+    pub fn len(&self) -> usize {
+        self.tracked.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tracked.is_empty()
+    }
+
+    /// Stops tracking the transactions a block of `blockchain` confirmed, so only those still
+    /// pending are announced again, and the packages that left its mempool unconfirmed, e.g.
+    /// because they expired or a block spent their funds.
+    pub fn forget_settled(&mut self, blockchain: &Blockchain) {
+        self.tracked.retain_mut(|tracked| {
+            tracked
+                .transactions
+                .retain(|transaction| blockchain.confirmations(&transaction.hash()) == 0);
+            let Some(first) = tracked.transactions.first() else {
+                return false;
+            };
+            if !tracked
+                .transactions
+                .iter()
+                .any(|transaction| blockchain.mempool().contains(&transaction.hash()))
+            {
+                info!("Transaction {} left the mempool unconfirmed", first.hash());
+                return false;
+            }
+            true
+        });
+    }
+
+    /// The packages to announce again at `now`. Those rebroadcast [`MAX_REBROADCASTS`] times
+    /// already are given up on instead.
+    pub fn due(&mut self, now: Instant) -> Vec<Vec<Transaction>> {
+        let mut due = Vec::new();
+        self.tracked.retain_mut(|tracked| {
+            if tracked.next_at > now {
+                return true;
+            }
+            if tracked.rebroadcasts >= MAX_REBROADCASTS {
+                warn!(
+                    "Giving up on transaction {} after {} rebroadcasts",
+                    tracked.transactions[0].hash(),
+                    tracked.rebroadcasts
+                );
+                return false;
+            }
+
+            tracked.rebroadcasts += 1;
+            let delay = FIRST_REBROADCAST.saturating_mul(1 << tracked.rebroadcasts.min(16));
+            tracked.next_at = now + delay.min(MAX_REBROADCAST_DELAY);
+            due.push(tracked.transactions.clone());
+            true
+        });
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors;
+    use crate::wallet::{Wallet, DEFAULT_NETWORK};
+
+    #[test]
+    fn local_transactions_are_rebroadcast_with_backoff_until_settled() {
+        let blocks = vectors::blocks();
+        let confirmed = blocks[1].transactions[1].clone();
+        let blockchain = Blockchain::from_blocks(blocks[..2].to_vec()).unwrap();
+        let mut local = LocalTransactions::new();
+        let start = Instant::now();
+        local.track(vec![confirmed.clone()], start);
+
+        assert!(local.due(start).is_empty());
+        let after = start + FIRST_REBROADCAST;
+        let due = local.due(after);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0][0].hash(), confirmed.hash());
+        // The next rebroadcast waits twice as long
+        assert!(local.due(after + FIRST_REBROADCAST).is_empty());
+        assert_eq!(local.due(after + 2 * FIRST_REBROADCAST).len(), 1);
+        assert_eq!(local.due(after + 100 * MAX_REBROADCAST_DELAY).len(), 1);
+
+        // Neither a confirmed transaction nor one that isn't pending is tracked any longer
+        let (sender, recipient) = (Wallet::generate(), Wallet::generate());
+        local.track(vec![sender.transfer(recipient.address(), 5, 0)], start);
+        assert_eq!(local.len(), 2);
+        local.forget_settled(&blockchain);
+        assert!(local.is_empty());
+
+        // Only the members of a package still pending are announced again
+        let mut blockchain = blockchain;
+        let sender = Wallet::from_secret_key(DEFAULT_NETWORK, [1; 32]);
+        let pending = sender.transfer(recipient.address(), 5, 1);
+        blockchain.submit_transaction(pending.clone()).unwrap();
+        local.track(vec![confirmed, pending.clone()], start);
+        local.forget_settled(&blockchain);
+        let due = local.due(after);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].len(), 1);
+        assert_eq!(due[0][0].hash(), pending.hash());
+    }
+}
@@ -0,0 +1,408 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::blockchain::BLOCK_REWARD;
+use crate::decisions::{Decision, Input};
+use crate::transaction::Transaction;
+use crate::{Block, BlockchainError};
+
+/// A cut of the block reward going to someone other than the miner, e.g. an operator fund, or
+/// a burn address nobody holds the key of.
+#[derive(Clone, Debug, Deserialize)]
+pub struct RewardShare {
+    /// What the share is for, only used to report it.
+    pub role: String,
+    pub address: String,
+    /// Percentage of [`BLOCK_REWARD`] paid to `address`, rounded down.
+    pub percent: u64,
+}
+
+/// How a node splits the reward of the blocks it mines: the shares first, then whatever they
+/// leave to the miner.
+///
+/// The split is policy, not consensus: a block paying its reward some other way is still
+/// valid. Nodes configured with the same split hold each other to it with
+/// [`RewardSplit::check`].
+#[derive(Clone, Debug, Default)]
+pub struct RewardSplit {
+    shares: Vec<RewardShare>,
+}
+
+impl RewardShare {
+    fn amount(&self) -> u64 {
+        BLOCK_REWARD * self.percent / 100
+    }
+}
+
+impl FromStr for RewardShare {
+    type Err = String;
+
+    /// Parses `role:address:percent`.
+    fn from_str(share: &str) -> Result<Self, Self::Err> {
+        let mut parts = share.splitn(3, ':');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(role), Some(address), Some(percent)) if !address.is_empty() => Ok(Self {
+                role: role.to_string(),
+                address: address.to_string(),
+                percent: percent
+                    .parse()
+                    .map_err(|err| format!("invalid percentage in {}: {}", share, err))?,
+            }),
+            _ => Err(format!(
+                "malformed reward share {}, expected role:address:percent",
+                share
+            )),
+        }
+    }
+}
+
+impl RewardSplit {
+    /// Fails if the shares add up to more than the whole reward.
+    pub fn new(shares: Vec<RewardShare>) -> Result<Self, BlockchainError> {
+        let percent = shares.iter().fold(0, |percent: u64, share| {
+            percent.saturating_add(share.percent)
+        });
+        if percent > 100 {
+            return Err(BlockchainError::InvalidRewardSplit { percent });
+        }
+        Ok(Self { shares })
+    }
+
+    pub fn shares(&self) -> &[RewardShare] {
+        &self.shares
+    }
+
+    /// The coinbase transactions of a block mined by `miner_address`: one per address the
+    /// shares pay, in order, then one paying the rest to the miner. Shares rounding down to
+    /// nothing are left out.
+    pub fn coinbase(&self, miner_address: &str) -> Vec<Transaction> {
+        let mut transactions: Vec<Transaction> = Vec::new();
+        let payouts = self
+            .shares
+            .iter()
+            .map(|share| (share.address.as_str(), share.amount()));
+
+        for (address, amount) in payouts {
+            if amount == 0 {
+                continue;
+            }
+            match transactions
+                .iter_mut()
+                .find(|transaction| transaction.recipient == address)
+            {
+                Some(transaction) => transaction.amount += amount,
+                None => transactions.push(Transaction::coinbase(address.to_string(), amount)),
+            }
+        }
+
+        let shared: u64 = transactions
+            .iter()
+            .map(|transaction| transaction.amount)
+            .sum();
+        let rest = BLOCK_REWARD - shared;
+        if rest > 0 {
+            match transactions
+                .iter_mut()
+                .find(|transaction| transaction.recipient == miner_address)
+            {
+                Some(transaction) => transaction.amount += rest,
+                None => transactions.push(Transaction::coinbase(miner_address.to_string(), rest)),
+            }
+        }
+        transactions
+    }
+
+    /// Checks that the coinbase transactions of `block` pay every share at least its cut. The
+    /// genesis block pays no reward at all.
+    pub fn check(&self, block: &Block) -> Result<(), BlockchainError> {
+        if block.id == 0 {
+            return Ok(());
+        }
+
+        for share in &self.shares {
+            let owed: u64 = self
+                .shares
+                .iter()
+                .filter(|other| other.address == share.address)
+                .map(RewardShare::amount)
+                .sum();
+            let paid: u64 = block
+                .transactions
+                .iter()
+                .filter(|transaction| {
+                    transaction.is_coinbase() && transaction.recipient == share.address
+                })
+                .map(|transaction| transaction.amount)
+                .sum();
+
+            if paid < owed {
+                return Err(BlockchainError::RewardShareUnpaid {
+                    id: block.id,
+                    role: share.role.clone(),
+                    owed,
+                    paid,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What the blocks of a chain paid one address, see `mining report`.
+#[derive(Debug, PartialEq)]
+pub struct MiningReport {
+    pub address: String,
+    /// Id of the first block looked at.
+    pub since: u64,
+    /// Stored blocks from `since` on.
+    pub blocks: u64,
+    /// How many of them paid `address`.
+    pub found: u64,
+    /// Block subsidy they paid it. Transactions carry no fees, so it's all `address` earned.
+    pub subsidy: u64,
+    /// Sum of their difficulties, i.e. the hashes expected to find them.
+    pub work: u64,
+    /// Blocks paying `address` the chain connected but a reorg disconnected since, `None`
+    /// without a decision log to tell.
+    pub orphaned: Option<u64>,
+}
+
+impl MiningReport {
+    /// Reports on the stored `blocks` from `since` on, along with the blocks of the chain's
+    /// `decisions`, if it logs them, a reorg left out of it.
+    pub fn new(
+        address: &str,
+        since: u64,
+        blocks: &[Block],
+        decisions: Option<&[Decision]>,
+    ) -> Self {
+        let mut report = Self {
+            address: address.to_string(),
+            since,
+            blocks: 0,
+            found: 0,
+            subsidy: 0,
+            work: 0,
+            orphaned: None,
+        };
+        for block in blocks.iter().filter(|block| block.id >= since) {
+            report.blocks += 1;
+            let reward = paid(block, address);
+            if reward > 0 {
+                report.found += 1;
+                report.subsidy += reward;
+                report.work += block.difficulty;
+            }
+        }
+
+        report.orphaned = decisions.map(|decisions| {
+            let stored: HashSet<&str> = blocks.iter().map(|block| block.hash.as_str()).collect();
+            let connected = decisions
+                .iter()
+                .filter(|decision| {
+                    !decision.outcome.starts_with("refused") && decision.outcome != "not heavier"
+                })
+                .flat_map(|decision| match &decision.input {
+                    Input::Block { block } => std::slice::from_ref(block),
+                    Input::Blocks { blocks } | Input::ReplaceChain { blocks, .. } => blocks,
+                    _ => &[],
+                });
+            let orphaned: HashSet<&str> = connected
+                .filter(|block| block.id >= since && paid(block, address) > 0)
+                .map(|block| block.hash.as_str())
+                .filter(|hash| !stored.contains(hash))
+                .collect();
+            orphaned.len() as u64
+        });
+        report
+    }
+}
+
+impl fmt::Display for MiningReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} of {} block(s) from #{} on paid {}",
+            self.found, self.blocks, self.since, self.address
+        )?;
+        if self.blocks > 0 {
+            writeln!(
+                f,
+                "  Share of the blocks: {:.1}%",
+                100.0 * self.found as f64 / self.blocks as f64
+            )?;
+        }
+        writeln!(
+            f,
+            "  Rewards earned: {} (all block subsidy, the chain has no fees)",
+            self.subsidy
+        )?;
+        match self.orphaned {
+            Some(orphaned) => writeln!(f, "  Orphaned by reorgs: {} block(s)", orphaned)?,
+            None => writeln!(f, "  Orphaned by reorgs: unknown without a decision log")?,
+        }
+        if self.work > 0 {
+            writeln!(f, "  Expected hashes to find them: {}", self.work)?;
+            writeln!(
+                f,
+                "  Earned per million hashes: {:.6}",
+                self.subsidy as f64 * 1e6 / self.work as f64
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// What the coinbase transactions of `block` pay `address`.
+fn paid(block: &Block, address: &str) -> u64 {
+    block
+        .transactions
+        .iter()
+        .filter(|transaction| transaction.is_coinbase() && transaction.recipient == address)
+        .map(|transaction| transaction.amount)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors;
+
+    #[test]
+    fn reward_split_pays_its_shares() {
+        let blocks = vectors::blocks();
+        let miner = blocks[1].transactions[0].recipient.clone();
+        let shares: Vec<RewardShare> = ["operator:operator-fund:10", "burn:burn:5"]
+            .into_iter()
+            .map(|share| share.parse().unwrap())
+            .collect();
+        let split = RewardSplit::new(shares.clone()).unwrap();
+
+        let coinbase = split.coinbase(&miner);
+        let amounts: Vec<u64> = coinbase
+            .iter()
+            .map(|transaction| transaction.amount)
+            .collect();
+        assert_eq!(amounts, [5, 2, BLOCK_REWARD - 7]);
+        assert_eq!(coinbase[2].recipient, miner);
+
+        let mut block = blocks[1].clone();
+        assert!(matches!(
+            split.check(&block),
+            Err(BlockchainError::RewardShareUnpaid {
+                owed: 5,
+                paid: 0,
+                ..
+            })
+        ));
+        block.transactions.splice(..1, coinbase);
+        split.check(&block).expect("block should pay every share");
+
+        let greedy: RewardShare = "greedy:somebody:90".parse().unwrap();
+        assert!(matches!(
+            RewardSplit::new([shares, vec![greedy]].concat()),
+            Err(BlockchainError::InvalidRewardSplit { percent: 105 })
+        ));
+    }
+
+    /// Block `id` with hash `hash`, paying its reward to `miner`.
+    fn block(id: u64, hash: &str, miner: &str) -> Block {
+        Block {
+            id,
+            hash: hash.to_string(),
+            previous_hash: String::new(),
+            timestamp: 0,
+            difficulty: 1_000,
+            merkle_root: String::new(),
+            transactions: vec![Transaction::coinbase(miner.to_string(), BLOCK_REWARD)],
+            nonce: 0,
+        }
+    }
+
+    fn decision(input: Input, outcome: &str) -> Decision {
+        Decision {
+            at: 0,
+            input,
+            outcome: outcome.to_string(),
+        }
+    }
+
+    #[test]
+    fn mining_reports_count_orphaned_blocks_from_the_decision_log() {
+        let stored = vec![
+            block(0, "genesis", "nobody"),
+            block(1, "ours", "alice"),
+            block(2, "theirs", "bob"),
+            block(3, "ours-again", "alice"),
+        ];
+        let report = MiningReport::new("alice", 1, &stored, None);
+        assert_eq!(
+            report,
+            MiningReport {
+                address: String::from("alice"),
+                since: 1,
+                blocks: 3,
+                found: 2,
+                subsidy: 2 * BLOCK_REWARD,
+                work: 2_000,
+                orphaned: None,
+            }
+        );
+        assert!(report.to_string().contains("no fees"));
+
+        let decisions = [
+            decision(
+                Input::Block {
+                    block: stored[1].clone(),
+                },
+                "connected",
+            ),
+            // Mined on top of `ours`, then lost to `theirs` and refused again later
+            decision(
+                Input::Block {
+                    block: block(2, "orphan", "alice"),
+                },
+                "connected",
+            ),
+            decision(
+                Input::Block {
+                    block: block(2, "orphan", "alice"),
+                },
+                "refused: stale",
+            ),
+            decision(
+                Input::Block {
+                    block: block(2, "never-connected", "alice"),
+                },
+                "refused: invalid",
+            ),
+            decision(
+                Input::ReplaceChain {
+                    fork: 2,
+                    blocks: vec![block(2, "heavier", "alice")],
+                },
+                "not heavier",
+            ),
+            decision(
+                Input::ReplaceChain {
+                    fork: 2,
+                    blocks: stored[2..].to_vec(),
+                },
+                "replaced 1 block(s) from #2 with 2",
+            ),
+        ];
+        let report = MiningReport::new("alice", 1, &stored, Some(&decisions));
+        assert_eq!(report.orphaned, Some(1));
+        assert_eq!(
+            MiningReport::new("alice", 3, &stored, Some(&decisions)).orphaned,
+            Some(0)
+        );
+        assert_eq!(
+            MiningReport::new("bob", 0, &stored, Some(&decisions)).orphaned,
+            Some(0)
+        );
+    }
+}
@@ -0,0 +1,112 @@
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use log::info;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{Block, Blockchain, BlockchainError};
+
+/// Prefix of binary snapshots, telling them apart from JSON ones on import.
+const BINCODE_MAGIC: &[u8] = b"BCHNBIN1";
+
+/// Encoding of a chain snapshot written by [`Blockchain::export`].
+#[derive(Clone, Copy)]
+pub enum Format {
+    /// Human-readable, the same encoding blocks are stored and sent to peers in.
+    Json,
+    /// Compact binary encoding.
+    Bincode,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    /// Parses `json` or `bincode`.
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "json" => Ok(Self::Json),
+            "bincode" => Ok(Self::Bincode),
+            _ => Err(format!(
+                "unknown snapshot format {}, expected json or bincode",
+                format
+            )),
+        }
+    }
+}
+
+/// What a snapshot holds. Balances can be derived from the blocks and the mempool and storage
+/// are local to a node, so only the blocks are kept.
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    blocks: &'a [Block],
+}
+
+#[derive(Deserialize)]
+struct Snapshot {
+    blocks: Vec<Block>,
+}
+
+impl Serialize for Blockchain {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SnapshotRef {
+            blocks: self.blocks(),
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Deserializing a chain validates it in full, see [`Blockchain::from_blocks`].
+impl<'de> Deserialize<'de> for Blockchain {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let snapshot = Snapshot::deserialize(deserializer)?;
+        Blockchain::from_blocks(snapshot.blocks).map_err(D::Error::custom)
+    }
+}
+
+impl Blockchain {
+    /// Writes the active chain to `path`.
+    pub fn export<P: AsRef<Path>>(&self, path: P, format: Format) -> Result<(), BlockchainError> {
+        let bytes = match format {
+            Format::Json => serde_json::to_vec_pretty(self)
+                .map_err(|err| BlockchainError::Encoding(err.to_string()))?,
+            Format::Bincode => {
+                let mut bytes = BINCODE_MAGIC.to_vec();
+                bincode::serialize_into(&mut bytes, self)
+                    .map_err(|err| BlockchainError::Encoding(err.to_string()))?;
+                bytes
+            }
+        };
+
+        fs::write(&path, bytes)?;
+        info!(
+            "Exported {} block(s) to {}",
+            self.height(),
+            path.as_ref().display()
+        );
+        Ok(())
+    }
+
+    /// Reads a chain written by [`Blockchain::export`] in either format and validates it in
+    /// full, ids, previous hashes, proofs of work, timestamps and transactions included. The
+    /// chain is kept in memory only.
+    pub fn import<P: AsRef<Path>>(path: P) -> Result<Self, BlockchainError> {
+        let bytes = fs::read(&path)?;
+
+        let snapshot: Snapshot = match bytes.strip_prefix(BINCODE_MAGIC) {
+            Some(encoded) => bincode::deserialize(encoded)
+                .map_err(|err| BlockchainError::Encoding(err.to_string()))?,
+            None => serde_json::from_slice(&bytes)
+                .map_err(|err| BlockchainError::Encoding(err.to_string()))?,
+        };
+
+        let blockchain = Blockchain::from_blocks(snapshot.blocks)?;
+        info!(
+            "Imported {} block(s) from {}",
+            blockchain.height(),
+            path.as_ref().display()
+        );
+        Ok(blockchain)
+    }
+}
@@ -0,0 +1,101 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::difficulty::RETARGET_INTERVAL;
+use crate::miner::Miner;
+use crate::network::Network;
+use crate::{Blockchain, ChainHandle};
+
+/// How often a sample is taken.
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+/// How many samples the history keeps, five minutes' worth.
+pub const MAX_SAMPLES: usize = 300;
+
+/// Vital signs of the node at one point in time.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StatsSample {
+    /// When the sample was taken, in Unix seconds.
+    pub at: i64,
+    pub height: u64,
+    /// Hashes per second our miner tried since the previous sample.
+    pub hashrate: f64,
+    /// Average time between the last [`RETARGET_INTERVAL`] blocks, once there are two.
+    pub block_interval_secs: Option<f64>,
+    pub mempool_size: usize,
+    pub peer_count: usize,
+    /// Alerts up, see [`crate::alerts::Alerts`].
+    #[serde(default)]
+    pub alert_count: usize,
+}
+
+/// Rolling history of the last [`MAX_SAMPLES`] samples, kept in process so the node can be
+/// watched without any external monitoring.
+#[derive(Default)]
+pub struct StatsHistory {
+    samples: Mutex<VecDeque<StatsSample>>,
+}
+
+impl StatsHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends `sample`, dropping the oldest one once the history is full.
+    pub fn record(&self, sample: StatsSample) {
+        let mut samples = self.samples.lock().unwrap();
+        if samples.len() == MAX_SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back(sample);
+    }
+
+    /// The samples, oldest first.
+    pub fn samples(&self) -> Vec<StatsSample> {
+        self.samples.lock().unwrap().iter().cloned().collect()
+    }
+
+    /// Samples the node every [`SAMPLE_INTERVAL`] into the history, forever.
+    pub async fn run(
+        self: Arc<Self>,
+        blockchain: ChainHandle,
+        network: Arc<Network>,
+        miner: Arc<Miner>,
+    ) {
+        let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+        let mut previous = (Instant::now(), miner.hashes());
+
+        loop {
+            interval.tick().await;
+            let (now, hashes) = (Instant::now(), miner.hashes());
+            let elapsed = now.duration_since(previous.0).as_secs_f64();
+            let hashrate = (hashes - previous.1) as f64 / elapsed.max(f64::EPSILON);
+            previous = (now, hashes);
+
+            let blockchain = blockchain.read();
+            self.record(StatsSample {
+                at: Utc::now().timestamp(),
+                height: blockchain.height(),
+                hashrate,
+                block_interval_secs: block_interval_secs(&blockchain),
+                mempool_size: blockchain.mempool().len(),
+                peer_count: network.peer_count(),
+                alert_count: network.alerts().active().len(),
+            });
+        }
+    }
+}
+
+fn block_interval_secs(blockchain: &Blockchain) -> Option<f64> {
+    let blocks = blockchain.blocks();
+    let recent = &blocks[blocks.len().saturating_sub(RETARGET_INTERVAL as usize)..];
+    let (first, last) = (recent.first()?, recent.last()?);
+    if recent.len() < 2 {
+        return None;
+    }
+
+    Some((last.timestamp - first.timestamp) as f64 / (recent.len() - 1) as f64)
+}
@@ -0,0 +1,283 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+use serde::de::DeserializeOwned;
+
+use crate::{Block, BlockHeader};
+
+/// Append-only block storage: one JSON-encoded block per line, in chain order.
+pub struct Storage {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    /// Byte offset at which each stored block ends, used to keep the blocks a reorg doesn't
+    /// roll back.
+    offsets: Vec<u64>,
+}
+
+/// Forward-only reader over a block storage file, decoding one block at a time so a chain of
+/// any size can be walked in constant memory. Reading [`BlockHeader`]s skips over the
+/// transactions without decoding them, see [`BlockReader::headers`].
+///
+/// Nothing is validated; load a [`crate::Blockchain`] for that. A trailing partial line left
+/// behind by an interrupted write ends the iteration, like it's dropped by
+/// [`Storage::read_blocks`].
+pub struct BlockReader<T> {
+    reader: BufReader<File>,
+    line: String,
+    line_number: usize,
+    decoded: PhantomData<T>,
+}
+
+impl BlockReader<Block> {
+    /// Reads the stored blocks with their transactions.
+    pub fn blocks<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::open(path)
+    }
+}
+
+impl BlockReader<BlockHeader> {
+    /// Reads the headers of the stored blocks only.
+    pub fn headers<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::open(path)
+    }
+}
+
+impl<T> BlockReader<T> {
+    fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(Self {
+            reader: BufReader::new(File::open(path)?),
+            line: String::new(),
+            line_number: 0,
+            decoded: PhantomData,
+        })
+    }
+}
+
+impl<T: DeserializeOwned> Iterator for BlockReader<T> {
+    type Item = io::Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.line.clear();
+        match self.reader.read_line(&mut self.line) {
+            Ok(0) => return None,
+            Ok(_) => self.line_number += 1,
+            Err(err) => return Some(Err(err)),
+        }
+
+        let Some(line) = self.line.strip_suffix('\n') else {
+            warn!(
+                "Stopping at the partially written block on line {}",
+                self.line_number
+            );
+            return None;
+        };
+        Some(serde_json::from_str(line).map_err(|err| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("corrupted block on line {}: {}", self.line_number, err),
+            )
+        }))
+    }
+}
+
+impl Storage {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        info!("Opened block storage at {}", path.display());
+
+        Ok(Self {
+            path,
+            writer: BufWriter::new(file),
+            offsets: Vec::new(),
+        })
+    }
+
+    /// Reads every stored block.
+    ///
+    /// A trailing line that can't be decoded is treated as a write interrupted by a crash: it is
+    /// dropped and the file is truncated back to the last complete block. Undecodable lines
+    /// anywhere else mean the file is corrupted and are reported as an error.
+    pub fn read_blocks(&mut self) -> io::Result<Vec<Block>> {
+        let reader = BufReader::new(File::open(&self.path)?);
+        let lines = reader.lines().collect::<io::Result<Vec<_>>>()?;
+
+        let mut blocks = Vec::with_capacity(lines.len());
+        let mut valid_len = 0;
+        self.offsets.clear();
+
+        for (index, line) in lines.iter().enumerate() {
+            match serde_json::from_str::<Block>(line) {
+                Ok(block) => {
+                    blocks.push(block);
+                    valid_len += line.len() as u64 + 1;
+                    self.offsets.push(valid_len);
+                }
+                Err(err) if index == lines.len() - 1 => {
+                    warn!(
+                        "Discarding partially written block at the end of {}: {}",
+                        self.path.display(),
+                        err
+                    );
+                    self.writer.get_ref().set_len(valid_len)?;
+                }
+                Err(err) => {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("corrupted block on line {}: {}", index + 1, err),
+                    ));
+                }
+            }
+        }
+
+        Ok(blocks)
+    }
+
+    /// Appends a block. The line is handed to the OS right away so a crash of the process
+    /// doesn't lose it; use [`Storage::flush`] to also force it onto the disk.
+    pub fn append(&mut self, block: &Block) -> io::Result<()> {
+        self.append_all(std::slice::from_ref(block))
+    }
+
+    /// Appends consecutive blocks in a single write. A crash halfway through leaves a prefix of
+    /// them stored, which is still a valid chain.
+    pub fn append_all(&mut self, blocks: &[Block]) -> io::Result<()> {
+        let start = self.offsets.last().copied().unwrap_or(0);
+        let (lines, ends) = Self::encode(blocks, start)?;
+
+        self.writer.write_all(&lines)?;
+        self.writer.flush()?;
+
+        self.offsets.extend(ends);
+        Ok(())
+    }
+
+    /// Replaces every stored block after the first `len` ones with `blocks`, as a reorg does.
+    /// The new chain is written to a temporary file renamed over the storage once complete, so
+    /// a failure or a crash halfway through leaves the old chain stored.
+    pub fn replace(&mut self, len: usize, blocks: &[Block]) -> io::Result<()> {
+        let len = len.min(self.offsets.len());
+        let kept = len.checked_sub(1).map_or(0, |last| self.offsets[last]);
+        let (lines, ends) = Self::encode(blocks, kept)?;
+        self.writer.flush()?;
+
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        let temporary = PathBuf::from(temporary);
+        let file = self
+            .write_replacement(&temporary, kept, &lines)
+            .inspect_err(|_| {
+                let _ = fs::remove_file(&temporary);
+            })?;
+
+        self.writer = BufWriter::new(file);
+        self.offsets.truncate(len);
+        self.offsets.extend(ends);
+        Ok(())
+    }
+
+    /// Writes the first `kept` bytes of the storage followed by `lines` to `temporary` and
+    /// renames it over the storage, returning it open for appending.
+    fn write_replacement(&self, temporary: &Path, kept: u64, lines: &[u8]) -> io::Result<File> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(temporary)?;
+        file.set_len(0)?;
+        io::copy(&mut File::open(&self.path)?.take(kept), &mut file)?;
+        file.write_all(lines)?;
+        file.sync_data()?;
+        fs::rename(temporary, &self.path)?;
+        Ok(file)
+    }
+
+    /// The lines storing `blocks` and the byte offset each of them ends at, for blocks stored
+    /// from offset `start` on.
+    fn encode(blocks: &[Block], start: u64) -> io::Result<(Vec<u8>, Vec<u64>)> {
+        let mut lines = Vec::new();
+        let mut ends = Vec::with_capacity(blocks.len());
+        for block in blocks {
+            serde_json::to_writer(&mut lines, block)?;
+            lines.push(b'\n');
+            ends.push(start + lines.len() as u64);
+        }
+        Ok((lines, ends))
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+    use crate::vectors;
+    use crate::{Blockchain, BlockchainError};
+
+    #[test]
+    fn block_reader_streams_stored_headers() {
+        let mut blocks = vectors::blocks();
+        blocks.pop();
+        let dir = env::temp_dir().join(format!("blockchain-reader-{}", process::id()));
+        let path = dir.join("blocks.jsonl");
+        let mut blockchain = Blockchain::load(&path).unwrap();
+        blockchain
+            .try_add_blocks(blocks.clone())
+            .expect("vector chain should be valid");
+        drop(blockchain);
+
+        let hashes: Vec<String> = BlockReader::headers(&path)
+            .unwrap()
+            .map(|header| header.unwrap().hash)
+            .collect();
+        assert_eq!(
+            hashes,
+            blocks
+                .iter()
+                .map(|block| block.hash.clone())
+                .collect::<Vec<_>>()
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_reorgs_leave_the_stored_chain_alone() {
+        let blocks = vectors::blocks();
+        let dir = env::temp_dir().join(format!("blockchain-reorg-{}", process::id()));
+        let path = dir.join("blocks.jsonl");
+        let mut blockchain = Blockchain::load(&path).unwrap();
+        blockchain.try_add_block(blocks[0].clone()).unwrap();
+
+        // A directory in the way of the temporary file fails the write
+        let temporary = dir.join("blocks.jsonl.tmp");
+        fs::create_dir(&temporary).unwrap();
+        assert!(matches!(
+            blockchain.replace_chain(blocks[..2].to_vec()),
+            Err(BlockchainError::Io(_))
+        ));
+        assert_eq!(blockchain.height(), 1);
+        drop(blockchain);
+        assert_eq!(Blockchain::load(&path).unwrap().height(), 1);
+
+        fs::remove_dir(&temporary).unwrap();
+        let mut blockchain = Blockchain::load(&path).unwrap();
+        blockchain.replace_chain(blocks[..2].to_vec()).unwrap();
+        drop(blockchain);
+        assert_eq!(Blockchain::load(&path).unwrap().height(), 2);
+        assert!(!temporary.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::Serialize;
+
+use crate::mempool::Mempool;
+use crate::transaction::Transaction;
+use crate::view::ChainView;
+
+/// Transactions stop being followed once they have this many confirmations.
+pub const MAX_REPORTED_CONFIRMATIONS: u64 = 6;
+/// How many blocks below the previously scanned height are scanned again after a reorg, so
+/// transactions confirmed in a block replacing one of ours aren't missed.
+const RESCAN_DEPTH: u64 = 10;
+
+/// Something that happened to a transaction involving a subscribed address.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AddressEvent {
+    /// The transaction showed up in the mempool.
+    Pending { address: String, hash: String },
+    /// The number of blocks confirming the transaction changed, see
+    /// [`crate::Blockchain::confirmations`]. It's back to zero if a reorg disconnected it.
+    Confirmations {
+        address: String,
+        hash: String,
+        confirmations: u64,
+    },
+}
+
+/// Per-client state of a subscription to a set of addresses: turns the changes of the chain
+/// and the mempool between two [`AddressWatch::poll`]s into [`AddressEvent`]s for the
+/// subscribed addresses only.
+pub struct AddressWatch {
+    addresses: HashSet<String>,
+    /// Transactions involving subscribed addresses, by id.
+    followed: HashMap<String, Followed>,
+    /// Tip as of the last poll.
+    tip: Option<String>,
+    /// Height up to which blocks were scanned for transactions to follow.
+    scanned: u64,
+}
+
+struct Followed {
+    /// The subscribed addresses the transaction involves.
+    addresses: Vec<String>,
+    /// Confirmations last reported, `None` if nothing was reported yet.
+    confirmations: Option<u64>,
+}
+
+impl AddressWatch {
+    /// A watch with no subscriptions yet. Only what happens after `view` is reported.
+    pub fn new(view: &ChainView) -> Self {
+        Self {
+            addresses: HashSet::new(),
+            followed: HashMap::new(),
+            tip: view.tip().map(|tip| tip.hash.clone()),
+            scanned: view.height(),
+        }
+    }
+
+    pub fn subscribe(&mut self, address: String) {
+        self.addresses.insert(address);
+    }
+
+    pub fn unsubscribe(&mut self, address: &str) {
+        self.addresses.remove(address);
+        self.followed.retain(|_, followed| {
+            followed.addresses.retain(|followed| followed != address);
+            !followed.addresses.is_empty()
+        });
+    }
+
+    /// Events since the last poll, given the current view of the chain and its mempool.
+    pub fn poll(&mut self, view: &ChainView, mempool: &Mempool) -> Vec<AddressEvent> {
+        let mut events = Vec::new();
+
+        for transaction in mempool.iter() {
+            let hash = transaction.hash();
+            if self.followed.contains_key(&hash) {
+                continue;
+            }
+            let addresses = self.involved(transaction);
+            if addresses.is_empty() {
+                continue;
+            }
+
+            events.extend(addresses.iter().map(|address| AddressEvent::Pending {
+                address: address.clone(),
+                hash: hash.clone(),
+            }));
+            self.followed.insert(
+                hash,
+                Followed {
+                    addresses,
+                    confirmations: Some(0),
+                },
+            );
+        }
+
+        let tip = view.tip().map(|tip| tip.hash.clone());
+        if tip != self.tip {
+            // The previous tip is gone from the chain after a reorg
+            let reorged = self
+                .tip
+                .as_ref()
+                .is_some_and(|tip| view.block(tip).is_none());
+            let from = if reorged {
+                self.scanned.min(view.height()).saturating_sub(RESCAN_DEPTH)
+            } else {
+                self.scanned
+            };
+            for transaction in view.blocks_from(from).flat_map(|block| &block.transactions) {
+                let hash = transaction.hash();
+                if self.followed.contains_key(&hash)
+                    || view.confirmations(&hash) > MAX_REPORTED_CONFIRMATIONS
+                {
+                    continue;
+                }
+                let addresses = self.involved(transaction);
+                if !addresses.is_empty() {
+                    self.followed.insert(
+                        hash,
+                        Followed {
+                            addresses,
+                            confirmations: None,
+                        },
+                    );
+                }
+            }
+            self.tip = tip;
+            self.scanned = view.height();
+        }
+
+        self.followed.retain(|hash, followed| {
+            let confirmations = view.confirmations(hash);
+            if followed.confirmations != Some(confirmations) {
+                followed.confirmations = Some(confirmations);
+                events.extend(followed.addresses.iter().map(|address| {
+                    AddressEvent::Confirmations {
+                        address: address.clone(),
+                        hash: hash.clone(),
+                        confirmations,
+                    }
+                }));
+            }
+
+            // Neither confirmed nor pending means it was evicted from the mempool
+            confirmations < MAX_REPORTED_CONFIRMATIONS
+                && (confirmations > 0 || mempool.contains(hash))
+        });
+
+        events
+    }
+
+    fn involved(&self, transaction: &Transaction) -> Vec<String> {
+        [&transaction.sender, &transaction.recipient]
+            .into_iter()
+            .filter(|address| self.addresses.contains(*address))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors;
+    use crate::Blockchain;
+
+    #[test]
+    fn address_watch_reports_confirmations_of_subscribed_addresses() {
+        let blocks = vectors::blocks();
+        let transfer = &blocks[1].transactions[1];
+        let mut blockchain = Blockchain::new();
+        blockchain.try_add_block(blocks[0].clone()).unwrap();
+
+        let mut watch = AddressWatch::new(&blockchain.views().latest());
+        watch.subscribe(transfer.recipient.clone());
+        blockchain.try_add_block(blocks[1].clone()).unwrap();
+
+        let events = watch.poll(&blockchain.views().latest(), blockchain.mempool());
+        assert!(matches!(
+            &events[..],
+            [AddressEvent::Confirmations { hash, confirmations: 1, .. }] if *hash == transfer.hash()
+        ));
+        assert!(watch
+            .poll(&blockchain.views().latest(), blockchain.mempool())
+            .is_empty());
+    }
+}
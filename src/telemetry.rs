@@ -0,0 +1,181 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::Utc;
+use log::{debug, info};
+use serde::Serialize;
+
+use crate::metrics::Histogram;
+
+/// How often the exporter posts a report.
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// Something worth counting that the node did.
+pub enum TelemetryEvent {
+    /// We mined a block and connected it, after mining for `elapsed`.
+    BlockMined { elapsed: Duration },
+    /// A mining round was abandoned, e.g. because a competing block arrived first.
+    MiningCancelled,
+    /// A block announced by a peer was validated and connected within `elapsed`.
+    BlockValidated { elapsed: Duration },
+    /// A block announced by a peer was invalid.
+    BlockRejected,
+    /// `blocks` blocks sent by a peer were connected within `elapsed`, replacing some of ours
+    /// if `reorg`.
+    Synced {
+        blocks: usize,
+        reorg: bool,
+        elapsed: Duration,
+    },
+}
+
+/// Aggregates of the events of an interval. Nothing in them tells which node, peers or
+/// addresses they came from.
+#[derive(Clone, Default, Serialize)]
+pub struct TelemetryCounters {
+    pub blocks_mined: u64,
+    pub mining_rounds_cancelled: u64,
+    /// Time spent mining each block we connected.
+    pub mining: Histogram,
+    pub blocks_validated: u64,
+    pub blocks_rejected: u64,
+    /// Time spent validating and connecting each block announced by a peer.
+    pub validation: Histogram,
+    pub sync_rounds: u64,
+    pub blocks_synced: u64,
+    pub reorgs: u64,
+    /// Time spent connecting each batch of blocks sent by a peer.
+    pub sync: Histogram,
+}
+
+/// What the exporter posts, as JSON.
+#[derive(Serialize)]
+pub struct TelemetryReport {
+    pub chain_id: String,
+    pub version: &'static str,
+    /// Start and end of the interval covered, in Unix seconds.
+    pub from: i64,
+    pub to: i64,
+    #[serde(flatten)]
+    pub counters: TelemetryCounters,
+}
+
+/// Counters and timings of mining, validation and sync, collected all the time but only sent
+/// anywhere if the node opted in by starting [`Telemetry::export`].
+pub struct Telemetry {
+    counters: Mutex<TelemetryCounters>,
+    /// When the counters were last reset, in Unix seconds.
+    since: Mutex<i64>,
+}
+
+impl Default for Telemetry {
+    fn default() -> Self {
+        Self {
+            counters: Mutex::default(),
+            since: Mutex::new(Utc::now().timestamp()),
+        }
+    }
+}
+
+impl Telemetry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, event: TelemetryEvent) {
+        let mut counters = self.counters.lock().unwrap();
+        match event {
+            TelemetryEvent::BlockMined { elapsed } => {
+                counters.blocks_mined += 1;
+                counters.mining.observe(elapsed.as_millis() as u64);
+            }
+            TelemetryEvent::MiningCancelled => counters.mining_rounds_cancelled += 1,
+            TelemetryEvent::BlockValidated { elapsed } => {
+                counters.blocks_validated += 1;
+                counters.validation.observe(elapsed.as_millis() as u64);
+            }
+            TelemetryEvent::BlockRejected => counters.blocks_rejected += 1,
+            TelemetryEvent::Synced {
+                blocks,
+                reorg,
+                elapsed,
+            } => {
+                counters.sync_rounds += 1;
+                counters.blocks_synced += blocks as u64;
+                counters.reorgs += reorg as u64;
+                counters.sync.observe(elapsed.as_millis() as u64);
+            }
+        }
+    }
+
+    /// The counters since the previous report, which are reset.
+    pub fn report(&self, chain_id: &str) -> TelemetryReport {
+        let counters = std::mem::take(&mut *self.counters.lock().unwrap());
+        let to = Utc::now().timestamp();
+        let from = std::mem::replace(&mut *self.since.lock().unwrap(), to);
+
+        TelemetryReport {
+            chain_id: chain_id.to_string(),
+            version: env!("CARGO_PKG_VERSION"),
+            from,
+            to,
+            counters,
+        }
+    }
+
+    /// Posts a [`TelemetryReport`] to `endpoint` every [`REPORT_INTERVAL`], forever. A report
+    /// the endpoint doesn't take is dropped rather than retried.
+    pub async fn export(self: Arc<Self>, chain_id: String, endpoint: String) {
+        info!("Reporting telemetry to {}", endpoint);
+        let client = reqwest::Client::new();
+        let mut interval = tokio::time::interval(REPORT_INTERVAL);
+        // The first tick is immediate, with nothing to report yet
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+            let report = self.report(&chain_id);
+            let result = client
+                .post(&endpoint)
+                .json(&report)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(err) = result {
+                debug!("Dropping the telemetry report: {}", err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn telemetry_reports_aggregates_since_the_previous_report() {
+        let telemetry = Telemetry::new();
+        telemetry.record(TelemetryEvent::BlockMined {
+            elapsed: Duration::from_millis(20),
+        });
+        telemetry.record(TelemetryEvent::Synced {
+            blocks: 3,
+            reorg: true,
+            elapsed: Duration::from_millis(5),
+        });
+        telemetry.record(TelemetryEvent::BlockRejected);
+
+        let report = telemetry.report("main");
+        assert_eq!(report.counters.blocks_mined, 1);
+        assert_eq!(report.counters.blocks_synced, 3);
+        assert_eq!(report.counters.reorgs, 1);
+        assert_eq!(report.counters.blocks_rejected, 1);
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["chain_id"], "main");
+        assert_eq!(json["mining"]["count"], 1);
+
+        let next = telemetry.report("main");
+        assert_eq!(next.counters.blocks_mined, 0);
+        assert_eq!(next.from, report.to);
+    }
+}
@@ -0,0 +1,170 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::verifier::SignatureVerifier;
+use crate::{hashing, merkle};
+
+/// Sender of the reward transaction a miner puts at the start of its block.
+pub const COINBASE_SENDER: &str = "coinbase";
+/// How many times more a byte counts towards the weight than a byte of a signature.
+pub const WITNESS_SCALE_FACTOR: usize = 4;
+/// Most bytes of the tag a miner may identify itself with, see [`Transaction::miner_tag`].
+pub const MAX_MINER_TAG_LEN: usize = 32;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Transaction {
+    pub sender: String,
+    pub recipient: String,
+    pub amount: u64,
+    pub timestamp: i64,
+    /// Position of the transaction among those of its sender: the first one an address sends
+    /// carries `0` and every other one the next number, so a confirmed transaction can't be
+    /// applied again and those sent concurrently are applied in order. Coinbase transactions
+    /// carry the height of their block instead, which keeps them apart.
+    #[serde(default)]
+    pub nonce: u64,
+    /// Hex-encoded Ed25519 signature of the sender over [`Transaction::signature_hash`].
+    /// Coinbase transactions aren't signed; theirs holds the tag of the miner, if any.
+    pub signature: String,
+}
+
+impl Transaction {
+    pub fn new(sender: String, recipient: String, amount: u64) -> Self {
+        Self {
+            sender,
+            recipient,
+            amount,
+            timestamp: Utc::now().timestamp(),
+            nonce: 0,
+            signature: String::new(),
+        }
+    }
+
+    pub fn coinbase(recipient: String, amount: u64) -> Self {
+        Self::new(String::from(COINBASE_SENDER), recipient, amount)
+    }
+
+    pub fn is_coinbase(&self) -> bool {
+        self.sender == COINBASE_SENDER
+    }
+
+    /// The short string a miner identifies itself with, e.g. the name of a pool, carried in
+    /// place of the signature of its coinbase transaction. Blocks commit to it through their
+    /// Merkle root, but it's not part of the transaction id.
+    pub fn miner_tag(&self) -> Option<&str> {
+        (self.is_coinbase() && !self.signature.is_empty()).then_some(self.signature.as_str())
+    }
+
+    /// Everything the sender commits to when signing, encoded like the block header of
+    /// [`crate::Block::hash`]: the addresses prefixed with their length and the numbers
+    /// fixed-width, so no two transactions share a payload.
+    pub fn signing_payload(&self) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(40 + self.sender.len() + self.recipient.len());
+        hashing::encode_str(&mut payload, &self.sender);
+        hashing::encode_str(&mut payload, &self.recipient);
+        payload.extend_from_slice(&self.amount.to_be_bytes());
+        payload.extend_from_slice(&self.timestamp.to_be_bytes());
+        payload.extend_from_slice(&self.nonce.to_be_bytes());
+        payload
+    }
+
+    /// The digest that actually gets signed, tagged so it can't double as any other hash.
+    pub fn signature_hash(&self) -> [u8; 32] {
+        hashing::tagged_digest(hashing::SIGNATURE_TAG, self.signing_payload())
+    }
+
+    /// The transaction id, over the same payload the sender signs. It leaves the signature
+    /// out, so nobody can change the id of a transaction by re-encoding its signature, and
+    /// changing any signed field changes the id.
+    pub fn hash(&self) -> String {
+        hashing::tagged_hash(hashing::TRANSACTION_TAG, self.signing_payload())
+    }
+
+    /// Hash of the transaction including its signature, which is what blocks commit to. The
+    /// signature is length-prefixed after the signing payload, like every other string field.
+    pub fn witness_digest(&self) -> [u8; 32] {
+        let mut unified_transaction_data = self.signing_payload();
+        hashing::encode_str(&mut unified_transaction_data, &self.signature);
+
+        hashing::tagged_digest(hashing::WITNESS_TAG, unified_transaction_data)
+    }
+
+    /// Hex-encoded [`Transaction::witness_digest`].
+    pub fn witness_hash(&self) -> String {
+        hex::encode(self.witness_digest())
+    }
+
+    /// Size of the fields of the transaction in bytes, the measure mempool limits are
+    /// expressed in.
+    pub fn size(&self) -> usize {
+        self.sender.len() + self.recipient.len() + self.signature.len() + 3 * 8
+    }
+
+    /// Weight of the transaction: every byte counts [`WITNESS_SCALE_FACTOR`] times except those
+    /// of the signature, which isn't needed any more once the transaction is buried deep
+    /// enough, and counts once.
+    pub fn weight(&self) -> usize {
+        WITNESS_SCALE_FACTOR * (self.size() - self.signature.len()) + self.signature.len()
+    }
+
+    /// Virtual size: the weight in bytes that aren't part of a signature, rounded up.
+    pub fn vsize(&self) -> usize {
+        self.weight().div_ceil(WITNESS_SCALE_FACTOR)
+    }
+
+    /// Checks that don't depend on the chain, including the signature, which is verified by
+    /// the [`SignatureVerifier::shared`] pool unless it already was. Coinbase transactions
+    /// carry no signature; where they may appear is up to block validation.
+    pub fn is_valid(&self) -> bool {
+        self.amount > 0
+            && !self.sender.is_empty()
+            && !self.recipient.is_empty()
+            && self.sender != self.recipient
+            && (self.is_coinbase() || SignatureVerifier::shared().verify(self))
+    }
+}
+
+/// Merkle root committing to an ordered set of transactions and their signatures, with the
+/// witness digests as leaves.
+pub fn merkle_root(transactions: &[Transaction]) -> String {
+    let leaves: Vec<[u8; 32]> = transactions
+        .iter()
+        .map(Transaction::witness_digest)
+        .collect();
+    merkle::root(&leaves)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vectors;
+
+    #[test]
+    fn moving_digits_between_fields_changes_the_id_and_breaks_the_signature() {
+        let transfer = vectors::blocks()[1].transactions[1].clone();
+        let mut forged = transfer.clone();
+        // The amount and timestamp written out together read the same as before
+        let timestamp = transfer.timestamp.to_string();
+        forged.amount = format!("{}{}", transfer.amount, &timestamp[..1])
+            .parse()
+            .unwrap();
+        forged.timestamp = timestamp[1..].parse().unwrap();
+        assert_eq!(
+            format!("{}{}", forged.amount, forged.timestamp),
+            format!("{}{}", transfer.amount, transfer.timestamp)
+        );
+
+        assert!(transfer.is_valid());
+        assert_ne!(forged.hash(), transfer.hash());
+        assert_ne!(forged.witness_digest(), transfer.witness_digest());
+        assert!(!forged.is_valid());
+    }
+
+    #[test]
+    fn signatures_weigh_a_quarter_of_the_other_bytes() {
+        for transaction in vectors::transactions() {
+            let signature = transaction.signature.len();
+            assert_eq!(transaction.weight(), 4 * transaction.size() - 3 * signature);
+            assert!(transaction.vsize() <= transaction.size());
+        }
+    }
+}
@@ -0,0 +1,36 @@
+//! The blocks and transactions of `tests/vectors`, for the unit tests of the crate.
+
+use serde::Deserialize;
+
+use crate::transaction::Transaction;
+use crate::Block;
+
+#[derive(Deserialize)]
+struct TransactionVector {
+    transaction: Transaction,
+}
+
+/// The vector blocks: the first two are mined and form a valid chain, the last one is not
+/// mined and spends more than its sender holds.
+pub fn blocks() -> Vec<Block> {
+    serde_json::from_str(include_str!("../tests/vectors/blocks.json"))
+        .expect("block vectors should decode")
+}
+
+/// Two mined blocks forking off the vector genesis, so more work than the vector chain: the
+/// first pays the sender of the vector transfer, the second its recipient.
+pub fn fork() -> Vec<Block> {
+    serde_json::from_str(include_str!("../tests/vectors/fork.json"))
+        .expect("fork vectors should decode")
+}
+
+/// The vector transactions, all of them validly signed.
+pub fn transactions() -> Vec<Transaction> {
+    let vectors: Vec<TransactionVector> =
+        serde_json::from_str(include_str!("../tests/vectors/transactions.json"))
+            .expect("transaction vectors should decode");
+    vectors
+        .into_iter()
+        .map(|vector| vector.transaction)
+        .collect()
+}
@@ -0,0 +1,198 @@
+use std::collections::{HashSet, VecDeque};
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+use std::thread;
+
+use log::warn;
+
+use crate::transaction::Transaction;
+use crate::wallet;
+
+/// Most verified signatures remembered; the oldest are forgotten first.
+pub const SIGNATURE_CACHE_SIZE: usize = 100_000;
+/// Most signatures a worker verifies per job, so a large block is spread over all of them.
+pub const VERIFY_BATCH_SIZE: usize = 16;
+
+/// Signatures of `transactions` to verify on a worker, sending back whether each one verified
+/// along with its position in the request.
+struct Job {
+    transactions: Vec<(usize, Transaction)>,
+    results: mpsc::Sender<Vec<(usize, bool)>>,
+}
+
+/// Witness digests of the transactions whose signature verified, see
+/// [`Transaction::witness_digest`]. The digest covers both the transaction id and the
+/// signature, so a transaction re-signed differently is verified anew.
+#[derive(Default)]
+struct SignatureCache {
+    verified: HashSet<[u8; 32]>,
+    order: VecDeque<[u8; 32]>,
+}
+
+/// Pool of threads verifying transaction signatures for the mempool and for block validation
+/// alike, in batches. Signatures that verified are cached, so a transaction accepted into the
+/// mempool isn't verified again once it's mined, nor when the same block comes in twice.
+pub struct SignatureVerifier {
+    jobs: mpsc::Sender<Job>,
+    cache: Mutex<SignatureCache>,
+    /// Signatures actually verified rather than found in the cache.
+    verified: AtomicU64,
+}
+
+impl SignatureCache {
+    fn contains(&self, digest: &[u8; 32]) -> bool {
+        self.verified.contains(digest)
+    }
+
+    fn insert(&mut self, digest: [u8; 32]) {
+        if !self.verified.insert(digest) {
+            return;
+        }
+        self.order.push_back(digest);
+        if self.order.len() > SIGNATURE_CACHE_SIZE {
+            if let Some(oldest) = self.order.pop_front() {
+                self.verified.remove(&oldest);
+            }
+        }
+    }
+}
+
+impl SignatureVerifier {
+    /// A verifier running `threads` workers, at least one. They stop once it's dropped.
+    pub fn new(threads: usize) -> Self {
+        let (jobs, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        for worker in 0..threads.max(1) {
+            let receiver = Arc::clone(&receiver);
+            let spawned = thread::Builder::new()
+                .name(format!("verifier-{}", worker))
+                .spawn(move || loop {
+                    let Ok(job) = receiver.lock().unwrap().recv() else {
+                        return;
+                    };
+                    let results = job
+                        .transactions
+                        .iter()
+                        .map(|(index, transaction)| (*index, wallet::verify_signature(transaction)))
+                        .collect();
+                    // The requester only stops listening if it panicked
+                    let _ = job.results.send(results);
+                });
+            if let Err(err) = spawned {
+                warn!("Failed to start signature verifier #{}: {}", worker, err);
+            }
+        }
+
+        Self {
+            jobs,
+            cache: Mutex::default(),
+            verified: AtomicU64::new(0),
+        }
+    }
+
+    /// The verifier shared by every chain of the process, with a worker per CPU.
+    pub fn shared() -> &'static Self {
+        static SHARED: OnceLock<SignatureVerifier> = OnceLock::new();
+        SHARED.get_or_init(|| {
+            Self::new(
+                thread::available_parallelism()
+                    .map(NonZeroUsize::get)
+                    .unwrap_or(1),
+            )
+        })
+    }
+
+    /// Signatures verified so far, leaving out those found in the cache.
+    pub fn verified(&self) -> u64 {
+        self.verified.load(Ordering::Relaxed)
+    }
+
+    /// Whether the signature of `transaction` verifies, see [`wallet::verify_signature`].
+    pub fn verify(&self, transaction: &Transaction) -> bool {
+        self.verify_all(std::slice::from_ref(transaction))[0]
+    }
+
+    /// Whether the signature of each of `transactions` verifies, in order. Those not in the
+    /// cache are split into batches of [`VERIFY_BATCH_SIZE`] verified in parallel; this blocks
+    /// until all of them are done.
+    pub fn verify_all(&self, transactions: &[Transaction]) -> Vec<bool> {
+        let digests: Vec<[u8; 32]> = transactions
+            .iter()
+            .map(Transaction::witness_digest)
+            .collect();
+        let mut results = vec![true; transactions.len()];
+        let pending: Vec<(usize, Transaction)> = {
+            let cache = self.cache.lock().unwrap();
+            (0..transactions.len())
+                .filter(|&index| !cache.contains(&digests[index]))
+                .map(|index| (index, transactions[index].clone()))
+                .collect()
+        };
+        if pending.is_empty() {
+            return results;
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        let mut outstanding = vec![false; transactions.len()];
+        for batch in pending.chunks(VERIFY_BATCH_SIZE) {
+            for (index, _) in batch {
+                outstanding[*index] = true;
+            }
+            let job = Job {
+                transactions: batch.to_vec(),
+                results: sender.clone(),
+            };
+            if self.jobs.send(job).is_err() {
+                warn!("No signature verifier is running");
+                break;
+            }
+        }
+        // Only the workers hold senders now, so the results end once every batch is done
+        drop(sender);
+
+        for (index, valid) in receiver.into_iter().flatten() {
+            results[index] = valid;
+            outstanding[index] = false;
+        }
+        self.verified
+            .fetch_add(pending.len() as u64, Ordering::Relaxed);
+
+        let mut cache = self.cache.lock().unwrap();
+        for (index, (valid, outstanding)) in results.iter_mut().zip(outstanding).enumerate() {
+            // A batch no worker answered for counts as not verifying
+            *valid &= !outstanding;
+            if *valid {
+                cache.insert(digests[index]);
+            }
+        }
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wallet::Wallet;
+
+    #[test]
+    fn signature_verifier_verifies_each_signature_once() {
+        let (sender, recipient) = (Wallet::generate(), Wallet::generate());
+        let mut transactions: Vec<Transaction> = (1..=40)
+            .map(|amount| sender.transfer(recipient.address(), amount, amount))
+            .collect();
+        transactions[7].amount += 1;
+        let verifier = SignatureVerifier::new(4);
+
+        let results = verifier.verify_all(&transactions);
+        assert_eq!(results.iter().filter(|valid| !**valid).count(), 1);
+        assert!(!results[7]);
+        assert_eq!(verifier.verified(), 40);
+
+        // Only the signature that didn't verify is checked again
+        assert_eq!(verifier.verify_all(&transactions), results);
+        assert!(verifier.verify(&transactions[0]));
+        assert_eq!(verifier.verified(), 41);
+    }
+}
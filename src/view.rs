@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::changes::{Change, ChangeKind, ChangeLog, Cursor, CursorError};
+use crate::transaction::Transaction;
+use crate::{Block, Blockchain, BlockchainError};
+
+/// Blocks per chunk of a [`ChainView`].
+const CHUNK_BLOCKS: usize = 1024;
+
+/// Read-only copy of the active chain as of the last block connected or reorg, for queries
+/// that shouldn't hold the lock of the chain, nor see it halfway through connecting a block.
+#[derive(Clone, Default)]
+pub struct ChainView {
+    /// The blocks, [`CHUNK_BLOCKS`] to a chunk but the last one, which may hold fewer. Views
+    /// share the chunks they have in common.
+    chunks: Vec<Arc<Chunk>>,
+}
+
+/// Consecutive blocks of a [`ChainView`] with their indexes.
+#[derive(Clone, Default)]
+struct Chunk {
+    blocks: Vec<Block>,
+    /// Ids of the blocks, by hash.
+    block_ids: HashMap<String, u64>,
+    /// Id of the block each transaction of the chunk is in, by transaction id.
+    transaction_index: HashMap<String, u64>,
+}
+
+/// Hands out the latest [`ChainView`] of a chain, see [`Blockchain::views`].
+///
+/// Views are copy-on-write, chunk by chunk: the chain updates the latest one in place, unless
+/// a reader still holds on to it, in which case the reader keeps the old view and the chain
+/// moves on with a copy of the chunks it changes only.
+///
+/// Every block a new view connects or disconnects is recorded to the [`ChangeLog`] of the
+/// chain along the way.
+#[derive(Clone, Default)]
+pub struct ChainViews {
+    latest: Arc<RwLock<Arc<ChainView>>>,
+    changes: Arc<Mutex<ChangeLog>>,
+}
+
+impl ChainView {
+    /// The blocks of the chain, from genesis to the tip.
+    pub fn blocks(&self) -> impl DoubleEndedIterator<Item = &Block> {
+        self.chunks.iter().flat_map(|chunk| &chunk.blocks)
+    }
+
+    /// The blocks of the chain from id `id` on.
+    pub fn blocks_from(&self, id: u64) -> impl Iterator<Item = &Block> {
+        let id = (id as usize).min(self.height() as usize);
+        self.chunks[id / CHUNK_BLOCKS..]
+            .iter()
+            .flat_map(|chunk| &chunk.blocks)
+            .skip(id % CHUNK_BLOCKS)
+    }
+
+    pub fn tip(&self) -> Option<&Block> {
+        self.chunks.last()?.blocks.last()
+    }
+
+    pub fn height(&self) -> u64 {
+        self.chunks.last().map_or(0, |last| {
+            ((self.chunks.len() - 1) * CHUNK_BLOCKS + last.blocks.len()) as u64
+        })
+    }
+
+    /// The block with the given id.
+    pub fn block_at(&self, id: u64) -> Option<&Block> {
+        let id = usize::try_from(id).ok()?;
+        self.chunks
+            .get(id / CHUNK_BLOCKS)?
+            .blocks
+            .get(id % CHUNK_BLOCKS)
+    }
+
+    /// The block with the given id, or hash if it isn't a number.
+    pub fn block(&self, id_or_hash: &str) -> Option<&Block> {
+        let id = match id_or_hash.parse::<u64>() {
+            Ok(id) => id,
+            Err(_) => *self
+                .chunks
+                .iter()
+                .rev()
+                .find_map(|chunk| chunk.block_ids.get(id_or_hash))?,
+        };
+        self.block_at(id)
+    }
+
+    /// See [`Blockchain::confirmations`].
+    pub fn confirmations(&self, hash: &str) -> u64 {
+        self.confirmed_in(hash).map_or(0, |id| self.height() - id)
+    }
+
+    /// The confirmed transaction with the given id, along with the block it's in.
+    pub fn transaction(&self, hash: &str) -> Option<(&Block, &Transaction)> {
+        let block = self.block_at(self.confirmed_in(hash)?)?;
+        let transaction = block
+            .transactions
+            .iter()
+            .find(|transaction| transaction.hash() == hash)?;
+        Some((block, transaction))
+    }
+
+    /// Id of the block the transaction with the given id is in.
+    fn confirmed_in(&self, hash: &str) -> Option<u64> {
+        self.chunks
+            .iter()
+            .rev()
+            .find_map(|chunk| chunk.transaction_index.get(hash))
+            .copied()
+    }
+
+    /// Re-validates the whole chain, see [`Blockchain::validate`].
+    pub fn validate(&self) -> Result<(), BlockchainError> {
+        Blockchain::from_blocks(self.blocks().cloned().collect()).map(|_| ())
+    }
+}
+
+impl ChainViews {
+    pub fn latest(&self) -> Arc<ChainView> {
+        self.latest.read().unwrap().clone()
+    }
+
+    /// The cursor after the last change of the active chain, see [`ChangeLog::cursor`].
+    pub fn cursor(&self) -> Cursor {
+        self.changes.lock().unwrap().cursor()
+    }
+
+    /// See [`ChangeLog::since`].
+    pub fn changes_since(
+        &self,
+        cursor: Cursor,
+        limit: usize,
+    ) -> Result<(Vec<Change>, Cursor), CursorError> {
+        self.changes.lock().unwrap().since(cursor, limit)
+    }
+
+    /// Makes `blocks`, which match the latest view up to `from`, the latest view.
+    pub(crate) fn sync(&self, blocks: &[Block], from: usize) {
+        let mut latest = self.latest.write().unwrap();
+        let mut changes = self.changes.lock().unwrap();
+        let view = Arc::make_mut(&mut latest);
+
+        let from = from.min(view.height() as usize);
+        while view.height() as usize > from {
+            let Some(chunk) = view.chunks.last_mut().map(Arc::make_mut) else {
+                break;
+            };
+            if let Some(block) = chunk.blocks.pop() {
+                chunk.block_ids.remove(&block.hash);
+                for transaction in &block.transactions {
+                    chunk.transaction_index.remove(&transaction.hash());
+                }
+                changes.record(ChangeKind::Disconnected, block.id, block.hash);
+            }
+            if chunk.blocks.is_empty() {
+                view.chunks.pop();
+            }
+        }
+        for block in &blocks[from..] {
+            changes.record(ChangeKind::Connected, block.id, block.hash.clone());
+            if view
+                .chunks
+                .last()
+                .is_none_or(|chunk| chunk.blocks.len() == CHUNK_BLOCKS)
+            {
+                view.chunks.push(Arc::default());
+            }
+            let Some(chunk) = view.chunks.last_mut().map(Arc::make_mut) else {
+                break;
+            };
+            chunk.block_ids.insert(block.hash.clone(), block.id);
+            for transaction in &block.transactions {
+                chunk.transaction_index.insert(transaction.hash(), block.id);
+            }
+            chunk.blocks.push(block.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vectors;
+
+    #[test]
+    fn views_follow_the_active_chain() {
+        let blocks = vectors::blocks();
+        let mut blockchain = Blockchain::from_blocks(blocks[..1].to_vec()).unwrap();
+        let before = blockchain.views().latest();
+        blockchain.try_add_block(blocks[1].clone()).unwrap();
+
+        let view = blockchain.views().latest();
+        assert_eq!(view.height(), 2);
+        assert_eq!(view.block(&blocks[1].hash).map(|block| block.id), Some(1));
+        assert_eq!(view.confirmations(&blocks[1].transactions[0].hash()), 1);
+        let payment = blocks[1].transactions[1].hash();
+        assert_eq!(
+            view.transaction(&payment)
+                .map(|(block, transaction)| (block.id, transaction.hash())),
+            Some((1, payment))
+        );
+        // Views already handed out stay as they were
+        assert_eq!(before.height(), 1);
+        assert!(before.block(&blocks[1].hash).is_none());
+    }
+
+    #[test]
+    fn views_share_the_chunks_a_sync_leaves_alone() {
+        let genesis = vectors::blocks().remove(0);
+        let chain = |len: usize, fork: usize| -> Vec<Block> {
+            (0..len)
+                .map(|id| Block {
+                    id: id as u64,
+                    hash: format!("{}-{}", id, if id < fork { 0 } else { 1 }),
+                    ..genesis.clone()
+                })
+                .collect()
+        };
+        let views = ChainViews::default();
+        let main = chain(2 * CHUNK_BLOCKS + 10, usize::MAX);
+        views.sync(&main, 0);
+        let before = views.latest();
+        assert_eq!(before.height(), main.len() as u64);
+        assert_eq!(
+            before.blocks_from(CHUNK_BLOCKS as u64 - 1).count(),
+            CHUNK_BLOCKS + 11
+        );
+        assert_eq!(
+            before.block(&main[CHUNK_BLOCKS].hash).map(|block| block.id),
+            Some(CHUNK_BLOCKS as u64)
+        );
+
+        // A reorg back into the second chunk copies the chunks it touches only
+        let fork = 2 * CHUNK_BLOCKS - 5;
+        let side = chain(2 * CHUNK_BLOCKS + 20, fork);
+        views.sync(&side, fork);
+        let after = views.latest();
+        assert_eq!(after.height(), side.len() as u64);
+        assert_eq!(
+            after.tip().map(|tip| &tip.hash),
+            side.last().map(|tip| &tip.hash)
+        );
+        assert!(after.block(&main[fork].hash).is_none());
+        assert!(Arc::ptr_eq(&before.chunks[0], &after.chunks[0]));
+        assert!(!Arc::ptr_eq(&before.chunks[1], &after.chunks[1]));
+        assert_eq!(
+            before.tip().map(|tip| &tip.hash),
+            main.last().map(|tip| &tip.hash)
+        );
+    }
+}
@@ -0,0 +1,157 @@
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use rand::rngs::OsRng;
+
+use crate::transaction::Transaction;
+use crate::BlockchainError;
+
+/// Network of wallets that weren't given one, the default chain of a node.
+pub const DEFAULT_NETWORK: &str = "main";
+
+/// An Ed25519 keypair able to sign transactions, for use on one network.
+///
+/// The address of a wallet is its hex-encoded public key, so a transaction's signature can be
+/// verified from the sender address alone. Users are handed it with the network as a prefix
+/// instead, see [`encode_address`], so a wallet can refuse to pay an address of another
+/// network.
+pub struct Wallet {
+    signing_key: SigningKey,
+    network: String,
+}
+
+/// `address` prefixed with `network`, as `<network>_<address>`. Chains only ever see the bare
+/// address; the prefix is for users.
+pub fn encode_address(network: &str, address: &str) -> String {
+    format!("{}_{}", network, address)
+}
+
+/// Splits an address made by [`encode_address`] into its network and bare address.
+pub fn decode_address(encoded: &str) -> Result<(&str, &str), BlockchainError> {
+    let malformed = || BlockchainError::MalformedAddress {
+        address: encoded.to_string(),
+    };
+
+    let (network, address) = encoded.rsplit_once('_').ok_or_else(malformed)?;
+    let is_public_key = hex::decode(address).is_ok_and(|bytes| bytes.len() == 32);
+    if network.is_empty() || !is_public_key {
+        return Err(malformed());
+    }
+    Ok((network, address))
+}
+
+impl Wallet {
+    /// A new wallet on [`DEFAULT_NETWORK`].
+    pub fn generate() -> Self {
+        Self::generate_on(DEFAULT_NETWORK)
+    }
+
+    pub fn generate_on(network: &str) -> Self {
+        Self {
+            signing_key: SigningKey::generate(&mut OsRng),
+            network: network.to_string(),
+        }
+    }
+
+    /// The wallet of the Ed25519 secret key `secret`, e.g. to get the same addresses on every
+    /// run.
+    pub fn from_secret_key(network: &str, secret: [u8; 32]) -> Self {
+        Self {
+            signing_key: SigningKey::from_bytes(&secret),
+            network: network.to_string(),
+        }
+    }
+
+    pub fn network(&self) -> &str {
+        &self.network
+    }
+
+    pub fn address(&self) -> String {
+        hex::encode(self.signing_key.verifying_key().as_bytes())
+    }
+
+    /// The address to hand out to users, prefixed with the network.
+    pub fn encoded_address(&self) -> String {
+        encode_address(&self.network, &self.address())
+    }
+
+    pub fn sign(&self, transaction: &mut Transaction) {
+        let signature = self.signing_key.sign(&transaction.signature_hash());
+        transaction.signature = hex::encode(signature.to_bytes());
+    }
+
+    /// Creates a transfer from this wallet to the bare address `recipient` and signs it, as the
+    /// transaction with `nonce`, see [`crate::Blockchain::next_nonce`]. The network of the
+    /// recipient isn't checked, see [`Wallet::send`] for that.
+    pub fn transfer(&self, recipient: String, amount: u64, nonce: u64) -> Transaction {
+        let mut transaction = Transaction::new(self.address(), recipient, amount);
+        transaction.nonce = nonce;
+        self.sign(&mut transaction);
+        transaction
+    }
+
+    /// Creates a signed transfer to `recipient`, an address prefixed with its network, see
+    /// [`encode_address`]. Fails rather than pay an address of another network, where the
+    /// funds would be lost.
+    pub fn send(
+        &self,
+        recipient: &str,
+        amount: u64,
+        nonce: u64,
+    ) -> Result<Transaction, BlockchainError> {
+        let (network, address) = decode_address(recipient)?;
+        if network != self.network {
+            return Err(BlockchainError::WrongNetwork {
+                address: recipient.to_string(),
+                expected: self.network.clone(),
+            });
+        }
+        Ok(self.transfer(address.to_string(), amount, nonce))
+    }
+}
+
+/// Checks that the transaction was signed by the key behind its sender address. Verification
+/// is strict, rejecting non-canonical signature encodings.
+pub fn verify_signature(transaction: &Transaction) -> bool {
+    let public_key = hex::decode(&transaction.sender)
+        .ok()
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok());
+    let signature = hex::decode(&transaction.signature)
+        .ok()
+        .and_then(|bytes| <[u8; 64]>::try_from(bytes).ok())
+        .map(|bytes| Signature::from_bytes(&bytes));
+
+    match (public_key, signature) {
+        (Some(public_key), Some(signature)) => public_key
+            .verify_strict(&transaction.signature_hash(), &signature)
+            .is_ok(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wallets_refuse_to_pay_addresses_of_other_networks() {
+        let (sender, recipient) = (Wallet::generate_on("test"), Wallet::generate_on("test"));
+        let mainnet = Wallet::generate();
+
+        let transfer = sender.send(&recipient.encoded_address(), 5, 0).unwrap();
+        assert_eq!(transfer.recipient, recipient.address());
+        assert!(transfer.is_valid());
+
+        assert!(matches!(
+            sender.send(&mainnet.encoded_address(), 5, 0),
+            Err(BlockchainError::WrongNetwork { .. })
+        ));
+        assert!(matches!(
+            sender.send(&recipient.address(), 5, 0),
+            Err(BlockchainError::MalformedAddress { .. })
+        ));
+        assert_eq!(
+            decode_address(&encode_address("my_net", &recipient.address())).unwrap(),
+            ("my_net", recipient.address().as_str())
+        );
+    }
+}
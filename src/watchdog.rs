@@ -0,0 +1,109 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::{debug, warn};
+
+use crate::network::Network;
+use crate::ChainHandle;
+
+/// How often the watchdog looks at the tip.
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Notices when the tip of the chain stops advancing, e.g. because we're cut off from the
+/// network or stuck on a chain nobody else extends, and tries to recover.
+pub struct Watchdog {
+    blockchain: ChainHandle,
+    network: Arc<Network>,
+    stall_after: Duration,
+}
+
+/// Snapshot of the state of the node, logged when the tip stalls.
+pub struct HealthReport {
+    pub height: u64,
+    pub tip_hash: Option<String>,
+    /// How long ago the tip last changed.
+    pub stalled_for: Duration,
+    pub work: u64,
+    pub next_difficulty: u64,
+    pub peers: usize,
+    pub pending_transactions: usize,
+    pub propagation: String,
+}
+
+impl Watchdog {
+    /// A watchdog considering the tip stalled once it hasn't changed for `stall_after`.
+    pub fn new(blockchain: ChainHandle, network: Arc<Network>, stall_after: Duration) -> Self {
+        Self {
+            blockchain,
+            network,
+            stall_after,
+        }
+    }
+
+    fn health_report(&self, stalled_for: Duration) -> HealthReport {
+        let blockchain = self.blockchain.read();
+
+        HealthReport {
+            height: blockchain.height(),
+            tip_hash: blockchain.tip().map(|tip| tip.hash.clone()),
+            stalled_for,
+            work: blockchain.work(),
+            next_difficulty: blockchain.next_difficulty(),
+            peers: self.network.peer_count(),
+            pending_transactions: blockchain.mempool().len(),
+            propagation: self.network.propagation_report(),
+        }
+    }
+
+    /// Watches the tip forever. Every `stall_after` without a new tip, it asks the peers for a
+    /// better chain, logs a health report and calls `on_stall`, e.g. to restart the miner.
+    pub async fn run(self, on_stall: impl Fn()) {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        let mut last_tip = None;
+        let mut last_change = Instant::now();
+        let mut last_alarm = Instant::now();
+
+        loop {
+            interval.tick().await;
+
+            let tip = self.blockchain.read().tip().map(|tip| tip.hash.clone());
+            if tip != last_tip {
+                last_tip = tip;
+                last_change = Instant::now();
+                last_alarm = last_change;
+                continue;
+            }
+
+            if last_alarm.elapsed() < self.stall_after {
+                continue;
+            }
+            last_alarm = Instant::now();
+
+            warn!(
+                "Tip has stalled: {}",
+                self.health_report(last_change.elapsed())
+            );
+            debug!("Asking peers for a better chain");
+            self.network.request_sync();
+            on_stall();
+        }
+    }
+}
+
+impl fmt::Display for HealthReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "height {}, tip {}, unchanged for {}s, work {}, next difficulty {}, {} peer(s), {} pending transaction(s); propagation: {}",
+            self.height,
+            self.tip_hash.as_deref().unwrap_or("none"),
+            self.stalled_for.as_secs(),
+            self.work,
+            self.next_difficulty,
+            self.peers,
+            self.pending_transactions,
+            self.propagation
+        )
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::block::{Block, BlockTemplate};
+use crate::{transaction, BlockchainError};
+
+/// How many nonces a single [`Work`] covers, a few seconds of hashing for a typical worker.
+pub const NONCE_RANGE: u64 = 1 << 22;
+/// How many jobs are remembered, so late submissions for a recently replaced job still count.
+const MAX_JOBS: usize = 16;
+
+/// A slice of the nonce space of a block header to mine, handed out to a remote worker. The
+/// header is fixed, timestamp included, so the nonce is all a worker varies; every worker gets
+/// its own range, the 64-bit nonce leaving room for far more workers than a node will ever
+/// have.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Work {
+    /// Job the work belongs to, to name when submitting a solution.
+    pub work_id: u64,
+    pub id: u64,
+    pub previous_hash: String,
+    pub timestamp: i64,
+    pub difficulty: u64,
+    pub merkle_root: String,
+    /// First nonce of the range.
+    pub nonce_start: u64,
+    /// End of the range, itself excluded.
+    pub nonce_end: u64,
+}
+
+/// A block being mined by remote workers.
+struct Job {
+    work_id: u64,
+    /// See [`BlockTemplate::fingerprint`].
+    fingerprint: String,
+    /// Recipient and amount of every coinbase transaction, which the fingerprint leaves out.
+    payout: Vec<(String, u64)>,
+    template: BlockTemplate,
+    timestamp: i64,
+    merkle_root: String,
+    /// Start of the next range to hand out.
+    next_nonce: u64,
+}
+
+impl Job {
+    /// Hands out the next [`NONCE_RANGE`] nonces of the job.
+    fn next_range(&mut self) -> Work {
+        let nonce_start = self.next_nonce;
+        self.next_nonce = self.next_nonce.saturating_add(NONCE_RANGE);
+
+        Work {
+            work_id: self.work_id,
+            id: self.template.id,
+            previous_hash: self.template.previous_hash.clone(),
+            timestamp: self.timestamp,
+            difficulty: self.template.difficulty,
+            merkle_root: self.merkle_root.clone(),
+            nonce_start,
+            nonce_end: self.next_nonce,
+        }
+    }
+}
+
+/// Hands out [`Work`] to remote workers and turns their solutions back into blocks, so several
+/// machines can mine one node's chain.
+#[derive(Default)]
+pub struct WorkQueue {
+    /// Most recent job last.
+    jobs: VecDeque<Job>,
+    next_work_id: u64,
+}
+
+impl WorkQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The next range of nonces for `template`, the block the node wants mined right now. A
+    /// new job is started whenever the template changes materially or pays someone else, so
+    /// workers mining for different addresses never share a job.
+    pub fn get_work(&mut self, template: BlockTemplate) -> Work {
+        let fingerprint = template.fingerprint();
+        let payout: Vec<(String, u64)> = template
+            .transactions
+            .iter()
+            .take_while(|transaction| transaction.is_coinbase())
+            .map(|coinbase| (coinbase.recipient.clone(), coinbase.amount))
+            .collect();
+        match self
+            .jobs
+            .iter_mut()
+            .rev()
+            .find(|job| job.fingerprint == fingerprint && job.payout == payout)
+        {
+            Some(job) => job.next_range(),
+            None => {
+                let mut job = Job {
+                    work_id: self.next_work_id,
+                    fingerprint,
+                    payout,
+                    merkle_root: transaction::merkle_root(&template.transactions),
+                    timestamp: template.timestamp,
+                    template,
+                    next_nonce: 0,
+                };
+                let work = job.next_range();
+
+                self.next_work_id += 1;
+                if self.jobs.len() == MAX_JOBS {
+                    self.jobs.pop_front();
+                }
+                self.jobs.push_back(job);
+                work
+            }
+        }
+    }
+
+    /// The block a worker solved with `nonce` for job `work_id`. Whether it's actually valid,
+    /// proof of work included, is up to [`crate::Blockchain::try_add_block`].
+    pub fn solution(&self, work_id: u64, nonce: u64) -> Result<Block, BlockchainError> {
+        let job = self
+            .jobs
+            .iter()
+            .find(|job| job.work_id == work_id)
+            .ok_or(BlockchainError::UnknownWork { work_id })?;
+
+        Ok(Block {
+            id: job.template.id,
+            hash: Block::hash(
+                job.template.id,
+                &job.template.previous_hash,
+                job.timestamp,
+                job.template.difficulty,
+                &job.merkle_root,
+                nonce,
+            ),
+            previous_hash: job.template.previous_hash.clone(),
+            timestamp: job.timestamp,
+            difficulty: job.template.difficulty,
+            merkle_root: job.merkle_root.clone(),
+            transactions: job.template.transactions.clone(),
+            nonce,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::BLOCK_REWARD;
+    use crate::difficulty::INITIAL_DIFFICULTY;
+    use crate::transaction::Transaction;
+
+    /// The block after `previous_hash`, paying its whole reward to `address`.
+    fn template(previous_hash: &str, address: &str) -> BlockTemplate {
+        BlockTemplate {
+            id: 1,
+            previous_hash: previous_hash.to_string(),
+            difficulty: INITIAL_DIFFICULTY,
+            timestamp: 1_700_000_000,
+            transactions: vec![Transaction::coinbase(address.to_string(), BLOCK_REWARD)],
+        }
+    }
+
+    #[test]
+    fn every_payout_address_gets_its_own_job() {
+        let mut queue = WorkQueue::new();
+        let first = queue.get_work(template("tip", "alice"));
+        let second = queue.get_work(template("tip", "alice"));
+        assert_eq!(second.work_id, first.work_id);
+        assert_eq!(
+            (first.nonce_start, first.nonce_end, second.nonce_start),
+            (0, NONCE_RANGE, NONCE_RANGE)
+        );
+
+        let other = queue.get_work(template("tip", "bob"));
+        assert_ne!(other.work_id, first.work_id);
+        assert_eq!(other.nonce_start, 0);
+        let paid = |work_id| queue.solution(work_id, 7).unwrap().transactions[0].clone();
+        assert_eq!(paid(first.work_id).recipient, "alice");
+        assert_eq!(paid(other.work_id).recipient, "bob");
+
+        // Workers of the first address carry on where they left off
+        let third = queue.get_work(template("tip", "alice"));
+        assert_eq!(third.work_id, first.work_id);
+        assert_eq!(third.nonce_start, 2 * NONCE_RANGE);
+    }
+
+    #[test]
+    fn solutions_rebuild_the_block_of_their_job() {
+        let mut queue = WorkQueue::new();
+        let work = queue.get_work(template("tip", "alice"));
+        let block = queue.solution(work.work_id, 42).unwrap();
+        assert_eq!(block.nonce, 42);
+        assert_eq!(block.timestamp, work.timestamp);
+        assert_eq!(block.merkle_root, work.merkle_root);
+        assert_eq!(block.hash, block.calculate_hash());
+
+        assert!(matches!(
+            queue.solution(work.work_id + 1, 0),
+            Err(BlockchainError::UnknownWork { .. })
+        ));
+        // Jobs of tips long replaced are forgotten
+        for tip in 0..MAX_JOBS {
+            queue.get_work(template(&tip.to_string(), "alice"));
+        }
+        assert!(matches!(
+            queue.solution(work.work_id, 42),
+            Err(BlockchainError::UnknownWork { work_id }) if work_id == work.work_id
+        ));
+    }
+}
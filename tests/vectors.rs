@@ -0,0 +1,159 @@
+use std::{env, fs, process};
+
+use blockchain::bridge::HeaderRelay;
+use blockchain::merkle;
+use blockchain::snapshot::Format;
+use blockchain::transaction::Transaction;
+use blockchain::{Block, Blockchain, BlockchainError};
+use serde::Deserialize;
+
+/// Blocks in their storage encoding: the first two are mined and form a valid chain, the last
+/// one is not mined and spends more than its sender holds.
+const BLOCK_VECTORS: &str = include_str!("vectors/blocks.json");
+const TRANSACTION_VECTORS: &str = include_str!("vectors/transactions.json");
+
+#[derive(Deserialize)]
+struct TransactionVector {
+    transaction: Transaction,
+    hash: String,
+}
+
+fn vector_blocks() -> Vec<Block> {
+    serde_json::from_str(BLOCK_VECTORS).expect("block vectors should decode")
+}
+
+#[test]
+fn transaction_hashes_match_vectors() {
+    let vectors: Vec<TransactionVector> =
+        serde_json::from_str(TRANSACTION_VECTORS).expect("transaction vectors should decode");
+
+    for vector in vectors {
+        assert_eq!(vector.transaction.hash(), vector.hash);
+        assert!(vector.transaction.is_valid());
+    }
+}
+
+#[test]
+fn block_hashes_match_vectors() {
+    for block in vector_blocks() {
+        assert_eq!(
+            block.calculate_hash(),
+            block.hash,
+            "hash mismatch for block #{}",
+            block.id
+        );
+    }
+}
+
+#[test]
+fn merkle_proofs_verify_against_vectors() {
+    for block in vector_blocks() {
+        assert_eq!(block.calculate_merkle_root(), block.merkle_root);
+
+        for transaction in &block.transactions {
+            let proof = block
+                .transaction_proof(&transaction.hash())
+                .expect("transaction should be in the block");
+            assert!(merkle::verify_proof(
+                &block.merkle_root,
+                transaction.witness_digest(),
+                &proof
+            ));
+            assert!(!merkle::verify_proof(
+                &block.merkle_root,
+                transaction.hash(),
+                &proof
+            ));
+        }
+    }
+}
+
+#[test]
+fn block_validity_matches_vectors() {
+    let mut blocks = vector_blocks();
+    let unmined = blocks.pop().unwrap();
+    let mut blockchain = Blockchain::new();
+
+    for block in blocks {
+        blockchain
+            .try_add_block(block)
+            .expect("vector chain should be valid");
+    }
+    blockchain.validate().expect("vector chain should be valid");
+
+    assert!(matches!(
+        blockchain.try_add_block(unmined),
+        Err(BlockchainError::InsufficientProofOfWork { id: 2 })
+    ));
+    assert_eq!(blockchain.height(), 2);
+}
+
+#[test]
+fn snapshots_round_trip_and_revalidate() {
+    let mut blocks = vector_blocks();
+    blocks.pop();
+    let blockchain = Blockchain::from_blocks(blocks).expect("vector chain should be valid");
+    let dir = env::temp_dir().join(format!("blockchain-snapshot-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    for (name, format) in [("chain.json", Format::Json), ("chain.bin", Format::Bincode)] {
+        let path = dir.join(name);
+        blockchain.export(&path, format).unwrap();
+
+        let imported = Blockchain::import(&path).expect("exported chain should import");
+        assert_eq!(imported.height(), blockchain.height());
+        assert_eq!(
+            imported.tip().map(|block| &block.hash),
+            blockchain.tip().map(|block| &block.hash)
+        );
+    }
+
+    let mut tampered = blockchain.blocks().to_vec();
+    tampered[1].nonce += 1;
+    let path = dir.join("tampered.json");
+    fs::write(
+        &path,
+        serde_json::to_vec(&serde_json::json!({ "blocks": tampered })).unwrap(),
+    )
+    .unwrap();
+    assert!(matches!(
+        Blockchain::import(&path),
+        Err(BlockchainError::HashMismatch { id: 1 })
+    ));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn header_relay_verifies_vector_inclusion_proofs() {
+    let mut blocks = vector_blocks();
+    let unmined = blocks.pop().unwrap();
+    let headers: Vec<_> = blocks.iter().map(|block| block.header()).collect();
+
+    let mut relay = HeaderRelay::new(headers[0].clone()).expect("vector genesis should be valid");
+    relay
+        .ingest(headers[1..].to_vec())
+        .expect("vector headers should be valid");
+    assert_eq!(relay.height(), 2);
+
+    let block = &blocks[1];
+    let transaction = &block.transactions[0];
+    let proof = block.transaction_proof(&transaction.hash()).unwrap();
+    relay
+        .verify_transaction(&block.hash, transaction, &proof, 1)
+        .expect("proof should verify against the relayed header");
+    assert!(matches!(
+        relay.verify_transaction(&block.hash, transaction, &proof, 2),
+        Err(BlockchainError::NotEnoughConfirmations { .. })
+    ));
+    assert!(matches!(
+        relay.verify_transaction(&blocks[0].hash, transaction, &proof, 1),
+        Err(BlockchainError::InvalidInclusionProof { .. })
+    ));
+
+    assert!(matches!(
+        relay.ingest(vec![unmined.header()]),
+        Err(BlockchainError::InsufficientProofOfWork { id: 2 })
+    ));
+    assert_eq!(relay.height(), 2);
+}